@@ -0,0 +1,353 @@
+extern crate proc_macro;
+extern crate genecs;
+
+use std::iter::{FromIterator, Peekable};
+
+use proc_macro::{Delimiter, Group, Ident, Literal, Punct, Spacing, Span, TokenStream, TokenTree};
+use proc_macro::token_stream::IntoIter;
+
+use genecs::{GenerateError, SchemaError};
+
+const EXPECTED_ITEM: &str = "expected `component`, `action_property`, `use`, `mod` or `dynamic`";
+
+struct Error {
+    span: Span,
+    message: String,
+}
+
+impl Error {
+    fn new(span: Span, message: &str) -> Self {
+        Error {
+            span,
+            message: message.to_string(),
+        }
+    }
+
+    // compile_error! { "message" }, reported at the error's span
+    fn into_tokens(self) -> TokenStream {
+        let mut message = Literal::string(&self.message);
+        message.set_span(self.span);
+        let mut bang = Punct::new('!', Spacing::Alone);
+        bang.set_span(self.span);
+        let mut body = Group::new(Delimiter::Brace, TokenStream::from(TokenTree::from(message)));
+        body.set_span(self.span);
+
+        TokenStream::from_iter(vec![
+            TokenTree::from(Ident::new("compile_error", self.span)),
+            TokenTree::from(bang),
+            TokenTree::from(body),
+        ])
+    }
+}
+
+enum Value {
+    Bool(bool),
+    Int(u64),
+    Str(String),
+}
+
+// A key of a component or action property, as it appears in a spec file
+struct Field {
+    key: String,
+    value: Value,
+    span: Span,
+}
+
+struct Entry {
+    section: &'static str,
+    id: String,
+    span: Span,
+    fields: Vec<Field>,
+}
+
+struct Spec {
+    public: bool,
+    module: String,
+    dynamic: bool,
+    imports: Vec<String>,
+    entries: Vec<Entry>,
+}
+
+struct Parser {
+    tokens: Peekable<IntoIter>,
+    // the span of the last token, for errors at the end of the input
+    span: Span,
+}
+
+impl Parser {
+    fn new(tokens: TokenStream, span: Span) -> Self {
+        Parser {
+            tokens: tokens.into_iter().peekable(),
+            span,
+        }
+    }
+
+    fn next(&mut self) -> Option<TokenTree> {
+        let token = self.tokens.next();
+        if let Some(ref token) = token {
+            self.span = token.span();
+        }
+
+        token
+    }
+
+    fn error<T>(&self, token: Option<TokenTree>, message: &str) -> Result<T, Error> {
+        Err(Error::new(token.map_or(self.span, |token| token.span()), message))
+    }
+
+    fn is_punct(&mut self, ch: char) -> bool {
+        match self.tokens.peek() {
+            Some(TokenTree::Punct(punct)) => punct.as_char() == ch,
+            _ => false,
+        }
+    }
+
+    fn expect_punct(&mut self, ch: char) -> Result<(), Error> {
+        match self.next() {
+            Some(TokenTree::Punct(ref punct)) if punct.as_char() == ch => Ok(()),
+            other => self.error(other, &format!("expected `{}`", ch)),
+        }
+    }
+
+    fn expect_ident(&mut self) -> Result<Ident, Error> {
+        match self.next() {
+            Some(TokenTree::Ident(ident)) => Ok(ident),
+            other => self.error(other, "expected an identifier"),
+        }
+    }
+
+    fn expect_end(&mut self, expected: &str) -> Result<(), Error> {
+        match self.next() {
+            None => Ok(()),
+            other => self.error(other, &format!("expected `{}`", expected)),
+        }
+    }
+
+    // the tokens up to the next `;`, which is consumed
+    fn until_semicolon(&mut self) -> Result<Vec<TokenTree>, Error> {
+        let mut tokens = Vec::new();
+        loop {
+            match self.next() {
+                Some(TokenTree::Punct(ref punct)) if punct.as_char() == ';' => return Ok(tokens),
+                Some(token) => tokens.push(token),
+                None => return self.error(None, "expected `;`"),
+            }
+        }
+    }
+
+    // #[copy], #[container(RefCell)], #[storage(hash)], #[index(0)], ...
+    fn attributes(&mut self) -> Result<Vec<Field>, Error> {
+        let mut fields: Vec<Field> = Vec::new();
+        while self.is_punct('#') {
+            self.next();
+            let group = match self.next() {
+                Some(TokenTree::Group(group)) if group.delimiter() == Delimiter::Bracket => group,
+                other => return self.error(other, "expected `[`"),
+            };
+
+            let mut parser = Parser::new(group.stream(), group.span());
+            let key = parser.expect_ident()?.to_string();
+            let value = match parser.next() {
+                None => Value::Bool(true),
+                Some(TokenTree::Group(ref args)) if args.delimiter() == Delimiter::Parenthesis => {
+                    let mut args = Parser::new(args.stream(), args.span());
+                    let value = match args.next() {
+                        Some(TokenTree::Ident(ident)) => Value::Str(ident.to_string()),
+                        Some(TokenTree::Literal(literal)) => match literal.to_string().parse() {
+                            Ok(value) => Value::Int(value),
+                            Err(_) => return args.error(Some(TokenTree::Literal(literal)), "expected an integer"),
+                        },
+                        other => return args.error(other, "expected an identifier or an integer"),
+                    };
+                    args.expect_end(")")?;
+                    parser.expect_end("]")?;
+                    value
+                }
+                other => return parser.error(other, "expected `(` or `]`"),
+            };
+
+            if fields.iter().any(|field| field.key == key) {
+                return Err(Error::new(group.span(), &format!("duplicate attribute `{}`", key)));
+            }
+            fields.push(Field {
+                key,
+                value,
+                span: group.span(),
+            });
+        }
+
+        Ok(fields)
+    }
+
+    fn entry(&mut self, section: &'static str, mut fields: Vec<Field>) -> Result<Entry, Error> {
+        let id = self.expect_ident()?;
+        if self.is_punct(':') {
+            self.next();
+            let tokens = self.until_semicolon()?;
+            let span = tokens.first().map_or(self.span, TokenTree::span);
+            fields.insert(0, Field {
+                key: "type".to_string(),
+                value: Value::Str(TokenStream::from_iter(tokens).to_string()),
+                span,
+            });
+        } else {
+            self.expect_punct(';')?;
+        }
+
+        Ok(Entry {
+            section,
+            id: id.to_string(),
+            span: id.span(),
+            fields,
+        })
+    }
+
+    fn module(&mut self, spec: &mut Spec) -> Result<(), Error> {
+        spec.module = self.expect_ident()?.to_string();
+        self.expect_punct(';')
+    }
+
+    fn spec(&mut self) -> Result<Spec, Error> {
+        let mut spec = Spec {
+            public: false,
+            module: "ecs".to_string(),
+            dynamic: false,
+            imports: Vec::new(),
+            entries: Vec::new(),
+        };
+
+        loop {
+            let fields = self.attributes()?;
+            let keyword = match self.next() {
+                Some(TokenTree::Ident(ident)) => ident,
+                None if fields.is_empty() => return Ok(spec),
+                other => return self.error(other, EXPECTED_ITEM),
+            };
+
+            let section = match keyword.to_string().as_str() {
+                "component" => Some("component"),
+                "action_property" => Some("action_property"),
+                _ => None,
+            };
+            if let Some(section) = section {
+                let entry = self.entry(section, fields)?;
+                if spec.entries.iter().any(|other| other.section == section && other.id == entry.id) {
+                    return Err(Error::new(entry.span, &format!("{} `{}` is defined more than once", section, entry.id)));
+                }
+                spec.entries.push(entry);
+                continue;
+            }
+
+            if let Some(field) = fields.first() {
+                return Err(Error::new(field.span, "attributes are only allowed on components and action properties"));
+            }
+
+            match keyword.to_string().as_str() {
+                "use" => {
+                    let path = TokenStream::from_iter(self.until_semicolon()?);
+                    spec.imports.push(path.to_string());
+                }
+                "dynamic" => {
+                    self.expect_punct(';')?;
+                    spec.dynamic = true;
+                }
+                "pub" => {
+                    match self.next() {
+                        Some(TokenTree::Ident(ref ident)) if ident.to_string() == "mod" => (),
+                        other => return self.error(other, "expected `mod`"),
+                    }
+                    spec.public = true;
+                    self.module(&mut spec)?;
+                }
+                "mod" => self.module(&mut spec)?,
+                _ => return Err(Error::new(keyword.span(), EXPECTED_ITEM)),
+            }
+        }
+    }
+}
+
+fn quote(string: &str) -> String {
+    format!("\"{}\"", string.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+// The spec file describing the same ecs, so that it goes through the same
+// validation and code generation as one read from disk
+fn to_toml(spec: &Spec) -> String {
+    let mut toml = String::new();
+    if spec.dynamic {
+        toml.push_str("dynamic = true\n");
+    }
+    if !spec.imports.is_empty() {
+        let imports: Vec<String> = spec.imports.iter().map(|import| quote(import)).collect();
+        toml.push_str(&format!("imports = [{}]\n", imports.join(", ")));
+    }
+
+    for section in &["component", "action_property"] {
+        for entry in spec.entries.iter().filter(|entry| entry.section == *section) {
+            toml.push_str(&format!("\n[{}.{}]\n", entry.section, entry.id));
+            for field in &entry.fields {
+                let value = match field.value {
+                    Value::Bool(value) => value.to_string(),
+                    Value::Int(value) => value.to_string(),
+                    Value::Str(ref value) => quote(value),
+                };
+                toml.push_str(&format!("{} = {}\n", field.key, value));
+            }
+        }
+    }
+
+    toml
+}
+
+fn error_span(spec: &Spec, error: &SchemaError) -> Span {
+    let entry = spec.entries.iter().find(|entry| {
+        entry.section == error.section && Some(&entry.id) == error.id.as_ref()
+    });
+
+    match (entry, error.field.as_ref()) {
+        (Some(entry), Some(key)) => entry.fields.iter().find(|field| field.key == *key).map_or(entry.span, |field| field.span),
+        (Some(entry), None) => entry.span,
+        (None, _) => Span::call_site(),
+    }
+}
+
+/// Generates an ecs in a module, as the build script generator does from a
+/// spec file. The module imports everything from the module containing the
+/// invocation, so component types can be used without `use` items.
+///
+/// ```text
+/// ecs! {
+///     pub mod ecs;                    // defaults to a private module named ecs
+///     dynamic;                        // access to components by name
+///     use geometry::Vec2;             // imports are copied into the module
+///
+///     #[copy] #[index(0)] component position: Vec2;   // pins the index
+///     #[container(RefCell)] #[storage(hash)] component inventory: Vec<u32>;
+///     component solid;
+///     #[copy] action_property turn: u64;
+/// }
+/// ```
+///
+/// Attributes correspond to the keys of spec file entries.
+#[proc_macro]
+pub fn ecs(input: TokenStream) -> TokenStream {
+    let spec = match Parser::new(input, Span::call_site()).spec() {
+        Ok(spec) => spec,
+        Err(error) => return error.into_tokens(),
+    };
+
+    match genecs::generate_ecs_code_from_spec(&to_toml(&spec)) {
+        Ok(code) => {
+            let visibility = if spec.public { "pub " } else { "" };
+            format!("{}mod {} {{\n{}\nuse super::*;\n}}", visibility, spec.module, code)
+                .parse()
+                .expect("generated code is always valid rust")
+        }
+        Err(GenerateError::Schema(errors)) => {
+            TokenStream::from_iter(errors.iter().map(|error| {
+                Error::new(error_span(&spec, error), &error.to_string()).into_tokens()
+            }))
+        }
+        Err(error) => Error::new(Span::call_site(), &error.to_string()).into_tokens(),
+    }
+}
@@ -0,0 +1,145 @@
+#[macro_use]
+extern crate serde_derive;
+extern crate serde;
+extern crate serde_json;
+extern crate genecs_macros;
+
+// The same components on each storage backend
+genecs_macros::ecs! {
+    #[copy] component btree_value: u32;
+    component btree_flag;
+    #[container(RefCell)] component btree_cell: u32;
+
+    #[copy] #[storage(hash)] component hash_value: u32;
+    #[storage(hash)] component hash_flag;
+    #[container(RefCell)] #[storage(hash)] component hash_cell: u32;
+
+    #[copy] #[storage(dense_vec)] component dense_value: u32;
+    #[storage(dense_vec)] component dense_flag;
+    #[container(RefCell)] #[storage(dense_vec)] component dense_cell: u32;
+
+    #[copy] #[storage(sparse_set)] component sparse_value: u32;
+    #[storage(sparse_set)] component sparse_flag;
+    #[container(RefCell)] #[storage(sparse_set)] component sparse_cell: u32;
+}
+
+use ecs::*;
+
+// A few indices over two generations, so that actions change stale ids too
+fn ids() -> Vec<EntityId> {
+    let mut ids = Vec::new();
+    for index in 0..6 {
+        ids.push(entity_id::new(index, 0));
+        ids.push(entity_id::new(index, 1));
+    }
+    ids
+}
+
+type BackendState = Vec<(EntityId, Option<u32>, bool, Option<u32>)>;
+
+// The components of every entity on each backend
+fn states(ctx: &EcsCtx) -> Vec<BackendState> {
+    let ids = ids();
+    vec![
+        ids.iter().map(|&id| (id, ctx.btree_value(id), ctx.contains_btree_flag(id),
+                              ctx.btree_cell(id).map(|cell| *cell.borrow()))).collect(),
+        ids.iter().map(|&id| (id, ctx.hash_value(id), ctx.contains_hash_flag(id),
+                              ctx.hash_cell(id).map(|cell| *cell.borrow()))).collect(),
+        ids.iter().map(|&id| (id, ctx.dense_value(id), ctx.contains_dense_flag(id),
+                              ctx.dense_cell(id).map(|cell| *cell.borrow()))).collect(),
+        ids.iter().map(|&id| (id, ctx.sparse_value(id), ctx.contains_sparse_flag(id),
+                              ctx.sparse_cell(id).map(|cell| *cell.borrow()))).collect(),
+    ]
+}
+
+// Records the same change to every component of every backend
+fn record(action: &mut EcsAction, operation: u32, a: EntityId, b: EntityId, value: u32) {
+    macro_rules! record {
+        ($insert:ident, $remove:ident, $swap:ident, $move:ident, $($value:expr)*) => {
+            match operation {
+                0 | 1 => action.$insert(a $(, $value)*),
+                2 => action.$remove(a),
+                3 => action.$swap(a, b),
+                _ => action.$move(a, b),
+            }
+        }
+    }
+
+    record!(insert_btree_value, remove_btree_value, swap_btree_value, move_btree_value, value);
+    record!(insert_btree_flag, remove_btree_flag, swap_btree_flag, move_btree_flag,);
+    record!(insert_btree_cell, remove_btree_cell, swap_btree_cell, move_btree_cell, value);
+    record!(insert_hash_value, remove_hash_value, swap_hash_value, move_hash_value, value);
+    record!(insert_hash_flag, remove_hash_flag, swap_hash_flag, move_hash_flag,);
+    record!(insert_hash_cell, remove_hash_cell, swap_hash_cell, move_hash_cell, value);
+    record!(insert_dense_value, remove_dense_value, swap_dense_value, move_dense_value, value);
+    record!(insert_dense_flag, remove_dense_flag, swap_dense_flag, move_dense_flag,);
+    record!(insert_dense_cell, remove_dense_cell, swap_dense_cell, move_dense_cell, value);
+    record!(insert_sparse_value, remove_sparse_value, swap_sparse_value, move_sparse_value, value);
+    record!(insert_sparse_flag, remove_sparse_flag, swap_sparse_flag, move_sparse_flag,);
+    record!(insert_sparse_cell, remove_sparse_cell, swap_sparse_cell, move_sparse_cell, value);
+}
+
+// Commits a pseudo-random sequence of actions with their inverses, checking
+// that every backend ends up in the same state as a plain commit would leave
+// it, and then undoes them one at a time
+fn commit_and_undo(ordered: bool) {
+    let ids = ids();
+    let mut ctx = EcsCtx::new();
+    let mut plain_ctx = EcsCtx::new();
+    let mut history = vec![states(&ctx)];
+    let mut inverses = Vec::new();
+    let mut state: u32 = 54321;
+
+    for step in 0..50 {
+        let (mut action, mut plain_action) = if ordered {
+            (EcsAction::ordered(), EcsAction::ordered())
+        } else {
+            (EcsAction::new(), EcsAction::new())
+        };
+
+        for _ in 0..6 {
+            state = state.wrapping_mul(1103515245).wrapping_add(12345);
+            let bits = state >> 8;
+            let a = ids[bits as usize % ids.len()];
+            let b = ids[(bits / 16) as usize % ids.len()];
+            if a == b {
+                continue;
+            }
+            record(&mut action, (bits / 256) % 5, a, b, step);
+            record(&mut plain_action, (bits / 256) % 5, a, b, step);
+        }
+
+        let mut inverse = EcsAction::new();
+        ctx.commit_with_inverse(&mut action, &mut inverse);
+        plain_ctx.commit(&mut plain_action);
+
+        let current = states(&ctx);
+        for backend in &current[1..] {
+            assert_eq!(*backend, current[0], "backends differ after step {}", step);
+        }
+        assert_eq!(states(&plain_ctx), current, "commit_with_inverse differs from commit after step {}", step);
+
+        history.push(current);
+        inverses.push(inverse);
+    }
+
+    assert!(history.iter().any(|states| states[0].iter().any(|&(_, value, flag, cell)| {
+        value.is_some() && flag && cell.is_some()
+    })));
+
+    while let Some(mut inverse) = inverses.pop() {
+        history.pop();
+        ctx.commit(&mut inverse);
+        assert_eq!(states(&ctx), *history.last().unwrap(), "undo to step {}", history.len() - 1);
+    }
+}
+
+#[test]
+fn backends_agree_with_unordered_actions() {
+    commit_and_undo(false);
+}
+
+#[test]
+fn backends_agree_with_ordered_actions() {
+    commit_and_undo(true);
+}
@@ -0,0 +1,58 @@
+#[macro_use]
+extern crate serde_derive;
+extern crate serde;
+extern crate serde_json;
+extern crate genecs_macros;
+
+genecs_macros::ecs! {
+    #[copy] component position: (i32, i32);
+    component solid;
+}
+
+use ecs::*;
+
+#[test]
+fn despawned_entities_are_only_reported_as_removed() {
+    let mut ctx = EcsCtx::new();
+    let start = ctx.change_tick();
+    let a = ctx.spawn();
+    let b = ctx.spawn();
+    ctx.insert_position(a, (0, 0));
+    ctx.insert_position(b, (1, 1));
+    ctx.insert_solid(a);
+
+    assert!(ctx.despawn(a));
+
+    assert_eq!(ctx.position_changed_since(start).collect::<Vec<_>>(), vec![b]);
+    assert_eq!(ctx.position_added_since(start).collect::<Vec<_>>(), vec![b]);
+    assert_eq!(ctx.position_removed_since(start).collect::<Vec<_>>(), vec![a]);
+    assert_eq!(ctx.solid_removed_since(start).collect::<Vec<_>>(), vec![a]);
+
+    // the index is reused by a new generation, which is a different entity
+    let c = ctx.spawn();
+    assert_eq!(entity_id::index(c), entity_id::index(a));
+    ctx.insert_position(c, (2, 2));
+    assert_eq!(ctx.position_added_since(start).collect::<Vec<_>>(), vec![b, c]);
+    assert_eq!(ctx.position_removed_since(start).collect::<Vec<_>>(), vec![a]);
+}
+
+#[test]
+fn forgotten_changes_are_not_reported() {
+    let mut ctx = EcsCtx::new();
+    let start = ctx.change_tick();
+    let a = ctx.spawn();
+    ctx.insert_position(a, (0, 0));
+    let middle = ctx.change_tick();
+    let b = ctx.spawn();
+    ctx.insert_position(b, (1, 1));
+    ctx.despawn(a);
+
+    ctx.forget_changes(start);
+    assert_eq!(ctx.position_added_since(start).collect::<Vec<_>>(), vec![b]);
+    assert_eq!(ctx.position_removed_since(middle).collect::<Vec<_>>(), vec![a]);
+
+    let end = ctx.change_tick();
+    ctx.forget_changes(end);
+    assert_eq!(ctx.position_changed_since(start).count(), 0);
+    assert_eq!(ctx.position_removed_since(start).count(), 0);
+}
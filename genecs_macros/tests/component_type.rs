@@ -0,0 +1,104 @@
+#[macro_use]
+extern crate serde_derive;
+extern crate serde;
+extern crate serde_json;
+extern crate genecs_macros;
+
+genecs_macros::ecs! {
+    #[copy] component position: (i32, i32);
+    #[storage(hash)] component name: String;
+    #[container(RefCell)] #[storage(dense_vec)] component inventory: Vec<u32>;
+    #[container(UnsafeCell)] #[storage(sparse_set)] component path: Vec<u32>;
+    component solid;
+    #[storage(hash)] component hidden;
+    #[storage(dense_vec)] component active;
+    #[storage(sparse_set)] component flying;
+}
+
+use ecs::*;
+
+const ALL: &[(ComponentType, &str, Option<&str>)] = &[
+    (component_type::POSITION, "position", Some("(i32, i32)")),
+    (component_type::NAME, "name", Some("String")),
+    (component_type::INVENTORY, "inventory", Some("Vec<u32>")),
+    (component_type::PATH, "path", Some("Vec<u32>")),
+    (component_type::SOLID, "solid", None),
+    (component_type::HIDDEN, "hidden", None),
+    (component_type::ACTIVE, "active", None),
+    (component_type::FLYING, "flying", None),
+];
+
+#[test]
+fn names_and_types() {
+    for &(component_type, name, type_name) in ALL {
+        assert_eq!(component_type::name(component_type), name);
+        assert_eq!(component_type::from_name(name), Some(component_type));
+        // the macro writes types as tokens, which may be spaced differently
+        let written = component_type::type_name(component_type).map(|ty| ty.replace(' ', ""));
+        assert_eq!(written, type_name.map(|ty| ty.replace(' ', "")));
+        assert_eq!(component_type::is_flag(component_type), type_name.is_none());
+    }
+}
+
+#[test]
+fn unknown_names() {
+    assert_eq!(component_type::from_name("velocity"), None);
+    assert_eq!(component_type::from_name("Position"), None);
+    assert_eq!(component_type::from_name(""), None);
+}
+
+#[test]
+#[should_panic(expected = "Invalid component type")]
+fn invalid_component_types_have_no_name() {
+    component_type::name(component_type::INVALID_COMPONENT);
+}
+
+fn sorted(ids: Box<dyn Iterator<Item=EntityId> + '_>) -> Vec<EntityId> {
+    let mut ids: Vec<EntityId> = ids.collect();
+    ids.sort();
+    ids
+}
+
+#[test]
+fn components_by_type() {
+    let mut ctx = EcsCtx::new();
+    let entities: Vec<EntityId> = (0..4).map(|_| ctx.spawn()).collect();
+    let (a, b, c, d) = (entities[0], entities[1], entities[2], entities[3]);
+
+    // each component is on a different pair of entities
+    ctx.insert_position(a, (0, 0));
+    ctx.insert_position(b, (1, 1));
+    ctx.insert_name(b, "b".to_string());
+    ctx.insert_name(c, "c".to_string());
+    ctx.insert_inventory(c, vec![1]);
+    ctx.insert_inventory(d, vec![2]);
+    ctx.insert_path(a, vec![3]);
+    ctx.insert_path(d, vec![4]);
+    ctx.insert_solid(a);
+    ctx.insert_solid(c);
+    ctx.insert_hidden(b);
+    ctx.insert_hidden(d);
+    ctx.insert_active(a);
+    ctx.insert_active(b);
+    ctx.insert_flying(c);
+    ctx.insert_flying(d);
+    ctx.remove_entity(b);
+
+    let expected = [
+        (component_type::POSITION, vec![a]),
+        (component_type::NAME, vec![c]),
+        (component_type::INVENTORY, vec![c, d]),
+        (component_type::PATH, vec![a, d]),
+        (component_type::SOLID, vec![a, c]),
+        (component_type::HIDDEN, vec![d]),
+        (component_type::ACTIVE, vec![a]),
+        (component_type::FLYING, vec![c, d]),
+    ];
+    for &(component_type, ref ids) in &expected {
+        let name = component_type::name(component_type);
+        assert_eq!(sorted(ctx.component_ids(component_type)), *ids, "{}", name);
+        for &entity in &entities {
+            assert_eq!(ctx.contains_component(entity, component_type), ids.contains(&entity), "{} {}", name, entity);
+        }
+    }
+}
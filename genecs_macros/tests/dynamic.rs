@@ -0,0 +1,128 @@
+#[macro_use]
+extern crate serde_derive;
+extern crate serde;
+#[macro_use]
+extern crate serde_json;
+extern crate genecs_macros;
+
+genecs_macros::ecs! {
+    dynamic;
+    #[copy] component position: (i32, i32);
+    #[storage(hash)] component name: String;
+    #[container(RefCell)] #[storage(dense_vec)] component inventory: Vec<u32>;
+    #[container(UnsafeCell)] #[storage(sparse_set)] component path: Vec<u32>;
+    component solid;
+}
+
+use ecs::*;
+
+#[test]
+fn values() {
+    let mut ctx = EcsCtx::new();
+    let entity = ctx.spawn();
+    assert_eq!(ctx.get_dynamic(entity, "position").unwrap(), None);
+
+    ctx.insert_dynamic(entity, "position", json!([1, 2])).unwrap();
+    ctx.insert_dynamic(entity, "name", json!("a")).unwrap();
+    assert_eq!(ctx.position(entity), Some((1, 2)));
+    assert_eq!(ctx.name(entity).map(String::as_str), Some("a"));
+    assert_eq!(ctx.get_dynamic(entity, "position").unwrap(), Some(json!([1, 2])));
+
+    assert_eq!(ctx.remove_dynamic(entity, "name").unwrap(), Some(json!("a")));
+    assert_eq!(ctx.name(entity), None);
+    assert_eq!(ctx.remove_dynamic(entity, "name").unwrap(), None);
+}
+
+#[test]
+fn cells() {
+    let mut ctx = EcsCtx::new();
+    let entity = ctx.spawn();
+
+    ctx.insert_dynamic(entity, "inventory", json!([1, 2])).unwrap();
+    ctx.insert_dynamic(entity, "path", json!([3])).unwrap();
+    ctx.inventory_borrow_mut(entity).unwrap().push(4);
+    assert_eq!(ctx.get_dynamic(entity, "inventory").unwrap(), Some(json!([1, 2, 4])));
+    assert_eq!(ctx.get_dynamic(entity, "path").unwrap(), Some(json!([3])));
+
+    assert_eq!(ctx.remove_dynamic(entity, "inventory").unwrap(), Some(json!([1, 2, 4])));
+    assert_eq!(ctx.remove_dynamic(entity, "path").unwrap(), Some(json!([3])));
+    assert!(!ctx.contains_inventory(entity));
+    assert!(!ctx.contains_path(entity));
+}
+
+#[test]
+fn flags() {
+    let mut ctx = EcsCtx::new();
+    let entity = ctx.spawn();
+    assert_eq!(ctx.get_dynamic(entity, "solid").unwrap(), None);
+
+    ctx.insert_dynamic(entity, "solid", json!(true)).unwrap();
+    assert!(ctx.contains_solid(entity));
+    assert_eq!(ctx.get_dynamic(entity, "solid").unwrap(), Some(json!(true)));
+
+    assert_eq!(ctx.remove_dynamic(entity, "solid").unwrap(), Some(json!(true)));
+    assert!(!ctx.contains_solid(entity));
+}
+
+#[test]
+fn actions() {
+    let mut ctx = EcsCtx::new();
+    let entity = ctx.spawn();
+    ctx.insert_position(entity, (0, 0));
+
+    let mut action = EcsAction::new();
+    action.insert_dynamic(entity, "name", json!("a")).unwrap();
+    action.insert_dynamic(entity, "solid", json!(true)).unwrap();
+    action.remove_dynamic(entity, "position").unwrap();
+    ctx.commit(&mut action);
+
+    assert_eq!(ctx.name(entity).map(String::as_str), Some("a"));
+    assert!(ctx.contains_solid(entity));
+    assert!(!ctx.contains_position(entity));
+}
+
+fn unknown(result: Result<(), DynamicError>) {
+    match result {
+        Err(DynamicError::UnknownComponent(ref name)) => assert_eq!(name, "velocity"),
+        other => panic!("expected an unknown component, found {:?}", other),
+    }
+}
+
+#[test]
+fn unknown_components() {
+    let mut ctx = EcsCtx::new();
+    let entity = ctx.spawn();
+
+    unknown(ctx.get_dynamic(entity, "velocity").map(|_| ()));
+    unknown(ctx.insert_dynamic(entity, "velocity", json!([1, 1])));
+    unknown(ctx.remove_dynamic(entity, "velocity").map(|_| ()));
+
+    let mut action = EcsAction::new();
+    unknown(action.insert_dynamic(entity, "velocity", json!([1, 1])));
+    unknown(action.remove_dynamic(entity, "velocity"));
+    assert_eq!(DynamicError::UnknownComponent("velocity".to_string()).to_string(), "unknown component: velocity");
+}
+
+#[test]
+fn values_of_the_wrong_type() {
+    let mut ctx = EcsCtx::new();
+    let entity = ctx.spawn();
+    ctx.insert_position(entity, (1, 1));
+
+    for &(name, ref value) in &[("position", json!("north")), ("name", json!(1)), ("inventory", json!([-1]))] {
+        match ctx.insert_dynamic(entity, name, value.clone()) {
+            Err(ref error @ DynamicError::Value(_)) => assert!(error.to_string().starts_with("invalid component value: ")),
+            other => panic!("expected an invalid {} value, found {:?}", name, other),
+        }
+    }
+
+    // nothing changes when a value is rejected
+    assert_eq!(ctx.position(entity), Some((1, 1)));
+    assert!(!ctx.contains_name(entity));
+    assert!(!ctx.contains_inventory(entity));
+
+    let mut action = EcsAction::new();
+    assert!(action.insert_dynamic(entity, "position", json!({"x": 1})).is_err());
+    ctx.commit(&mut action);
+    assert_eq!(ctx.position(entity), Some((1, 1)));
+}
@@ -0,0 +1,46 @@
+#[macro_use]
+extern crate serde_derive;
+extern crate serde;
+extern crate serde_json;
+extern crate genecs_macros;
+
+genecs_macros::ecs! {
+    #[copy] component position: (i32, i32);
+    component solid;
+}
+
+use ecs::*;
+
+#[test]
+fn spawn_skips_ids_with_components() {
+    let mut ctx = EcsCtx::new();
+
+    // raw ids 0 and 1 are the ids spawn would return first
+    ctx.insert_position(0, (1, 1));
+    ctx.insert_solid(1);
+
+    let spawned = ctx.spawn();
+    assert_eq!(spawned, entity_id::new(2, 0));
+    assert!(ctx.position(spawned).is_none());
+    assert_eq!(ctx.position(0), Some((1, 1)));
+
+    // skipped ids stay allocated to the entities using them
+    assert!(ctx.is_alive(0));
+    assert!(ctx.despawn(0));
+    assert!(!ctx.is_alive(0));
+    assert!(ctx.position(0).is_none());
+}
+
+#[test]
+fn spawn_reuses_freed_indices() {
+    let mut ctx = EcsCtx::new();
+    let a = ctx.spawn();
+    ctx.insert_solid(a);
+    assert!(ctx.despawn(a));
+
+    let b = ctx.spawn();
+    assert_eq!(entity_id::index(a), entity_id::index(b));
+    assert!(a != b);
+    assert!(!ctx.is_alive(a));
+    assert!(!ctx.contains_solid(b));
+}
@@ -0,0 +1,81 @@
+#[macro_use]
+extern crate serde_derive;
+extern crate serde;
+extern crate serde_json;
+extern crate genecs_macros;
+
+genecs_macros::ecs! {
+    #[index(2)] component alpha;
+    component bravo;
+    #[copy] #[index(0)] component charlie: u32;
+    component delta: String;
+
+    #[index(1)] action_property undoable;
+    #[copy] action_property turn: u64;
+}
+
+// pins beyond the number of entries leave gaps, like those of removed entries
+genecs_macros::ecs! {
+    mod gaps;
+    #[index(3)] component alpha;
+    #[copy] component bravo: u32;
+    #[index(70)] component charlie;
+
+    #[index(2)] action_property undoable;
+}
+
+use ecs::*;
+
+#[test]
+fn pinned_indices() {
+    // unpinned entries fill the remaining indices in alphabetical order
+    assert_eq!(component_type::CHARLIE, 0);
+    assert_eq!(component_type::BRAVO, 1);
+    assert_eq!(component_type::ALPHA, 2);
+    assert_eq!(component_type::DELTA, 3);
+
+    assert_eq!(action_property_type::TURN, 0);
+    assert_eq!(action_property_type::UNDOABLE, 1);
+}
+
+#[test]
+fn pinned_components_are_stored_by_their_index() {
+    let mut ctx = EcsCtx::new();
+    let entity = ctx.spawn();
+    ctx.insert_alpha(entity);
+    ctx.insert_charlie(entity, 7);
+
+    assert!(ctx.contains_alpha(entity));
+    assert!(!ctx.contains_bravo(entity));
+    assert_eq!(ctx.charlie(entity), Some(7));
+    assert_eq!(ctx.delta(entity), None);
+}
+
+#[test]
+fn pinned_indices_leave_gaps() {
+    use gaps::*;
+
+    assert_eq!(component_type::BRAVO, 0);
+    assert_eq!(component_type::ALPHA, 3);
+    assert_eq!(component_type::CHARLIE, 70);
+    assert_eq!(NUM_COMPONENTS, 71);
+    assert_eq!(action_property_type::UNDOABLE, 2);
+    assert_eq!(NUM_ACTION_PROPERTIES, 3);
+
+    let mut ctx = EcsCtx::new();
+    let entity = ctx.spawn();
+    ctx.insert_bravo(entity, 4);
+    ctx.insert_charlie(entity);
+    assert_eq!(ctx.bravo(entity), Some(4));
+    assert!(ctx.contains_charlie(entity));
+    assert!(!ctx.contains_alpha(entity));
+
+    let mut action = EcsAction::new();
+    action.remove_charlie(entity);
+    action.insert_alpha(entity);
+    action.set_undoable();
+    assert!(action.contains_undoable());
+    ctx.commit(&mut action);
+    assert!(ctx.contains_alpha(entity));
+    assert!(!ctx.contains_charlie(entity));
+}
@@ -0,0 +1,152 @@
+#[macro_use]
+extern crate serde_derive;
+extern crate serde;
+extern crate serde_json;
+extern crate genecs_macros;
+
+genecs_macros::ecs! {
+    #[copy] component position: (i32, i32);
+    #[copy] #[storage(sparse_set)] component health: u32;
+    component solid;
+    #[copy] action_property turn: u64;
+    action_property animated;
+}
+
+use ecs::*;
+
+#[test]
+fn reserved_ids_follow_those_of_the_merged_into_action() {
+    let mut first = EcsAction::new();
+    let a = first.reserve_entity();
+    let b = first.reserve_entity();
+    first.insert_position(a, (0, 0));
+    first.insert_position(b, (1, 1));
+
+    let mut second = EcsAction::new();
+    let c = second.reserve_entity();
+    second.insert_position(c, (2, 2));
+    second.insert_solid(c);
+
+    // the reserved ids of both actions start at the same index, but don't
+    // conflict once the second's are renumbered
+    assert_eq!(a, c);
+    assert!(first.merge(second).is_ok());
+
+    let mut ctx = EcsCtx::new();
+    let reserved = ctx.commit(&mut first);
+    assert_eq!(reserved.len(), 3);
+    assert_eq!(ctx.position(reserved[0]), Some((0, 0)));
+    assert_eq!(ctx.position(reserved[1]), Some((1, 1)));
+    assert_eq!(ctx.position(reserved[2]), Some((2, 2)));
+    assert!(!ctx.contains_solid(reserved[0]));
+    assert!(ctx.contains_solid(reserved[2]));
+}
+
+#[test]
+fn moves_between_reserved_ids_are_renumbered() {
+    let mut first = EcsAction::new();
+    let a = first.reserve_entity();
+    first.insert_health(a, 10);
+
+    let mut second = EcsAction::new();
+    let b = second.reserve_entity();
+    let c = second.reserve_entity();
+    second.insert_health(b, 20);
+    second.move_health(b, c);
+    second.set_ordered();
+
+    assert!(first.merge(second).is_ok());
+    assert!(first.is_ordered());
+
+    let mut ctx = EcsCtx::new();
+    let reserved = ctx.commit(&mut first);
+    assert_eq!(ctx.health(reserved[0]), Some(10));
+    assert_eq!(ctx.health(reserved[1]), None);
+    assert_eq!(ctx.health(reserved[2]), Some(20));
+}
+
+#[test]
+fn conflicts_are_reported_and_nothing_is_merged() {
+    let mut ctx = EcsCtx::new();
+    let a = ctx.spawn();
+    let b = ctx.spawn();
+    ctx.insert_health(a, 5);
+
+    let mut first = EcsAction::new();
+    first.insert_position(a, (1, 1));
+    first.remove_health(a);
+    first.insert_solid(b);
+
+    let mut second = EcsAction::new();
+    second.insert_position(a, (2, 2));
+    second.insert_health(a, 6);
+    second.insert_position(b, (3, 3));
+    second.remove_solid(a);
+
+    let mut conflicts = first.merge_conflicts(&second);
+    conflicts.sort_by_key(|conflict| (conflict.entity, conflict.component_type));
+    let expected = vec![
+        MergeConflict { entity: a, component_type: component_type::HEALTH },
+        MergeConflict { entity: a, component_type: component_type::POSITION },
+    ];
+    assert_eq!(conflicts, expected);
+
+    let mut errors = first.merge(second).unwrap_err();
+    errors.sort_by_key(|conflict| (conflict.entity, conflict.component_type));
+    assert_eq!(errors, expected);
+
+    // the first action is left as it was
+    ctx.commit(&mut first);
+    assert_eq!(ctx.position(a), Some((1, 1)));
+    assert_eq!(ctx.health(a), None);
+    assert_eq!(ctx.position(b), None);
+    assert!(ctx.contains_solid(b));
+}
+
+#[test]
+fn merging_matches_committing_in_turn() {
+    let mut merged_ctx = EcsCtx::new();
+    let a = merged_ctx.spawn();
+    let b = merged_ctx.spawn();
+    merged_ctx.insert_position(a, (0, 0));
+    merged_ctx.insert_health(b, 1);
+
+    let mut sequential_ctx = EcsCtx::new();
+    assert_eq!(sequential_ctx.spawn(), a);
+    assert_eq!(sequential_ctx.spawn(), b);
+    sequential_ctx.insert_position(a, (0, 0));
+    sequential_ctx.insert_health(b, 1);
+
+    let actions = || {
+        let mut first = EcsAction::new();
+        first.swap_position(a, b);
+        first.insert_solid(a);
+        first.set_turn(1);
+
+        let mut second = EcsAction::new();
+        second.move_health(b, a);
+        second.remove_solid(b);
+        second.set_turn(2);
+        second.set_animated();
+
+        (first, second)
+    };
+
+    let (mut first, mut second) = actions();
+    sequential_ctx.commit(&mut first);
+    sequential_ctx.commit(&mut second);
+
+    let (mut merged, second) = actions();
+    assert!(merged.merge(second).is_ok());
+    assert_eq!(merged.turn(), Some(2));
+    assert!(merged.contains_animated());
+    merged_ctx.commit(&mut merged);
+
+    for &id in &[a, b] {
+        assert_eq!(merged_ctx.position(id), sequential_ctx.position(id));
+        assert_eq!(merged_ctx.health(id), sequential_ctx.health(id));
+        assert_eq!(merged_ctx.contains_solid(id), sequential_ctx.contains_solid(id));
+    }
+    assert_eq!(merged_ctx.position(b), Some((0, 0)));
+    assert_eq!(merged_ctx.health(a), Some(1));
+}
@@ -0,0 +1,184 @@
+#[macro_use]
+extern crate serde_derive;
+extern crate serde;
+extern crate serde_json;
+extern crate genecs_macros;
+
+genecs_macros::ecs! {
+    #[copy] component health: u32;
+    #[container(RefCell)] #[storage(sparse_set)] component name: u32;
+    component solid;
+}
+
+// without cell components, a ctx with observers can be shared between threads
+genecs_macros::ecs! {
+    mod plain;
+    #[copy] component health: u32;
+    #[storage(hash)] component name: String;
+    component solid;
+}
+
+use std::sync::{Arc, Mutex};
+use std::thread;
+use ecs::*;
+
+type Events = Arc<Mutex<Vec<String>>>;
+
+fn observe(ctx: &mut EcsCtx) -> Events {
+    let events = Arc::new(Mutex::new(Vec::new()));
+
+    macro_rules! log {
+        ($register:ident, |$($arg:ident),*| $($format:tt)*) => {{
+            let events = events.clone();
+            ctx.$register(move |$($arg),*| events.lock().unwrap().push(format!($($format)*)));
+        }}
+    }
+
+    log!(on_insert_health, |id, value| "insert health {} {}", id, value);
+    log!(on_remove_health, |id, value| "remove health {} {}", id, value);
+    log!(on_replace_health, |id, old, new| "replace health {} {} {}", id, old, new);
+    log!(on_insert_name, |id, value| "insert name {} {}", id, value.borrow());
+    log!(on_remove_name, |id, value| "remove name {} {}", id, value.borrow());
+    log!(on_replace_name, |id, old, new| "replace name {} {} {}", id, old.borrow(), new.borrow());
+    log!(on_insert_solid, |id| "insert solid {}", id);
+    log!(on_remove_solid, |id| "remove solid {}", id);
+
+    events
+}
+
+// Two contexts in the same state, observed from the start
+fn contexts() -> (EcsCtx, Events, EcsCtx, Events) {
+    let mut plain = EcsCtx::new();
+    let mut with_inverse = EcsCtx::new();
+    let plain_events = observe(&mut plain);
+    let inverse_events = observe(&mut with_inverse);
+
+    for ctx in [&mut plain, &mut with_inverse].iter_mut() {
+        for index in 0..4 {
+            let id = entity_id::new(index, 0);
+            ctx.insert_health(id, index + 1);
+            ctx.insert_name(id, index + 10);
+            ctx.insert_solid(id);
+        }
+    }
+
+    (plain, plain_events, with_inverse, inverse_events)
+}
+
+fn events(events: &Events) -> Vec<String> {
+    events.lock().unwrap().drain(..).collect()
+}
+
+#[test]
+fn replacements_are_reported_as_replacements() {
+    let (mut plain, plain_events, mut with_inverse, inverse_events) = contexts();
+
+    let record = || {
+        let mut action = EcsAction::new();
+        action.insert_health(0, 3);
+        action.insert_name(0, 30);
+        action.insert_solid(0);
+        action
+    };
+
+    plain.commit(&mut record());
+    with_inverse.commit_with_inverse(&mut record(), &mut EcsAction::new());
+
+    // the entity already had the flag, so nothing changes
+    let expected = vec!["replace health 0 1 3", "replace name 0 10 30"];
+    assert_eq!(events(&plain_events), expected);
+    assert_eq!(events(&inverse_events), expected);
+}
+
+fn same_events(ordered: bool) {
+    let (mut plain, plain_events, mut with_inverse, inverse_events) = contexts();
+    let mut state: u32 = 777;
+
+    for step in 0..40 {
+        let (mut plain_action, mut action) = if ordered {
+            (EcsAction::ordered(), EcsAction::ordered())
+        } else {
+            (EcsAction::new(), EcsAction::new())
+        };
+
+        for _ in 0..4 {
+            state = state.wrapping_mul(1103515245).wrapping_add(12345);
+            let bits = state >> 8;
+            let a = entity_id::new(bits % 6, 0);
+            let b = entity_id::new((bits / 8) % 6, 0);
+            if a == b {
+                continue;
+            }
+            for action in [&mut plain_action, &mut action].iter_mut() {
+                match (bits / 64) % 4 {
+                    0 => {
+                        action.insert_health(a, step);
+                        action.insert_name(a, step + 100);
+                        action.insert_solid(a);
+                    }
+                    1 => {
+                        action.remove_health(a);
+                        action.remove_name(a);
+                        action.remove_solid(a);
+                    }
+                    2 => {
+                        action.swap_health(a, b);
+                        action.swap_name(a, b);
+                        action.swap_solid(a, b);
+                    }
+                    _ => {
+                        action.move_health(a, b);
+                        action.move_name(a, b);
+                        action.move_solid(a, b);
+                    }
+                }
+            }
+        }
+
+        plain.commit(&mut plain_action);
+        with_inverse.commit_with_inverse(&mut action, &mut EcsAction::new());
+        let (mut expected, mut found) = (events(&plain_events), events(&inverse_events));
+        if !ordered {
+            // unordered actions don't fix the order of their changes
+            expected.sort();
+            found.sort();
+        }
+        assert_eq!(found, expected, "events differ at step {}", step);
+    }
+}
+
+#[test]
+fn both_commits_report_the_same_events_for_unordered_actions() {
+    same_events(false);
+}
+
+#[test]
+fn both_commits_report_the_same_events_for_ordered_actions() {
+    same_events(true);
+}
+
+#[test]
+fn ctx_without_cells_is_sync() {
+    fn assert_sync<T: Send + Sync>() {}
+    assert_sync::<plain::EcsCtx>();
+
+    let mut ctx = plain::EcsCtx::new();
+    let entity = ctx.spawn();
+    let inserted = Arc::new(Mutex::new(Vec::new()));
+    {
+        let inserted = inserted.clone();
+        ctx.on_insert_health(move |id, &value| inserted.lock().unwrap().push((id, value)));
+    }
+    let mut action = plain::EcsAction::new();
+    action.insert_health(entity, 3);
+    ctx.commit(&mut action);
+
+    let ctx = &ctx;
+    thread::scope(|scope| {
+        let readers: Vec<_> = (0..2).map(|_| scope.spawn(move || ctx.health(entity))).collect();
+        for reader in readers {
+            assert_eq!(reader.join().unwrap(), Some(3));
+        }
+    });
+    assert_eq!(*inserted.lock().unwrap(), vec![(entity, 3)]);
+}
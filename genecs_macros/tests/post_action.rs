@@ -0,0 +1,181 @@
+#[macro_use]
+extern crate serde_derive;
+extern crate serde;
+extern crate serde_json;
+extern crate genecs_macros;
+
+genecs_macros::ecs! {
+    #[copy] component health: u32;
+    #[storage(hash)] component label: String;
+    #[container(RefCell)] #[storage(dense_vec)] component cell: u32;
+    #[storage(sparse_set)] component solid;
+}
+
+use ecs::*;
+
+type Components = (Option<u32>, Option<String>, Option<u32>, bool);
+
+fn components(ctx: &EcsCtx, id: EntityId) -> Components {
+    (ctx.health(id), ctx.label(id).cloned(), ctx.cell(id).map(|cell| *cell.borrow()), ctx.contains_solid(id))
+}
+
+// The components an entity will have after the action, as answered by
+// PostActionEntityRef, checking its answers agree with each other
+fn predicted(entity: PostActionEntityRef) -> Components {
+    let health = entity.health();
+    assert_eq!(entity.health_ref().cloned(), health);
+    match entity.change_health() {
+        Some(Change::Insert(value)) => assert_eq!(Some(value), health),
+        Some(Change::Remove) => assert_eq!(health, None),
+        None => assert_eq!(entity.current_health(), health),
+    }
+
+    let cell = entity.cell().map(|cell| match cell {
+        ContainerComponentRef::Contained(cell) => *cell.borrow(),
+        ContainerComponentRef::Raw(value) => *value,
+    });
+
+    let solid = entity.contains_solid();
+    if entity.change_solid().is_none() {
+        assert_eq!(entity.current_contains_solid(), solid);
+    }
+
+    (health, entity.label().cloned(), cell, solid)
+}
+
+fn record(action: &mut EcsAction, operation: u32, a: EntityId, b: EntityId, value: u32) {
+    match operation {
+        0 | 1 => {
+            action.insert_health(a, value);
+            action.insert_label(a, format!("label {}", value));
+            action.insert_cell(a, value);
+            action.insert_solid(a);
+        }
+        2 => {
+            action.remove_health(a);
+            action.remove_label(a);
+            action.remove_cell(a);
+            action.remove_solid(a);
+        }
+        3 => {
+            action.swap_health(a, b);
+            action.swap_label(a, b);
+            action.swap_cell(a, b);
+            action.swap_solid(a, b);
+        }
+        _ => {
+            action.move_health(a, b);
+            action.move_label(a, b);
+            action.move_cell(a, b);
+            action.move_solid(a, b);
+        }
+    }
+}
+
+// The entities each component's positive and negative iterators yield, with
+// the values they yield for the positive ones
+struct Iterated {
+    health: (Vec<(EntityId, u32)>, Vec<EntityId>),
+    label: (Vec<(EntityId, String)>, Vec<EntityId>),
+    cell: (Vec<(EntityId, u32)>, Vec<EntityId>),
+    solid: (Vec<EntityId>, Vec<EntityId>),
+}
+
+fn iterated(ctx: &EcsCtx, action: &EcsAction) -> Iterated {
+    Iterated {
+        health: (action.health_positive_iter(ctx).map(|(id, value)| (id, *value)).collect(),
+                 action.health_negative_iter(ctx).collect()),
+        label: (action.label_positive_iter(ctx).map(|(id, value)| (id, value.clone())).collect(),
+                action.label_negative_iter(ctx).collect()),
+        cell: (action.cell_positive_iter(ctx).map(|(id, cell)| (id, match cell {
+                   ContainerComponentRef::Contained(cell) => *cell.borrow(),
+                   ContainerComponentRef::Raw(value) => *value,
+               })).collect(),
+               action.cell_negative_iter(ctx).collect()),
+        solid: (action.solid_positive_iter(ctx).collect(), action.solid_negative_iter(ctx).collect()),
+    }
+}
+
+// Checks the iterators yielded exactly the entities whose component the
+// commit changed, with the values they were left with
+fn check_iterated<T, F>(iterated: &(Vec<(EntityId, T)>, Vec<EntityId>), before: &[(EntityId, Components)],
+                        after: &[(EntityId, Components)], component: F)
+    where T: Clone + PartialEq + ::std::fmt::Debug, F: Fn(&Components) -> Option<T>
+{
+    let (positive, negative) = iterated;
+    for ((id, before), (_, after)) in before.iter().zip(after) {
+        let id = *id;
+        let value = component(after);
+        let yielded = positive.iter().find(|&&(other, _)| other == id).map(|(_, value)| value.clone());
+        if value.is_some() && value != component(before) {
+            assert_eq!(yielded, value, "positive iterator value of {}", id);
+        } else if value.is_none() && component(before).is_some() {
+            assert!(negative.contains(&id), "negative iterator missing {}", id);
+        }
+        if yielded.is_some() {
+            assert_eq!(yielded, value, "positive iterator yielded {}", id);
+        }
+        if negative.contains(&id) {
+            assert_eq!(value, None, "negative iterator yielded {}", id);
+        }
+    }
+}
+
+fn predictions_match_commit(ordered: bool) {
+    let mut ctx = EcsCtx::new();
+    let mut ids: Vec<EntityId> = (0..6).map(|_| ctx.spawn()).collect();
+    let mut state: u32 = 2468;
+
+    for step in 0..60 {
+        let mut action = if ordered { EcsAction::ordered() } else { EcsAction::new() };
+        let reserved = action.reserve_entity();
+        let candidates: Vec<EntityId> = ids.iter().cloned().chain(Some(reserved)).collect();
+
+        for _ in 0..5 {
+            state = state.wrapping_mul(1103515245).wrapping_add(12345);
+            let bits = state >> 8;
+            let a = candidates[bits as usize % candidates.len()];
+            let b = candidates[(bits / 8) as usize % candidates.len()];
+            if a != b {
+                record(&mut action, (bits / 64) % 5, a, b, step);
+            }
+        }
+
+        let predictions: Vec<Components> = candidates.iter()
+            .map(|&id| predicted(ctx.post_action_entity(id, &action)))
+            .collect();
+        assert!(ctx.post_action_entity(reserved, &action).is_alive());
+        let iterated = iterated(&ctx, &action);
+        let before: Vec<(EntityId, Components)> = candidates.iter().map(|&id| (id, components(&ctx, id))).collect();
+
+        let allocated = ctx.commit(&mut action);
+        ids.push(allocated[0]);
+        let committed: Vec<Components> = ids.iter()
+            .map(|&id| components(&ctx, id))
+            .collect();
+
+        assert_eq!(predictions, committed, "step {}", step);
+
+        // the reserved entity is known by its reserved id to the iterators
+        let after: Vec<(EntityId, Components)> = candidates.iter().cloned().zip(committed).collect();
+        check_iterated(&iterated.health, &before, &after, |components| components.0);
+        check_iterated(&iterated.label, &before, &after, |components| components.1.clone());
+        check_iterated(&iterated.cell, &before, &after, |components| components.2);
+        let solid = (iterated.solid.0.iter().map(|&id| (id, ())).collect(), iterated.solid.1.clone());
+        check_iterated(&solid, &before, &after, |components| if components.3 { Some(()) } else { None });
+
+        // keep the number of entities in check
+        let oldest = ids.remove(0);
+        ctx.despawn(oldest);
+    }
+}
+
+#[test]
+fn unordered_predictions_match_commit() {
+    predictions_match_commit(false);
+}
+
+#[test]
+fn ordered_predictions_match_commit() {
+    predictions_match_commit(true);
+}
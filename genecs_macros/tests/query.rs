@@ -0,0 +1,137 @@
+#[macro_use]
+extern crate serde_derive;
+extern crate serde;
+extern crate serde_json;
+extern crate genecs_macros;
+
+genecs_macros::ecs! {
+    #[copy] component a: u32;
+    component b;
+    #[copy] #[storage(hash)] component c: u32;
+    #[storage(hash)] component d;
+    #[copy] #[storage(dense_vec)] component e: u32;
+    #[storage(sparse_set)] component f;
+}
+
+use ecs::*;
+
+const NUM_ENTITIES: u32 = 300;
+
+// Gives each entity a pseudo-random selection of components, with the
+// entities spread over several generations
+fn populate() -> (EcsCtx, Vec<EntityId>) {
+    let mut ctx = EcsCtx::new();
+    let mut state: u32 = 12345;
+    let mut entities = Vec::new();
+    for index in 0..NUM_ENTITIES {
+        let entity = entity_id::new(index, index % 3);
+        entities.push(entity);
+        state = state.wrapping_mul(1103515245).wrapping_add(12345);
+        let bits = state >> 16;
+        let value = index * 10;
+        if bits & 1 != 0 { ctx.insert_a(entity, value); }
+        if bits & 2 != 0 { ctx.insert_b(entity); }
+        if bits & 4 != 0 { ctx.insert_c(entity, value + 1); }
+        if bits & 8 != 0 { ctx.insert_d(entity); }
+        if bits & 16 != 0 { ctx.insert_e(entity, value + 2); }
+        if bits & 32 != 0 { ctx.insert_f(entity); }
+    }
+
+    (ctx, entities)
+}
+
+fn sorted<I: IntoIterator<Item=EntityId>>(ids: I) -> Vec<EntityId> {
+    let mut ids: Vec<EntityId> = ids.into_iter().collect();
+    ids.sort();
+    ids
+}
+
+fn brute_force<P: Fn(EntityId) -> bool>(entities: &[EntityId], predicate: P) -> Vec<EntityId> {
+    sorted(entities.iter().cloned().filter(|&entity| predicate(entity)))
+}
+
+#[test]
+fn sorted_collections_are_joined() {
+    let (ctx, entities) = populate();
+
+    let expected = brute_force(&entities, |e| ctx.contains_a(e) && ctx.contains_b(e));
+    assert!(!expected.is_empty());
+    assert_eq!(sorted(ctx.query().with_a().with_b().into_iter().map(|(id, _)| id)), expected);
+
+    let expected = brute_force(&entities, |e| ctx.contains_b(e) && !ctx.contains_a(e));
+    assert_eq!(sorted(ctx.query().with_b().without_a()), expected);
+}
+
+#[test]
+fn unsorted_collections_are_probed() {
+    let (ctx, entities) = populate();
+
+    let expected = brute_force(&entities, |e| ctx.contains_d(e) && ctx.contains_f(e));
+    assert!(!expected.is_empty());
+    assert_eq!(sorted(ctx.query().with_d().with_f()), expected);
+
+    let expected = brute_force(&entities, |e| ctx.contains_c(e) && ctx.contains_e(e) && !ctx.contains_f(e));
+    let found = ctx.query().with_c().with_e().without_f().into_iter().map(|((id, _), _)| id);
+    assert_eq!(sorted(found), expected);
+}
+
+#[test]
+fn mixed_collections_are_probed() {
+    let (ctx, entities) = populate();
+
+    // every combination of a sorted and an unsorted collection, whichever is
+    // smaller driving the join
+    let expected = brute_force(&entities, |e| ctx.contains_a(e) && ctx.contains_d(e));
+    assert_eq!(sorted(ctx.query().with_a().with_d().into_iter().map(|(id, _)| id)), expected);
+
+    let expected = brute_force(&entities, |e| ctx.contains_b(e) && ctx.contains_e(e) && ctx.contains_f(e));
+    assert_eq!(sorted(ctx.query().with_b().with_e().with_f().into_iter().map(|(id, _)| id)), expected);
+
+    let expected = brute_force(&entities, |e| ctx.contains_c(e) && ctx.contains_b(e) && !ctx.contains_d(e) && !ctx.contains_a(e));
+    let found = ctx.query().with_c().with_b().without_d().without_a().into_iter().map(|(id, _)| id);
+    assert_eq!(sorted(found), expected);
+}
+
+#[test]
+fn queries_without_components_visit_every_entity() {
+    let (ctx, entities) = populate();
+
+    let with_any = |e| ctx.contains_a(e) || ctx.contains_b(e) || ctx.contains_c(e) ||
+        ctx.contains_d(e) || ctx.contains_e(e) || ctx.contains_f(e);
+    assert_eq!(sorted(ctx.query()), brute_force(&entities, with_any));
+
+    let expected = brute_force(&entities, |e| with_any(e) && !ctx.contains_e(e) && !ctx.contains_c(e));
+    assert_eq!(sorted(ctx.query().without_e().without_c()), expected);
+}
+
+#[test]
+fn queries_fetch_component_values() {
+    let (ctx, entities) = populate();
+
+    let mut found: Vec<(EntityId, u32, u32, u32)> = ctx.query().with_a().with_c().with_e().into_iter()
+        .map(|(((id, a), c), e)| (id, *a, *c, *e))
+        .collect();
+    found.sort();
+
+    let expected: Vec<(EntityId, u32, u32, u32)> = brute_force(&entities, |e| {
+        ctx.contains_a(e) && ctx.contains_c(e) && ctx.contains_e(e)
+    }).into_iter().map(|e| (e, ctx.a(e).unwrap(), ctx.c(e).unwrap(), ctx.e(e).unwrap())).collect();
+
+    assert!(!expected.is_empty());
+    assert_eq!(found, expected);
+}
+
+#[test]
+fn queries_follow_changes() {
+    let (mut ctx, entities) = populate();
+    for &entity in entities.iter().step_by(2) {
+        ctx.remove_a(entity);
+        ctx.insert_b(entity);
+    }
+
+    let expected = brute_force(&entities, |e| ctx.contains_a(e) && ctx.contains_b(e));
+    assert_eq!(sorted(ctx.query().with_a().with_b().into_iter().map(|(id, _)| id)), expected);
+
+    let expected = brute_force(&entities, |e| ctx.contains_b(e) && ctx.contains_d(e));
+    assert_eq!(sorted(ctx.query().with_b().with_d()), expected);
+}
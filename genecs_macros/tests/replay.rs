@@ -0,0 +1,142 @@
+#[macro_use]
+extern crate serde_derive;
+extern crate serde;
+extern crate serde_json;
+extern crate genecs_macros;
+
+genecs_macros::ecs! {
+    #[copy] component position: (i32, i32);
+    #[storage(hash)] component name: String;
+    #[container(RefCell)] #[storage(dense_vec)] component inventory: Vec<u32>;
+    #[storage(sparse_set)] component solid;
+    #[copy] action_property turn: u64;
+    action_property undoable;
+}
+
+use ecs::*;
+
+fn to_json(ctx: EcsCtx) -> serde_json::Value {
+    serde_json::to_value(SerializableEcsCtx::from(ctx)).unwrap()
+}
+
+fn from_json(json: &serde_json::Value) -> EcsCtx {
+    EcsCtx::from(serde_json::from_value::<SerializableEcsCtx>(json.clone()).unwrap())
+}
+
+// A copy of an action, made by serializing it
+fn through_json(action: &EcsAction) -> EcsAction {
+    serde_json::from_str(&serde_json::to_string(action).unwrap()).unwrap()
+}
+
+// Actions covering every kind of change, recorded against `ctx` before it
+// commits each of them
+fn actions(ctx: &mut EcsCtx, a: EntityId, b: EntityId) -> Vec<EcsAction> {
+    let mut actions = Vec::new();
+
+    let mut insertions = EcsAction::new();
+    let c = insertions.reserve_entity();
+    let d = insertions.reserve_entity();
+    insertions.insert_position(c, (3, 3));
+    insertions.insert_name(c, "c".to_string());
+    insertions.insert_inventory(d, vec![4]);
+    insertions.insert_solid(d);
+    insertions.set_turn(1);
+    insertions.set_undoable();
+    let reserved = ctx.commit(&mut through_json(&insertions));
+    actions.push(insertions);
+    let (c, d) = (reserved[0], reserved[1]);
+
+    let mut changes = EcsAction::new();
+    changes.remove_position(a);
+    changes.remove_inventory(b);
+    changes.swap_name(a, c);
+    changes.move_solid(d, a);
+    changes.swap_inventory(b, d);
+    changes.remove_entity_by_id(b, ctx);
+    changes.set_turn(2);
+    ctx.commit(&mut through_json(&changes));
+    actions.push(changes);
+
+    // later changes to the same component depend on earlier ones
+    let mut ordered = EcsAction::ordered();
+    let e = ordered.reserve_entity();
+    ordered.insert_position(e, (5, 5));
+    ordered.move_position(e, c);
+    ordered.insert_position(e, (6, 6));
+    ordered.swap_solid(a, e);
+    ordered.remove_name(c);
+    ordered.insert_name(c, "c again".to_string());
+    ctx.commit(&mut through_json(&ordered));
+    actions.push(ordered);
+
+    actions
+}
+
+
+#[test]
+fn actions_round_trip() {
+    let mut ctx = EcsCtx::new();
+    let a = ctx.spawn();
+    ctx.insert_position(a, (0, 0));
+
+    let mut action = EcsAction::ordered();
+    let reserved = action.reserve_entity();
+    action.insert_position(reserved, (1, 1));
+    action.insert_name(a, "a".to_string());
+    action.insert_inventory(a, vec![1, 2]);
+    action.insert_solid(reserved);
+    action.swap_position(a, reserved);
+    action.move_solid(reserved, a);
+    action.remove_name(a);
+    action.set_turn(7);
+    action.set_undoable();
+
+    let json = serde_json::to_value(&action).unwrap();
+    let restored: EcsAction = serde_json::from_value(json.clone()).unwrap();
+    assert_eq!(serde_json::to_value(&restored).unwrap(), json);
+
+    assert!(restored.is_ordered());
+    assert!(restored.is_reserved(reserved));
+    assert!(!restored.is_reserved(a));
+    assert_eq!(restored.turn(), Some(7));
+    assert!(restored.contains_undoable());
+
+    // the restored action has the same effect as the original
+    let snapshot = to_json(ctx);
+    let mut original_ctx = from_json(&snapshot);
+    let mut restored_ctx = from_json(&snapshot);
+    let original_ids = original_ctx.commit(&mut action);
+    let restored_ids = restored_ctx.commit(&mut { restored });
+    assert_eq!(original_ids, restored_ids);
+    assert_eq!(to_json(original_ctx), to_json(restored_ctx));
+}
+
+#[test]
+fn replaying_actions_onto_a_snapshot() {
+    let mut ctx = EcsCtx::new();
+    let a = ctx.spawn();
+    let b = ctx.spawn();
+    let removed = ctx.spawn();
+    ctx.insert_position(a, (0, 0));
+    ctx.insert_inventory(b, vec![1, 2]);
+    ctx.insert_solid(removed);
+    ctx.remove_entity(removed);
+
+    // a freed id in the snapshot's allocator is reused by the first reserved
+    // entity, both live and on replay
+    let snapshot = to_json(ctx);
+    let mut live = from_json(&snapshot);
+    let saved: Vec<String> = actions(&mut live, a, b).iter()
+        .map(|action| serde_json::to_string(action).unwrap())
+        .collect();
+
+    let mut replayed = from_json(&snapshot);
+    replayed.replay(saved.iter().map(|action| serde_json::from_str::<EcsAction>(action).unwrap()));
+
+    // entities spawned after replaying get the same ids as they would live
+    assert_eq!(live.spawn(), replayed.spawn());
+
+    let live = to_json(live);
+    assert!(live != snapshot);
+    assert_eq!(to_json(replayed), live);
+}
@@ -0,0 +1,156 @@
+#[macro_use]
+extern crate serde_derive;
+extern crate serde;
+extern crate serde_json;
+extern crate genecs_macros;
+
+genecs_macros::ecs! {
+    component solid;
+}
+
+use std::collections::{BTreeMap, BTreeSet};
+use ecs::*;
+
+// An index far beyond any other, which mustn't make the dense and sparse
+// collections allocate a slot for every index before it
+const LARGE_INDEX: u32 = 4_000_000_000;
+
+// Ids sharing a handful of indices across several generations, so that stale
+// ids are used alongside live ones, and the occasional very large index
+fn ids() -> Vec<EntityId> {
+    let mut ids = Vec::new();
+    for index in 0..8 {
+        for generation in 0..3 {
+            ids.push(entity_id::new(index, generation));
+        }
+    }
+    ids.push(entity_id::new(LARGE_INDEX, 0));
+    ids.push(entity_id::new(LARGE_INDEX, 1));
+    ids
+}
+
+// A deterministic sequence of (operation, id) pairs
+fn operations() -> Vec<(u32, EntityId)> {
+    let ids = ids();
+    let mut state: u32 = 12345;
+    (0..2000).map(|_| {
+        state = state.wrapping_mul(1103515245).wrapping_add(12345);
+        let bits = state >> 8;
+        (bits % 3, ids[(bits / 3) as usize % ids.len()])
+    }).collect()
+}
+
+macro_rules! map_tests {
+    ($map:ident, $stale:ident, $model:ident) => {
+        #[test]
+        fn $stale() {
+            let live = entity_id::new(3, 1);
+            let stale = entity_id::new(3, 0);
+            let mut map = $map::new();
+            map.insert(live, 1);
+            map.insert(stale, 2);
+
+            assert_eq!(map.get(live), Some(&1));
+            assert_eq!(map.get(stale), Some(&2));
+            assert_eq!(map.len(), 2);
+
+            assert_eq!(map.remove(stale), Some(2));
+            assert_eq!(map.remove(stale), None);
+            assert_eq!(map.get(live), Some(&1));
+            assert_eq!(map.len(), 1);
+
+            let large = entity_id::new(LARGE_INDEX, 0);
+            map.insert(large, 3);
+            assert_eq!(map.get(large), Some(&3));
+            assert_eq!(map.remove(large), Some(3));
+            assert_eq!(map.remove(live), Some(1));
+            assert_eq!(map.len(), 0);
+        }
+
+        #[test]
+        fn $model() {
+            let mut map = $map::new();
+            let mut model = BTreeMap::new();
+            for (step, (operation, id)) in operations().into_iter().enumerate() {
+                match operation {
+                    0 | 1 => {
+                        map.insert(id, step);
+                        model.insert(id, step);
+                    }
+                    _ => assert_eq!(map.remove(id), model.remove(&id)),
+                }
+
+                assert_eq!(map.len(), model.len());
+                for id in ids() {
+                    assert_eq!(map.get(id), model.get(&id));
+                    assert_eq!(map.contains_key(id), model.contains_key(&id));
+                }
+                let mut contents: Vec<(EntityId, usize)> = map.copy_iter().collect();
+                contents.sort();
+                assert_eq!(contents, model.iter().map(|(&id, &value)| (id, value)).collect::<Vec<_>>());
+            }
+        }
+    }
+}
+
+macro_rules! set_tests {
+    ($set:ident, $stale:ident, $model:ident) => {
+        #[test]
+        fn $stale() {
+            let live = entity_id::new(3, 1);
+            let stale = entity_id::new(3, 0);
+            let mut set = $set::new();
+            set.insert(live);
+            set.insert(stale);
+
+            assert!(set.contains(live));
+            assert!(set.contains(stale));
+            assert_eq!(set.len(), 2);
+
+            assert!(set.remove(stale));
+            assert!(!set.remove(stale));
+            assert!(set.contains(live));
+            assert_eq!(set.len(), 1);
+
+            let large = entity_id::new(LARGE_INDEX, 0);
+            set.insert(large);
+            assert!(set.contains(large));
+            assert!(set.remove(large));
+            assert!(set.remove(live));
+            assert!(set.is_empty());
+        }
+
+        #[test]
+        fn $model() {
+            let mut set = $set::new();
+            let mut model = BTreeSet::new();
+            for (operation, id) in operations() {
+                match operation {
+                    0 | 1 => {
+                        set.insert(id);
+                        model.insert(id);
+                    }
+                    _ => assert_eq!(set.remove(id), model.remove(&id)),
+                }
+
+                assert_eq!(set.len(), model.len());
+                for id in ids() {
+                    assert_eq!(set.contains(id), model.contains(&id));
+                }
+                let mut contents: Vec<EntityId> = set.iter().collect();
+                contents.sort();
+                assert_eq!(contents, model.iter().cloned().collect::<Vec<_>>());
+            }
+        }
+    }
+}
+
+map_tests!(EntityMap, btree_map_keeps_stale_ids_apart, btree_map_matches_model);
+map_tests!(EntityHashMap, hash_map_keeps_stale_ids_apart, hash_map_matches_model);
+map_tests!(EntityDenseMap, dense_map_keeps_stale_ids_apart, dense_map_matches_model);
+map_tests!(EntitySparseMap, sparse_map_keeps_stale_ids_apart, sparse_map_matches_model);
+
+set_tests!(EntitySet, btree_set_keeps_stale_ids_apart, btree_set_matches_model);
+set_tests!(EntityHashSet, hash_set_keeps_stale_ids_apart, hash_set_matches_model);
+set_tests!(EntityDenseSet, dense_set_keeps_stale_ids_apart, dense_set_matches_model);
+set_tests!(EntitySparseSet, sparse_set_keeps_stale_ids_apart, sparse_set_matches_model);
@@ -0,0 +1,8 @@
+extern crate genecs_macros;
+
+genecs_macros::ecs! {
+    component solid;
+    #[storage(hash)] component solid;
+}
+
+fn main() {}
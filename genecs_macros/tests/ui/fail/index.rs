@@ -0,0 +1,30 @@
+extern crate genecs_macros;
+
+genecs_macros::ecs! {
+    mod float;
+    #[index(1.5)] component alpha;
+    component bravo;
+}
+
+genecs_macros::ecs! {
+    mod string;
+    #[index("0")] component alpha;
+}
+
+genecs_macros::ecs! {
+    mod negative;
+    #[index(-1)] component alpha;
+}
+
+genecs_macros::ecs! {
+    mod identifier;
+    #[index(first)] component alpha;
+}
+
+genecs_macros::ecs! {
+    mod duplicate;
+    #[index(0)] component alpha;
+    #[index(0)] component bravo;
+}
+
+fn main() {}
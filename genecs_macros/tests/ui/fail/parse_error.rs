@@ -0,0 +1,8 @@
+extern crate genecs_macros;
+
+genecs_macros::ecs! {
+    #[copy] use std::collections::BTreeMap;
+    component position: (i32, i32);
+}
+
+fn main() {}
@@ -0,0 +1,10 @@
+extern crate genecs_macros;
+
+// each validation error is reported at the attribute with the invalid value
+genecs_macros::ecs! {
+    #[copy] component position: (i32, i32);
+    #[storage(linked_list)] component velocity: (i32, i32);
+    #[container(Box)] component path: Vec<u32>;
+}
+
+fn main() {}
@@ -0,0 +1,42 @@
+#[macro_use]
+extern crate serde_derive;
+extern crate serde;
+extern crate serde_json;
+extern crate genecs_macros;
+
+mod geometry {
+    #[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+    pub struct Vec2 {
+        pub x: i32,
+        pub y: i32,
+    }
+}
+
+// every item, and every attribute with each form of its argument
+genecs_macros::ecs! {
+    pub mod world;
+    dynamic;
+    use geometry::Vec2;
+
+    #[copy] #[index(1)] component position: Vec2;
+    #[storage(btree)] component velocity: (i32, i32);
+    #[container(RefCell)] #[storage(hash)] component inventory: Vec<u32>;
+    #[container(UnsafeCell)] #[storage(dense_vec)] component path: Vec<Vec2>;
+    #[storage(sparse_set)] #[index(0)] component solid;
+    #[copy] action_property turn: u64;
+    action_property label: String;
+    action_property undoable;
+}
+
+fn main() {
+    let mut ctx = world::EcsCtx::new();
+    let entity = ctx.spawn();
+    ctx.insert_position(entity, geometry::Vec2 { x: 1, y: 2 });
+    ctx.insert_solid(entity);
+
+    let mut action = world::EcsAction::new();
+    action.set_turn(3);
+    action.set_label("move".to_string());
+    action.set_undoable();
+    ctx.commit(&mut action);
+}
@@ -0,0 +1,177 @@
+#[macro_use]
+extern crate serde_derive;
+extern crate serde;
+extern crate serde_json;
+extern crate genecs_macros;
+
+genecs_macros::ecs! {
+    #[copy] component position: (i32, i32);
+    #[storage(hash)] component name: String;
+    #[storage(sparse_set)] component solid;
+}
+
+use ecs::*;
+
+fn order_dependent(entity: EntityId, component_type: ComponentType) -> ActionConflict {
+    ActionConflict::OrderDependent { entity, component_type }
+}
+
+fn missing_source(entity: EntityId, component_type: ComponentType) -> ActionConflict {
+    ActionConflict::MissingSource { entity, component_type }
+}
+
+// A ctx with two entities which both have every component
+fn ctx() -> (EcsCtx, EntityId, EntityId) {
+    let mut ctx = EcsCtx::new();
+    let a = ctx.spawn();
+    let b = ctx.spawn();
+    for &(entity, x) in &[(a, 0), (b, 1)] {
+        ctx.insert_position(entity, (x, x));
+        ctx.insert_name(entity, x.to_string());
+        ctx.insert_solid(entity);
+    }
+
+    (ctx, a, b)
+}
+
+fn conflicts(ctx: &EcsCtx, action: &EcsAction) -> Vec<ActionConflict> {
+    let mut conflicts = ctx.validate(action).err().unwrap_or_default();
+    conflicts.sort_by_key(|conflict| format!("{:?}", conflict));
+    conflicts
+}
+
+#[test]
+fn independent_changes_are_valid() {
+    let (ctx, a, b) = ctx();
+    assert_eq!(ctx.validate(&EcsAction::new()), Ok(()));
+
+    let mut action = EcsAction::new();
+    let reserved = action.reserve_entity();
+    action.insert_position(reserved, (2, 2));
+    action.remove_position(a);
+    action.swap_name(a, b);
+    action.move_solid(a, reserved);
+    assert_eq!(ctx.validate(&action), Ok(()));
+}
+
+#[test]
+fn changing_a_component_twice_is_order_dependent() {
+    let (ctx, a, b) = ctx();
+
+    let mut action = EcsAction::new();
+    action.insert_position(a, (5, 5));
+    action.remove_position(a);
+    action.insert_name(a, "a".to_string());
+    action.swap_name(a, b);
+    action.move_solid(a, b);
+    action.remove_solid(b);
+    action.insert_solid(a);
+
+    let mut expected = vec![
+        order_dependent(a, component_type::POSITION),
+        order_dependent(a, component_type::NAME),
+        order_dependent(a, component_type::SOLID),
+        order_dependent(b, component_type::SOLID),
+    ];
+    expected.sort_by_key(|conflict| format!("{:?}", conflict));
+    assert_eq!(conflicts(&ctx, &action), expected);
+}
+
+#[test]
+fn swapping_or_moving_a_component_with_itself_is_valid() {
+    let (mut ctx, a, _) = ctx();
+
+    let mut action = EcsAction::new();
+    action.swap_position(a, a);
+    action.move_name(a, a);
+    assert_eq!(ctx.validate(&action), Ok(()));
+
+    assert!(ctx.try_commit(&mut action).is_ok());
+    assert_eq!(ctx.position(a), Some((0, 0)));
+    assert_eq!(ctx.name(a).map(String::as_str), Some("0"));
+}
+
+#[test]
+fn moves_need_a_source() {
+    let (mut ctx, a, b) = ctx();
+    ctx.remove_position(a);
+    ctx.remove_solid(a);
+
+    let mut action = EcsAction::new();
+    let reserved = action.reserve_entity();
+    action.move_position(a, b);
+    action.move_solid(reserved, b);
+    action.move_name(a, reserved);
+
+    let mut expected = vec![
+        missing_source(a, component_type::POSITION),
+        missing_source(reserved, component_type::SOLID),
+    ];
+    expected.sort_by_key(|conflict| format!("{:?}", conflict));
+    assert_eq!(conflicts(&ctx, &action), expected);
+}
+
+#[test]
+fn ordered_actions_can_change_a_component_several_times() {
+    let (mut ctx, a, b) = ctx();
+
+    let mut action = EcsAction::ordered();
+    action.insert_position(a, (5, 5));
+    action.swap_position(a, b);
+    action.move_position(b, a);
+    action.remove_name(a);
+    action.insert_name(a, "a".to_string());
+    action.insert_solid(a);
+    action.move_solid(a, b);
+    assert_eq!(ctx.validate(&action), Ok(()));
+
+    assert!(ctx.try_commit(&mut action).is_ok());
+    assert_eq!(ctx.position(a), Some((5, 5)));
+    assert_eq!(ctx.position(b), None);
+    assert_eq!(ctx.name(a).map(String::as_str), Some("a"));
+    assert!(!ctx.contains_solid(a));
+    assert!(ctx.contains_solid(b));
+
+    // a move from an entity without the component is still missing its
+    // source
+    ctx.remove_position(b);
+    let mut action = EcsAction::ordered();
+    action.move_position(b, a);
+    assert_eq!(conflicts(&ctx, &action), vec![missing_source(b, component_type::POSITION)]);
+}
+
+#[test]
+fn invalid_actions_are_left_intact() {
+    let (mut ctx, a, b) = ctx();
+
+    let mut action = EcsAction::new();
+    let reserved = action.reserve_entity();
+    action.insert_position(reserved, (9, 9));
+    action.insert_position(a, (5, 5));
+    action.remove_position(a);
+    action.remove_solid(b);
+    let before = serde_json::to_value(&action).unwrap();
+
+    assert_eq!(ctx.try_commit(&mut action), Err(vec![order_dependent(a, component_type::POSITION)]));
+    assert_eq!(serde_json::to_value(&action).unwrap(), before);
+    assert!(action.is_reserved(reserved));
+
+    // nothing is committed, and no entity is allocated for the reserved one
+    assert_eq!(ctx.position(a), Some((0, 0)));
+    assert!(ctx.contains_solid(b));
+    assert_eq!(ctx.component_ids(component_type::POSITION).count(), 2);
+
+    // the action can still be committed as it was recorded
+    let ids = ctx.commit(&mut action);
+    assert_eq!(ctx.position(ids[0]), Some((9, 9)));
+    assert!(!ctx.contains_solid(b));
+}
+
+#[test]
+fn conflicts_are_described() {
+    let (_, a, _) = ctx();
+    assert_eq!(order_dependent(a, component_type::NAME).to_string(),
+               format!("name of entity {} is changed more than once", a));
+    assert_eq!(missing_source(a, component_type::SOLID).to_string(),
+               format!("solid moved from entity {} which doesn't have it", a));
+}
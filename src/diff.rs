@@ -0,0 +1,156 @@
+use std::collections::BTreeSet;
+use std::fmt;
+
+use rustc_serialize::json::{self, Json};
+
+use schema::{self, EcsSchema};
+
+/// A difference between two spec files. `section`, `id` and `field` locate
+/// the difference in the same way as they do for a `SchemaError`, and values
+/// are given as they appear in the spec, or None where they are absent.
+/// Entries in both specs whose index differs, such as those after an added
+/// or removed entry, are `Renumbered`, as their data can't be read back.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SpecChange {
+    Added {
+        section: String,
+        id: String,
+    },
+    Removed {
+        section: String,
+        id: String,
+    },
+    Changed {
+        section: String,
+        id: Option<String>,
+        field: Option<String>,
+        old: Option<String>,
+        new: Option<String>,
+    },
+    Renumbered {
+        section: String,
+        id: String,
+        old: usize,
+        new: usize,
+    },
+}
+
+impl fmt::Display for SpecChange {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            SpecChange::Added { ref section, ref id } => write!(f, "added {}.{}", section, id),
+            SpecChange::Removed { ref section, ref id } => write!(f, "removed {}.{}", section, id),
+            SpecChange::Changed { ref section, ref id, ref field, ref old, ref new } => {
+                write!(f, "changed {}", section)?;
+                if let Some(ref id) = *id {
+                    write!(f, ".{}", id)?;
+                }
+                if let Some(ref field) = *field {
+                    write!(f, ".{}", field)?;
+                }
+                write!(f, ": {} -> {}",
+                       old.as_ref().map_or("(unset)", String::as_str),
+                       new.as_ref().map_or("(unset)", String::as_str))
+            }
+            SpecChange::Renumbered { ref section, ref id, old, new } => {
+                write!(f, "renumbered {}.{}: {} -> {}", section, id, old, new)
+            }
+        }
+    }
+}
+
+fn describe(value: &Json) -> String {
+    match *value {
+        Json::String(ref string) => string.clone(),
+        ref other => other.to_string(),
+    }
+}
+
+fn changed(section: &str, id: Option<&str>, field: Option<&str>, old: Option<&Json>, new: Option<&Json>) -> SpecChange {
+    SpecChange::Changed {
+        section: section.to_string(),
+        id: id.map(str::to_string),
+        field: field.map(str::to_string),
+        old: old.map(describe),
+        new: new.map(describe),
+    }
+}
+
+fn diff_section(changes: &mut Vec<SpecChange>, section: &str, old: Option<&json::Object>, new: Option<&json::Object>) {
+    let empty = json::Object::new();
+    let old = old.unwrap_or(&empty);
+    let new = new.unwrap_or(&empty);
+
+    for (id, old_entry) in old {
+        let new_entry = match new.get(id) {
+            Some(new_entry) => new_entry,
+            None => {
+                changes.push(SpecChange::Removed { section: section.to_string(), id: id.clone() });
+                continue;
+            }
+        };
+
+        let (old_fields, new_fields) = match (old_entry.as_object(), new_entry.as_object()) {
+            (Some(old_fields), Some(new_fields)) => (old_fields, new_fields),
+            _ => continue,
+        };
+        let fields: BTreeSet<&String> = old_fields.keys().chain(new_fields.keys()).collect();
+        for field in fields {
+            let (old_value, new_value) = (old_fields.get(field), new_fields.get(field));
+            if old_value != new_value {
+                changes.push(changed(section, Some(id), Some(field), old_value, new_value));
+            }
+        }
+    }
+
+    for id in new.keys() {
+        if !old.contains_key(id) {
+            changes.push(SpecChange::Added { section: section.to_string(), id: id.clone() });
+        }
+    }
+}
+
+// The index of each component and action property, by section and id
+fn indices(schema: &EcsSchema) -> Vec<(&'static str, &str, usize)> {
+    let components = schema.components.iter().map(|component| ("component", component.id.as_str(), component.index));
+    let action_properties = schema.action_properties.iter()
+        .map(|action_property| ("action_property", action_property.id.as_str(), action_property.index));
+
+    components.chain(action_properties).collect()
+}
+
+// Compares two specs which have been validated
+pub fn diff(old: &json::Object, new: &json::Object) -> Vec<SpecChange> {
+    let mut changes = Vec::new();
+
+    for section in &["component", "action_property"] {
+        diff_section(&mut changes, section,
+                     old.get(*section).and_then(Json::as_object),
+                     new.get(*section).and_then(Json::as_object));
+    }
+
+    for option in &["imports", "dynamic"] {
+        let (old_value, new_value) = (old.get(*option), new.get(*option));
+        if old_value != new_value {
+            changes.push(changed(option, None, None, old_value, new_value));
+        }
+    }
+
+    let (old_schema, new_schema) = (schema::schema(old), schema::schema(new));
+    let new_indices = indices(&new_schema);
+    for (section, id, old_index) in indices(&old_schema) {
+        let new_index = new_indices.iter().find(|&&(other_section, other, _)| other_section == section && other == id);
+        if let Some(&(_, _, new_index)) = new_index {
+            if new_index != old_index {
+                changes.push(SpecChange::Renumbered {
+                    section: section.to_string(),
+                    id: id.to_string(),
+                    old: old_index,
+                    new: new_index,
+                });
+            }
+        }
+    }
+
+    changes
+}
@@ -0,0 +1,164 @@
+use std::error::Error;
+use std::fmt;
+use std::io;
+use std::path::PathBuf;
+
+use handlebars::TemplateRenderError;
+
+use format::SpecFormat;
+
+/// A syntax error in a spec file, located by 1-based line and column.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParseError {
+    pub line: usize,
+    pub column: usize,
+    pub description: String,
+}
+
+impl ParseError {
+    pub fn from_offset(source: &str, offset: usize, description: &str) -> Self {
+        let mut line = 1;
+        let mut column = 1;
+        for (i, ch) in source.char_indices() {
+            if i >= offset {
+                break;
+            }
+            if ch == '\n' {
+                line += 1;
+                column = 1;
+            } else {
+                column += 1;
+            }
+        }
+
+        ParseError {
+            line,
+            column,
+            description: description.to_string(),
+        }
+    }
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "line {}, column {}: {}", self.line, self.column, self.description)
+    }
+}
+
+/// A well-formed spec file that doesn't describe a valid ecs. `section` is
+/// the top-level table ("component", "action_property", ...), `id` the entry
+/// within it and `field` the offending key of that entry, where applicable.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SchemaError {
+    pub section: String,
+    pub id: Option<String>,
+    pub field: Option<String>,
+    pub description: String,
+}
+
+impl SchemaError {
+    pub fn new(section: &str, id: Option<&str>, field: Option<&str>, description: &str) -> Self {
+        SchemaError {
+            section: section.to_string(),
+            id: id.map(|id| id.to_string()),
+            field: field.map(|field| field.to_string()),
+            description: description.to_string(),
+        }
+    }
+}
+
+impl fmt::Display for SchemaError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.section)?;
+        if let Some(ref id) = self.id {
+            write!(f, ".{}", id)?;
+        }
+        if let Some(ref field) = self.field {
+            write!(f, ".{}", field)?;
+        }
+        write!(f, ": {}", self.description)
+    }
+}
+
+#[derive(Debug)]
+pub enum GenerateError {
+    Io {
+        path: PathBuf,
+        error: io::Error,
+    },
+    Parse(SpecFormat, Vec<ParseError>),
+    Schema(Vec<SchemaError>),
+    TemplateRender(Box<TemplateRenderError>),
+    ModuleName(String),
+    ExtensionPoint(String),
+    /// An error in a spec file included by another
+    Include {
+        path: PathBuf,
+        error: Box<GenerateError>,
+    },
+    /// Spec files which include each other, starting and ending with the
+    /// same file
+    IncludeCycle(Vec<PathBuf>),
+    /// Components or action properties whose indices differ from those
+    /// recorded in a lock file
+    Lock {
+        path: PathBuf,
+        errors: Vec<SchemaError>,
+    },
+}
+
+impl fmt::Display for GenerateError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            GenerateError::Io { ref path, ref error } => {
+                write!(f, "{}: {}", path.display(), error)
+            }
+            GenerateError::Parse(format, ref errors) => {
+                write!(f, "invalid {}", format)?;
+                for error in errors {
+                    write!(f, "\n  {}", error)?;
+                }
+                Ok(())
+            }
+            GenerateError::Schema(ref errors) => {
+                write!(f, "invalid schema")?;
+                for error in errors {
+                    write!(f, "\n  {}", error)?;
+                }
+                Ok(())
+            }
+            GenerateError::TemplateRender(ref error) => write!(f, "failed to render template: {}", error),
+            GenerateError::ModuleName(ref name) => write!(f, "invalid module name: {}", name),
+            GenerateError::ExtensionPoint(ref point) => write!(f, "unknown extension point: {}", point),
+            GenerateError::Include { ref path, ref error } => write!(f, "in {}: {}", path.display(), error),
+            GenerateError::IncludeCycle(ref paths) => {
+                let paths: Vec<String> = paths.iter().map(|path| path.display().to_string()).collect();
+                write!(f, "include cycle: {}", paths.join(" -> "))
+            }
+            GenerateError::Lock { ref path, ref errors } => {
+                write!(f, "indices don't match the lock file {}", path.display())?;
+                for error in errors {
+                    write!(f, "\n  {}", error)?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+impl Error for GenerateError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match *self {
+            GenerateError::Io { ref error, .. } => Some(error),
+            GenerateError::TemplateRender(ref error) => Some(error),
+            GenerateError::Include { ref error, .. } => Some(&**error),
+            _ => None,
+        }
+    }
+}
+
+impl From<TemplateRenderError> for GenerateError {
+    fn from(error: TemplateRenderError) -> Self {
+        GenerateError::TemplateRender(Box::new(error))
+    }
+}
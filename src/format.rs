@@ -0,0 +1,153 @@
+use std::fmt;
+use std::path::Path;
+
+use tomson::Toml;
+use rustc_serialize::json::{self, Json};
+use yaml_rust::{Yaml, YamlLoader};
+use ron::{self, Value};
+
+use error::{GenerateError, ParseError};
+
+/// The formats spec files can be written in. Each describes the same
+/// structure, which is converted to JSON before being validated.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SpecFormat {
+    Toml,
+    Json,
+    Yaml,
+    Ron,
+}
+
+impl SpecFormat {
+    /// The format of a spec file with the given path, according to its
+    /// extension, if it's one of toml, json, yaml, yml or ron
+    pub fn from_path<P: AsRef<Path>>(path: P) -> Option<SpecFormat> {
+        match path.as_ref().extension().and_then(|extension| extension.to_str()) {
+            Some("toml") => Some(SpecFormat::Toml),
+            Some("json") => Some(SpecFormat::Json),
+            Some("yaml") | Some("yml") => Some(SpecFormat::Yaml),
+            Some("ron") => Some(SpecFormat::Ron),
+            _ => None,
+        }
+    }
+
+    /// The format with the given name, as printed by `Display`
+    pub fn from_name(name: &str) -> Option<SpecFormat> {
+        match name {
+            "toml" => Some(SpecFormat::Toml),
+            "json" => Some(SpecFormat::Json),
+            "yaml" => Some(SpecFormat::Yaml),
+            "ron" => Some(SpecFormat::Ron),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for SpecFormat {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match *self {
+            SpecFormat::Toml => "toml",
+            SpecFormat::Json => "json",
+            SpecFormat::Yaml => "yaml",
+            SpecFormat::Ron => "ron",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+fn syntax_error(format: SpecFormat, line: usize, column: usize, description: &str) -> GenerateError {
+    GenerateError::Parse(format, vec![ParseError {
+        line,
+        column,
+        description: description.to_string(),
+    }])
+}
+
+fn from_yaml(yaml: Yaml) -> Result<Json, String> {
+    Ok(match yaml {
+        Yaml::String(string) => Json::String(string),
+        Yaml::Boolean(boolean) => Json::Boolean(boolean),
+        Yaml::Integer(integer) => Json::I64(integer),
+        Yaml::Real(real) => Json::F64(real.parse().map_err(|_| format!("invalid number: {}", real))?),
+        Yaml::Null => Json::Null,
+        Yaml::Array(array) => Json::Array(array.into_iter().map(from_yaml).collect::<Result<_, _>>()?),
+        Yaml::Hash(hash) => {
+            let mut object = json::Object::new();
+            for (key, value) in hash {
+                match key {
+                    Yaml::String(key) => object.insert(key, from_yaml(value)?),
+                    other => return Err(format!("keys must be strings, not {:?}", other)),
+                };
+            }
+            Json::Object(object)
+        }
+        Yaml::Alias(_) | Yaml::BadValue => return Err("unsupported yaml value".to_string()),
+    })
+}
+
+fn from_ron(value: Value) -> Result<Json, String> {
+    Ok(match value {
+        Value::String(string) => Json::String(string),
+        Value::Char(ch) => Json::String(ch.to_string()),
+        Value::Bool(boolean) => Json::Boolean(boolean),
+        Value::Number(ron::Number::Integer(integer)) => Json::I64(integer),
+        Value::Number(ron::Number::Float(float)) => Json::F64(float.get()),
+        Value::Option(None) => Json::Null,
+        Value::Option(Some(value)) => from_ron(*value)?,
+        Value::Seq(values) => Json::Array(values.into_iter().map(from_ron).collect::<Result<_, _>>()?),
+        // a struct without fields, such as a flag component written as ()
+        Value::Unit => Json::Object(json::Object::new()),
+        Value::Map(map) => {
+            let mut object = json::Object::new();
+            for (key, value) in map {
+                match key {
+                    Value::String(key) => object.insert(key, from_ron(value)?),
+                    other => return Err(format!("keys must be strings, not {:?}", other)),
+                };
+            }
+            Json::Object(object)
+        }
+    })
+}
+
+// Converts a spec from any format into the JSON that's validated and used
+// to generate code
+pub fn parse(format: SpecFormat, mut source: String) -> Result<json::Object, GenerateError> {
+    let json = match format {
+        SpecFormat::Toml => Toml::as_json(&mut source).map_err(|errors| {
+            GenerateError::Parse(format, errors.iter().map(|e| ParseError::from_offset(&source, e.lo, &e.desc)).collect())
+        })?,
+        SpecFormat::Json => Json::from_str(&source).map_err(|e| match e {
+            // rustc-serialize counts a newline as the first column of the
+            // line after it
+            json::ParserError::SyntaxError(code, line, column) => {
+                let column = if line > 1 { ::std::cmp::max(column - 1, 1) } else { column };
+                syntax_error(format, line, column, json::error_str(code))
+            }
+            json::ParserError::IoError(e) => syntax_error(format, 1, 1, &e.to_string()),
+        })?,
+        SpecFormat::Yaml => {
+            let mut documents = YamlLoader::load_from_str(&source).map_err(|e| {
+                #[allow(deprecated)]
+                let description = ::std::error::Error::description(&e).to_string();
+                syntax_error(format, e.marker().line(), e.marker().col() + 1, &description)
+            })?;
+            if documents.is_empty() {
+                Json::Object(json::Object::new())
+            } else {
+                from_yaml(documents.swap_remove(0)).map_err(|description| syntax_error(format, 1, 1, &description))?
+            }
+        }
+        SpecFormat::Ron => {
+            let value = ron::from_str(&source).map_err(|e: ron::error::SpannedError| {
+                syntax_error(format, e.position.line, e.position.col, &e.code.to_string())
+            })?;
+            from_ron(value).map_err(|description| syntax_error(format, 1, 1, &description))?
+        }
+    };
+
+    match json {
+        Json::Object(object) => Ok(object),
+        _ => Err(syntax_error(format, 1, 1, "the spec must be a table of sections")),
+    }
+}
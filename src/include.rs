@@ -0,0 +1,194 @@
+use std::collections::BTreeMap;
+use std::collections::btree_map::Entry;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use rustc_serialize::json::{self, Json};
+
+use error::{GenerateError, SchemaError};
+use format::{self, SpecFormat};
+
+const ENTRY_SECTIONS: &[&str] = &["component", "action_property"];
+
+// Spec files merged into one spec, along with the files they include.
+// Components and action properties are combined, as are imports, and the ecs
+// is dynamic if any of the files say so.
+pub struct Specs {
+    spec: json::Object,
+    // every file read, for telling cargo when to rerun build scripts
+    files: Vec<PathBuf>,
+    canonical_files: Vec<PathBuf>,
+    // the canonical and given paths of the files being read, each included by
+    // the one before it
+    including: Vec<(PathBuf, PathBuf)>,
+    // the file defining each component and action property
+    origins: BTreeMap<(String, String), String>,
+    duplicates: Vec<SchemaError>,
+}
+
+fn include_paths(include: Option<Json>) -> Result<Vec<String>, GenerateError> {
+    let error = || GenerateError::Schema(vec![SchemaError::new("include", None, None, "expected an array of paths")]);
+    match include {
+        Some(Json::Array(paths)) => paths.into_iter().map(|path| match path {
+            Json::String(path) => Ok(path),
+            _ => Err(error()),
+        }).collect(),
+        Some(_) => Err(error()),
+        None => Ok(Vec::new()),
+    }
+}
+
+// Names the included file an error is in, unless the error already does
+pub fn in_include(path: &Path, error: GenerateError) -> GenerateError {
+    let named = match error {
+        GenerateError::Io { path: ref error_path, .. } => error_path == path,
+        GenerateError::IncludeCycle(_) => true,
+        _ => false,
+    };
+
+    if named {
+        error
+    } else {
+        GenerateError::Include { path: path.to_path_buf(), error: Box::new(error) }
+    }
+}
+
+// Whether a section has the type which can be merged with the same section
+// of another file
+fn mergeable(section: &str, value: &Json) -> bool {
+    match section {
+        "component" | "action_property" => value.is_object(),
+        "imports" => value.is_array(),
+        "dynamic" => value.is_boolean(),
+        _ => false,
+    }
+}
+
+impl Specs {
+    pub fn new() -> Self {
+        Specs {
+            spec: json::Object::new(),
+            files: Vec::new(),
+            canonical_files: Vec::new(),
+            including: Vec::new(),
+            origins: BTreeMap::new(),
+            duplicates: Vec::new(),
+        }
+    }
+
+    // Merges the spec file at `path`, which is read as `format`
+    pub fn read(&mut self, path: &Path, format: SpecFormat) -> Result<(), GenerateError> {
+        let canonical = fs::canonicalize(path).map_err(|error| GenerateError::Io { path: path.to_path_buf(), error })?;
+        if let Some(start) = self.including.iter().position(|(other, _)| *other == canonical) {
+            let mut cycle: Vec<PathBuf> = self.including[start..].iter().map(|(_, path)| path.clone()).collect();
+            cycle.push(path.to_path_buf());
+            return Err(GenerateError::IncludeCycle(cycle));
+        }
+
+        // a file included by several others is only merged once
+        if self.canonical_files.contains(&canonical) {
+            return Ok(());
+        }
+
+        let source = ::read_file_to_string(path)?;
+        self.including.push((canonical.clone(), path.to_path_buf()));
+        let result = self.add(source, format, Some(path));
+        self.including.pop();
+        result?;
+
+        self.files.push(path.to_path_buf());
+        self.canonical_files.push(canonical);
+
+        Ok(())
+    }
+
+    // Merges the contents of a spec file, after the files it includes. Paths
+    // are relative to the directory of the including file, or to the current
+    // directory for specs which aren't read from a file.
+    pub fn add(&mut self, source: String, format: SpecFormat, path: Option<&Path>) -> Result<(), GenerateError> {
+        let mut spec = format::parse(format, source)?;
+
+        let dir = path.and_then(Path::parent).unwrap_or_else(|| Path::new(""));
+        for include in include_paths(spec.remove("include"))? {
+            let include = dir.join(include);
+            let format = SpecFormat::from_path(&include).unwrap_or(SpecFormat::Toml);
+            self.read(&include, format).map_err(|error| in_include(&include, error))?;
+        }
+
+        let origin = path.map_or_else(|| "<spec>".to_string(), |path| path.display().to_string());
+        self.merge(spec, &origin);
+
+        Ok(())
+    }
+
+    fn merge(&mut self, spec: json::Object, origin: &str) {
+        for (section, value) in spec {
+            let merged = match self.spec.remove(&section) {
+                None => value,
+                Some(existing) => {
+                    if !mergeable(&section, &existing) {
+                        // leave values which can't be merged for validation
+                        // to report
+                        existing
+                    } else if !mergeable(&section, &value) {
+                        value
+                    } else {
+                        self.merge_section(&section, existing, value, origin)
+                    }
+                }
+            };
+            self.spec.insert(section, merged);
+        }
+
+        // note where entries are first defined, including those from the
+        // first file to define their section
+        for &section in ENTRY_SECTIONS {
+            if let Some(entries) = self.spec.get(section).and_then(Json::as_object) {
+                for id in entries.keys() {
+                    self.origins.entry((section.to_string(), id.clone())).or_insert_with(|| origin.to_string());
+                }
+            }
+        }
+    }
+
+    fn merge_section(&mut self, section: &str, existing: Json, value: Json, origin: &str) -> Json {
+        match (existing, value) {
+            (Json::Object(mut entries), Json::Object(new_entries)) => {
+                for (id, entry) in new_entries {
+                    match entries.entry(id) {
+                        Entry::Occupied(occupied) => {
+                            let id = occupied.key();
+                            let first = &self.origins[&(section.to_string(), id.clone())];
+                            self.duplicates.push(SchemaError::new(section, Some(id), None,
+                                                                  &format!("defined in both {} and {}", first, origin)));
+                        }
+                        Entry::Vacant(vacant) => {
+                            vacant.insert(entry);
+                        }
+                    }
+                }
+                Json::Object(entries)
+            }
+            (Json::Array(mut imports), Json::Array(new_imports)) => {
+                for import in new_imports {
+                    if !imports.contains(&import) {
+                        imports.push(import);
+                    }
+                }
+                Json::Array(imports)
+            }
+            (Json::Boolean(existing), Json::Boolean(value)) => Json::Boolean(existing || value),
+            _ => unreachable!(),
+        }
+    }
+
+    // The merged spec, which is validated, and the files it was read from
+    pub fn finish(self) -> Result<(json::Object, Vec<PathBuf>), GenerateError> {
+        if !self.duplicates.is_empty() {
+            return Err(GenerateError::Schema(self.duplicates));
+        }
+        ::validate::validate(&self.spec).map_err(GenerateError::Schema)?;
+
+        Ok((self.spec, self.files))
+    }
+}
@@ -1,20 +1,34 @@
 extern crate tomson;
 extern crate handlebars;
 extern crate rustc_serialize;
-
-use std::collections::HashMap;
-use std::io::Read;
+extern crate yaml_rust;
+extern crate ron;
+
+mod error;
+mod validate;
+mod diff;
+mod schema;
+mod format;
+mod include;
+mod lock;
+
+use std::io::{self, Read};
 use std::io::Write;
-use std::path::Path;
-use std::fs::File;
-use std::mem;
+use std::path::{Path, PathBuf};
+use std::fs::{self, File};
+
+use handlebars::{Handlebars, TemplateRenderError};
+use rustc_serialize::json::{self, Json};
+
+pub use error::{GenerateError, ParseError, SchemaError};
+pub use diff::SpecChange;
+pub use format::SpecFormat;
+pub use schema::{EcsSchema, ComponentSchema, ActionPropertySchema, Container, Storage};
 
-use tomson::Toml;
-use handlebars::Handlebars;
-use rustc_serialize::json::Json;
+use include::Specs;
 
 
-const TEMPLATE: &'static str = r#"// Automatically generated. Do not edit.
+const TEMPLATE: &str = r#"// Automatically generated. Do not edit.
 #![allow(unused_imports)]
 
 use std::collections::{BTreeMap, btree_map, BTreeSet, btree_set, HashMap, hash_map, HashSet, hash_set};
@@ -23,16 +37,354 @@ use std::slice;
 use std::usize;
 use std::vec;
 use std::mem;
+use std::iter::FromIterator;
+use std::ops::Bound;
+use std::sync::OnceLock;
 
 {{#each imports}}
 use {{ this }};
 {{/each}}
 
+{{!-- module: types --}}
 pub type EntityId = u64;
 
+// An EntityId packs the index of an entity into its low 32 bits, and the
+// generation of that index into its high 32 bits. Indices are recycled once
+// their entity is despawned, and the generation distinguishes the entities
+// which have occupied the same index over time.
+pub mod entity_id {
+    use super::EntityId;
+
+    pub fn new(index: u32, generation: u32) -> EntityId {
+        ((generation as EntityId) << 32) | index as EntityId
+    }
+
+    pub fn index(id: EntityId) -> u32 {
+        id as u32
+    }
+
+    pub fn generation(id: EntityId) -> u32 {
+        (id >> 32) as u32
+    }
+
+    // Ids handed out by EcsAction::reserve_entity use this generation, which
+    // is never given to allocated entities. The index is the position of the
+    // reservation within its action.
+    pub const RESERVED_GENERATION: u32 = ::std::u32::MAX;
+
+    pub fn is_reserved(id: EntityId) -> bool {
+        generation(id) == RESERVED_GENERATION
+    }
+
+    pub fn next_generation(generation: u32) -> u32 {
+        let next = generation.wrapping_add(1);
+        if next == RESERVED_GENERATION {
+            0
+        } else {
+            next
+        }
+    }
+}
+
+{{!-- module: action --}}
+{{internal}}fn resolve_entity(reserved: &[EntityId], id: EntityId) -> EntityId {
+    if entity_id::is_reserved(id) {
+        *reserved.get(entity_id::index(id) as usize).expect("Entity reserved by a different action")
+    } else {
+        id
+    }
+}
+
+// Maps an id reserved by an action onto the id reserved for the same entity
+// once that action is merged into an action with `offset` reservations
+{{internal}}fn offset_reserved(id: EntityId, offset: u32) -> EntityId {
+    if entity_id::is_reserved(id) {
+        entity_id::new(entity_id::index(id) + offset, entity_id::RESERVED_GENERATION)
+    } else {
+        id
+    }
+}
+
+// Follows the values of a component through the changes made by an action,
+// to work out how to put each value back where it was before the action.
+// Slots are keyed by the entity holding a value, and contain the entity
+// which held that value before the action, or None for inserted values.
+{{internal}}struct ValueTracker {
+    {{internal}}touched: BTreeSet<EntityId>,
+    {{internal}}slots: BTreeMap<EntityId, Option<EntityId>>,
+    {{internal}}discarded: BTreeSet<EntityId>,
+}
+
+// A single change recorded by an action. An inserted value is found at its
+// entity in the profile's insertions, or at the given index of its
+// superseded values if another value was inserted at the entity afterwards.
+#[derive(Clone, Copy, Serialize, Deserialize)]
+{{internal}}enum Operation {
+    Insert(EntityId, Option<usize>),
+    Remove(EntityId),
+    Swap(EntityId, EntityId),
+    Move(EntityId, EntityId),
+}
+
+// Where the component an entity has after a sequence of operations came from
+{{internal}}enum Outcome {
+    // the component the given entity had before the operations, if any
+    Unchanged(EntityId),
+    Inserted(EntityId, Option<usize>),
+    Removed,
+}
+
+impl Outcome {
+    {{internal}}fn is_present<F: Fn(EntityId) -> bool>(&self, has_previous: &F) -> bool {
+        match *self {
+            Outcome::Unchanged(entity) => has_previous(entity),
+            Outcome::Inserted(..) => true,
+            Outcome::Removed => false,
+        }
+    }
+}
+
+// Works backwards from the end of a sequence of operations to find where
+// the component an entity ends up with came from. Applying the operations
+// in order is exactly what commit does, so this predicts its outcome.
+{{internal}}fn trace_operations<F: Fn(EntityId) -> bool>(operations: &[Operation], entity: EntityId, has_previous: &F) -> Outcome {
+    let mut entity = entity;
+    for (index, operation) in operations.iter().enumerate().rev() {
+        match *operation {
+            Operation::Insert(id, superseded) if id == entity => return Outcome::Inserted(id, superseded),
+            Operation::Remove(id) if id == entity => return Outcome::Removed,
+            Operation::Swap(a, b) if a == entity => entity = b,
+            Operation::Swap(a, b) if b == entity => entity = a,
+            Operation::Move(src, dst) if src != dst && src == entity => return Outcome::Removed,
+            Operation::Move(src, dst) if src != dst && dst == entity => {
+                // a move from an entity without the component does nothing
+                let moved = trace_operations(&operations[..index], src, has_previous);
+                if moved.is_present(has_previous) {
+                    return moved;
+                }
+            }
+            _ => {}
+        }
+    }
+
+    Outcome::Unchanged(entity)
+}
+
+{{internal}}struct RestorePlan {
+    // values to insert (or remove) at a position, which the swaps will then
+    // move to the entity which originally held them
+    {{internal}}restorations: Vec<(EntityId, EntityId)>,
+    {{internal}}swaps: Vec<(EntityId, EntityId)>,
+}
+
+impl ValueTracker {
+    {{internal}}fn new() -> Self {
+        ValueTracker {
+            touched: BTreeSet::new(),
+            slots: BTreeMap::new(),
+            discarded: BTreeSet::new(),
+        }
+    }
+
+    {{internal}}fn touch(&mut self, entity: EntityId, present: bool) {
+        self.touched.insert(entity);
+        if present {
+            self.slots.insert(entity, Some(entity));
+        }
+    }
+
+    // Notes that the value in a slot is discarded, returning the entity which
+    // held it before the action, if any
+    {{internal}}fn discard(&mut self, slot: Option<Option<EntityId>>) -> Option<EntityId> {
+        let owner = slot.and_then(|owner| owner);
+        if let Some(owner) = owner {
+            self.discarded.insert(owner);
+        }
+        owner
+    }
+
+    {{internal}}fn insert(&mut self, entity: EntityId) -> Option<EntityId> {
+        let slot = self.slots.insert(entity, None);
+        self.discard(slot)
+    }
+
+    {{internal}}fn remove(&mut self, entity: EntityId) -> Option<EntityId> {
+        let slot = self.slots.remove(&entity);
+        self.discard(slot)
+    }
+
+    {{internal}}fn swap(&mut self, a: EntityId, b: EntityId) {
+        let a_slot = self.slots.remove(&a);
+        let b_slot = self.slots.remove(&b);
+        if let Some(a_slot) = a_slot {
+            self.slots.insert(b, a_slot);
+        }
+        if let Some(b_slot) = b_slot {
+            self.slots.insert(a, b_slot);
+        }
+    }
+
+    {{internal}}fn mv(&mut self, source: EntityId, destination: EntityId) -> Option<EntityId> {
+        match self.slots.remove(&source) {
+            Some(source_slot) => {
+                let slot = self.slots.insert(destination, source_slot);
+                self.discard(slot)
+            }
+            None => None,
+        }
+    }
+
+    {{internal}}fn restore_plan(&self) -> RestorePlan {
+        // where the value at each touched entity belongs
+        let mut destinations = BTreeMap::new();
+        let mut owners = self.touched.clone();
+        for (&position, slot) in self.slots.iter() {
+            if let Some(owner) = *slot {
+                destinations.insert(position, owner);
+                owners.remove(&owner);
+            }
+        }
+
+        // the remaining positions hold values which don't need restoring,
+        // so can be overwritten with the values the action discarded
+        let free: Vec<EntityId> = self.touched.iter()
+            .filter(|position| !destinations.contains_key(position))
+            .cloned()
+            .collect();
+        let mut restorations = Vec::new();
+        for (position, owner) in free.into_iter().zip(owners.into_iter()) {
+            destinations.insert(position, owner);
+            restorations.push((position, owner));
+        }
+
+        // destinations is now a permutation of the touched entities, made
+        // from swaps along each of its cycles
+        let mut swaps = Vec::new();
+        let mut visited = BTreeSet::new();
+        for &start in self.touched.iter() {
+            if !visited.insert(start) {
+                continue;
+            }
+            let mut next = destinations[&start];
+            while next != start {
+                swaps.push((start, next));
+                visited.insert(next);
+                next = destinations[&next];
+            }
+        }
+
+        RestorePlan {
+            restorations: restorations,
+            swaps: swaps,
+        }
+    }
+}
+
+// The values of a component an action discards, kept by the entity which
+// held them before the action. The tracker follows the action's operations
+// as they are committed, so that each value can be kept as commit drops it.
+{{internal}}struct DiscardedValues<T> {
+    {{internal}}tracker: ValueTracker,
+    {{internal}}values: BTreeMap<EntityId, T>,
+}
+
+impl<T> DiscardedValues<T> {
+    {{internal}}fn new(tracker: ValueTracker) -> Self {
+        DiscardedValues {
+            tracker: tracker,
+            values: BTreeMap::new(),
+        }
+    }
+
+    {{internal}}fn keep(&mut self, owner: Option<EntityId>, value: T) {
+        if let Some(owner) = owner {
+            self.values.insert(owner, value);
+        }
+    }
+}
+
+{{!-- module: types --}}
+#[derive(Serialize, Deserialize)]
+{{internal}}struct EntitySlot {
+    {{internal}}generation: u32,
+    {{internal}}alive: bool,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct EntityIdAllocator {
+    {{internal}}slots: Vec<EntitySlot>,
+    {{internal}}free: Vec<u32>,
+}
+
+impl Default for EntityIdAllocator {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl EntityIdAllocator {
+    pub fn new() -> Self {
+        EntityIdAllocator {
+            slots: Vec::new(),
+            free: Vec::new(),
+        }
+    }
+
+    pub fn allocate(&mut self) -> EntityId {
+        if let Some(index) = self.free.pop() {
+            let slot = &mut self.slots[index as usize];
+            slot.alive = true;
+            return entity_id::new(index, slot.generation);
+        }
+
+        let index = self.slots.len();
+        assert!(index <= u32::max_value() as usize, "Too many entities");
+        self.slots.push(EntitySlot {
+            generation: 0,
+            alive: true,
+        });
+
+        entity_id::new(index as u32, 0)
+    }
+
+    pub fn free(&mut self, id: EntityId) -> bool {
+        if !self.is_alive(id) {
+            return false;
+        }
+
+        let index = entity_id::index(id);
+        let slot = &mut self.slots[index as usize];
+        slot.alive = false;
+        // the next entity to occupy this index gets a fresh generation,
+        // so handles to the freed entity can never refer to it
+        slot.generation = entity_id::next_generation(slot.generation);
+        self.free.push(index);
+
+        true
+    }
+
+    pub fn is_alive(&self, id: EntityId) -> bool {
+        self.slots.get(entity_id::index(id) as usize).map(|slot| {
+            slot.alive && slot.generation == entity_id::generation(id)
+        }).unwrap_or(false)
+    }
+
+    pub fn clear(&mut self) {
+        // free rather than forget existing entities, so that stale ids remain stale
+        for (index, slot) in self.slots.iter_mut().enumerate() {
+            if slot.alive {
+                slot.alive = false;
+                slot.generation = entity_id::next_generation(slot.generation);
+                self.free.push(index as u32);
+            }
+        }
+    }
+}
+
+{{!-- module: storage --}}
 #[derive(Serialize, Deserialize)]
 pub struct EntityMap<T> {
-    inner: BTreeMap<EntityId, T>,
+    {{internal}}inner: BTreeMap<EntityId, T>,
 }
 
 impl<T> EntityMap<T> {
@@ -81,6 +433,11 @@ impl<T> EntityMap<T> {
     pub fn keys(&self) -> EntityMapKeys<T> {
         EntityMapKeys::new(self.inner.keys())
     }
+
+    // returns the first entity not less than the given entity
+    pub fn seek(&self, entity: EntityId) -> Option<EntityId> {
+        self.inner.range(entity..).next().map(|(id_ref, _)| *id_ref)
+    }
 }
 
 impl<T: Copy> EntityMap<T> {
@@ -90,11 +447,11 @@ impl<T: Copy> EntityMap<T> {
 }
 
 pub struct EntityMapKeys<'a, T: 'a> {
-    keys: btree_map::Keys<'a, EntityId, T>,
+    {{internal}}keys: btree_map::Keys<'a, EntityId, T>,
 }
 
 impl<'a, T: 'a> EntityMapKeys<'a, T> {
-    fn new(keys: btree_map::Keys<'a, EntityId, T>) -> Self {
+    {{internal}}fn new(keys: btree_map::Keys<'a, EntityId, T>) -> Self {
         EntityMapKeys {
             keys: keys,
         }
@@ -109,11 +466,11 @@ impl<'a, T: 'a> Iterator for EntityMapKeys<'a, T> {
 }
 
 pub struct EntityMapIter<'a, T: 'a> {
-    iter: btree_map::Iter<'a, EntityId, T>,
+    {{internal}}iter: btree_map::Iter<'a, EntityId, T>,
 }
 
 impl<'a, T: 'a> EntityMapIter<'a, T> {
-    fn new(iter: btree_map::Iter<'a, EntityId, T>) -> Self {
+    {{internal}}fn new(iter: btree_map::Iter<'a, EntityId, T>) -> Self {
         EntityMapIter {
             iter: iter,
         }
@@ -128,11 +485,11 @@ impl<'a, T: 'a> Iterator for EntityMapIter<'a, T> {
 }
 
 pub struct EntityMapCopyIter<'a, T: 'a + Copy> {
-    iter: btree_map::Iter<'a, EntityId, T>,
+    {{internal}}iter: btree_map::Iter<'a, EntityId, T>,
 }
 
 impl<'a, T: 'a + Copy> EntityMapCopyIter<'a, T> {
-    fn new(iter: btree_map::Iter<'a, EntityId, T>) -> Self {
+    {{internal}}fn new(iter: btree_map::Iter<'a, EntityId, T>) -> Self {
         EntityMapCopyIter {
             iter: iter,
         }
@@ -148,7 +505,7 @@ impl<'a, T: 'a + Copy> Iterator for EntityMapCopyIter<'a, T> {
 
 #[derive(Serialize, Deserialize)]
 pub struct EntitySet {
-    inner: BTreeSet<EntityId>,
+    {{internal}}inner: BTreeSet<EntityId>,
 }
 
 impl EntitySet {
@@ -185,14 +542,19 @@ impl EntitySet {
     pub fn is_empty(&self) -> bool {
         self.inner.is_empty()
     }
+
+    // returns the first entity not less than the given entity
+    pub fn seek(&self, entity: EntityId) -> Option<EntityId> {
+        self.inner.range(entity..).next().map(|id_ref| *id_ref)
+    }
 }
 
 pub struct EntitySetIter<'a> {
-    iter: btree_set::Iter<'a, EntityId>,
+    {{internal}}iter: btree_set::Iter<'a, EntityId>,
 }
 
 impl<'a> EntitySetIter<'a> {
-    fn new(iter: btree_set::Iter<'a, EntityId>) -> Self {
+    {{internal}}fn new(iter: btree_set::Iter<'a, EntityId>) -> Self {
         EntitySetIter {
             iter: iter,
         }
@@ -206,8 +568,9 @@ impl<'a> Iterator for EntitySetIter<'a> {
     }
 }
 
+#[derive(Serialize, Deserialize)]
 pub struct EntityHashMap<T> {
-    inner: HashMap<EntityId, T>,
+    {{internal}}inner: HashMap<EntityId, T>,
 }
 
 impl<T> EntityHashMap<T> {
@@ -269,11 +632,11 @@ impl<T: Copy> EntityHashMap<T> {
 }
 
 pub struct EntityHashMapKeys<'a, T: 'a> {
-    keys: hash_map::Keys<'a, EntityId, T>,
+    {{internal}}keys: hash_map::Keys<'a, EntityId, T>,
 }
 
 impl<'a, T: 'a> EntityHashMapKeys<'a, T> {
-    fn new(keys: hash_map::Keys<'a, EntityId, T>) -> Self {
+    {{internal}}fn new(keys: hash_map::Keys<'a, EntityId, T>) -> Self {
         EntityHashMapKeys {
             keys: keys,
         }
@@ -288,11 +651,11 @@ impl<'a, T: 'a> Iterator for EntityHashMapKeys<'a, T> {
 }
 
 pub struct EntityHashMapCopyIter<'a, T: 'a + Copy> {
-    iter: hash_map::Iter<'a, EntityId, T>,
+    {{internal}}iter: hash_map::Iter<'a, EntityId, T>,
 }
 
 impl<'a, T: 'a + Copy> EntityHashMapCopyIter<'a, T> {
-    fn new(iter: hash_map::Iter<'a, EntityId, T>) -> Self {
+    {{internal}}fn new(iter: hash_map::Iter<'a, EntityId, T>) -> Self {
         EntityHashMapCopyIter {
             iter: iter,
         }
@@ -307,11 +670,11 @@ impl<'a, T: 'a + Copy> Iterator for EntityHashMapCopyIter<'a, T> {
 }
 
 pub struct EntityHashMapIter<'a, T: 'a> {
-    iter: hash_map::Iter<'a, EntityId, T>,
+    {{internal}}iter: hash_map::Iter<'a, EntityId, T>,
 }
 
 impl<'a, T: 'a> EntityHashMapIter<'a, T> {
-    fn new(iter: hash_map::Iter<'a, EntityId, T>) -> Self {
+    {{internal}}fn new(iter: hash_map::Iter<'a, EntityId, T>) -> Self {
         EntityHashMapIter {
             iter: iter,
         }
@@ -325,8 +688,9 @@ impl<'a, T: 'a> Iterator for EntityHashMapIter<'a, T> {
     }
 }
 
+#[derive(Serialize, Deserialize)]
 pub struct EntityHashSet {
-    inner: HashSet<EntityId>,
+    {{internal}}inner: HashSet<EntityId>,
 }
 
 impl EntityHashSet {
@@ -370,11 +734,11 @@ impl EntityHashSet {
 }
 
 pub struct EntityHashSetIter<'a> {
-    iter: hash_set::Iter<'a, EntityId>,
+    {{internal}}iter: hash_set::Iter<'a, EntityId>,
 }
 
 impl<'a> EntityHashSetIter<'a> {
-    fn new(iter: hash_set::Iter<'a, EntityId>) -> Self {
+    {{internal}}fn new(iter: hash_set::Iter<'a, EntityId>) -> Self {
         EntityHashSetIter {
             iter: iter,
         }
@@ -388,2118 +752,4365 @@ impl<'a> Iterator for EntityHashSetIter<'a> {
     }
 }
 
-pub const NUM_COMPONENTS: usize = {{num_components}};
-
-const WORD_SIZE: usize = {{word_size}};
-const WORD_BITS: usize = {{word_bits}};
-
-const COMPONENT_TYPE_SET_NUM_WORDS: usize = {{component_set_num_words}};
-
-pub type ComponentType = usize;
-
-pub mod component_type {
-    use std::usize;
-
-{{#each component}}
-    pub const {{id_uppercase}}: usize = {{index}};
-{{/each}}
-    pub const INVALID_COMPONENT: usize = usize::MAX;
-}
-
-#[derive(Serialize, Deserialize)]
-pub struct ComponentTypeSet {
-    bitfields: [usize; COMPONENT_TYPE_SET_NUM_WORDS],
+// The number of slots the dense and sparse collections holding `len`
+// entities may grow to. Entities with higher indices are kept in an overflow
+// map instead, so that a single large id doesn't allocate a huge vector.
+{{internal}}fn slot_capacity(len: usize) -> usize {
+    ::std::cmp::max(1024, len * 2)
 }
 
-pub struct ComponentTypeSetIter {
-    bitfields: [usize; COMPONENT_TYPE_SET_NUM_WORDS],
-    index: usize,
+// Storage for a component in a vector indexed by entity index. Each slot
+// holds one generation of its index, and other generations of an occupied
+// index, like stale ids, are kept in an overflow map along with indices
+// beyond the slot capacity.
+pub struct EntityDenseMap<T> {
+    {{internal}}inner: Vec<Option<(EntityId, T)>>,
+    {{internal}}overflow: BTreeMap<EntityId, T>,
+    {{internal}}len: usize,
 }
 
-impl ComponentTypeSetIter {
-    fn new(bitfields: [usize; COMPONENT_TYPE_SET_NUM_WORDS]) -> Self {
-        ComponentTypeSetIter {
-            bitfields: bitfields,
-            index: 0,
+impl<T> EntityDenseMap<T> {
+    pub fn new() -> Self {
+        EntityDenseMap {
+            inner: Vec::new(),
+            overflow: BTreeMap::new(),
+            len: 0,
         }
     }
-}
 
-impl Iterator for ComponentTypeSetIter {
-    type Item = ComponentType;
-    fn next(&mut self) -> Option<Self::Item> {
-        while self.index < COMPONENT_TYPE_SET_NUM_WORDS && self.bitfields[self.index] == 0 {
-            self.index += 1;
+    pub fn insert(&mut self, entity: EntityId, value: T) {
+        if let Some(existing) = self.overflow.get_mut(&entity) {
+            *existing = value;
+            return;
         }
-        if self.index == COMPONENT_TYPE_SET_NUM_WORDS {
-            return None;
+
+        let index = entity_id::index(entity) as usize;
+        if index >= self.inner.len() && index < slot_capacity(self.len()) {
+            self.inner.resize_with(index + 1, || None);
         }
 
-        let trailing = self.bitfields[self.index].trailing_zeros() as usize;
-        self.bitfields[self.index] &= !(1 << trailing);
-        Some(self.index * WORD_BITS + trailing)
+        match self.inner.get_mut(index) {
+            Some(slot) if slot.as_ref().map_or(true, |&(id, _)| id == entity) => {
+                if slot.is_none() {
+                    self.len += 1;
+                }
+                *slot = Some((entity, value));
+            }
+            _ => {
+                self.overflow.insert(entity, value);
+            }
+        }
     }
-}
 
-impl ComponentTypeSet {
-    pub fn new() -> Self {
-        ComponentTypeSet {
-            bitfields: [0; COMPONENT_TYPE_SET_NUM_WORDS],
+    pub fn get(&self, entity: EntityId) -> Option<&T> {
+        match self.inner.get(entity_id::index(entity) as usize) {
+            Some(&Some((id, ref value))) if id == entity => Some(value),
+            _ => self.overflow.get(&entity),
         }
     }
 
-    pub fn is_empty(&self) -> bool {
-        for b in &self.bitfields {
-            if *b != 0 {
-                return false;
-            }
+    pub fn get_mut(&mut self, entity: EntityId) -> Option<&mut T> {
+        match self.inner.get_mut(entity_id::index(entity) as usize) {
+            Some(&mut Some((id, ref mut value))) if id == entity => Some(value),
+            _ => self.overflow.get_mut(&entity),
         }
+    }
 
-        true
+    pub fn contains_key(&self, entity: EntityId) -> bool {
+        self.get(entity).is_some()
     }
 
-    pub fn clear(&mut self) {
-        for b in &mut self.bitfields {
-            *b = 0;
+    pub fn remove(&mut self, entity: EntityId) -> Option<T> {
+        match self.inner.get_mut(entity_id::index(entity) as usize) {
+            Some(slot) if slot.as_ref().map_or(false, |&(id, _)| id == entity) => {
+                self.len -= 1;
+                slot.take().map(|(_, value)| value)
+            }
+            _ => self.overflow.remove(&entity),
         }
     }
 
-    pub fn iter(&self) -> ComponentTypeSetIter {
-        ComponentTypeSetIter::new(self.bitfields)
+    pub fn clear(&mut self) {
+        self.inner.clear();
+        self.overflow.clear();
+        self.len = 0;
     }
 
-{{#each component}}
-    pub fn contains_{{id}}(&self) -> bool {
-        self.bitfields[{{set_index}}] & (1 << {{set_bit}}) != 0
+    pub fn len(&self) -> usize {
+        self.len + self.overflow.len()
     }
 
-    pub fn insert_{{id}}(&mut self) {
-        self.bitfields[{{set_index}}] |= 1 << {{set_bit}};
+    pub fn iter(&self) -> EntityDenseMapIter<T> {
+        EntityDenseMapIter {
+            iter: self.inner.iter(),
+            overflow: self.overflow.iter(),
+        }
     }
 
-    pub fn remove_{{id}}(&mut self) {
-        self.bitfields[{{set_index}}] &= !(1 << {{set_bit}});
+    pub fn keys(&self) -> EntityDenseMapKeys<T> {
+        EntityDenseMapKeys {
+            iter: self.iter(),
+        }
     }
-{{/each}}
 }
 
-pub struct EcsCtx {
-{{#each component}}
-    {{#if type}}
-        {{#if container}}
-    {{id}}: EntityMap<{{container}}<{{type}}>>,
-        {{else}}
-    {{id}}: EntityMap<{{type}}>,
-        {{/if}}
-    {{else}}
-    {{id}}: EntitySet,
-    {{/if}}
-{{/each}}
-    tracker: EntityMap<ComponentTypeSet>,
+impl<T: Copy> EntityDenseMap<T> {
+    pub fn copy_iter(&self) -> EntityDenseMapCopyIter<T> {
+        EntityDenseMapCopyIter {
+            iter: self.iter(),
+        }
+    }
 }
 
-impl EcsCtx {
-    pub fn new() -> Self {
-        EcsCtx {
-{{#each component}}
-            {{id}}: {{#if type}} EntityMap::new() {{else}} EntitySet::new() {{/if}},
-{{/each}}
-            tracker: EntityMap::new(),
+pub struct EntityDenseMapIter<'a, T: 'a> {
+    {{internal}}iter: slice::Iter<'a, Option<(EntityId, T)>>,
+    {{internal}}overflow: btree_map::Iter<'a, EntityId, T>,
+}
+
+impl<'a, T: 'a> Iterator for EntityDenseMapIter<'a, T> {
+    type Item = (EntityId, &'a T);
+    fn next(&mut self) -> Option<Self::Item> {
+        while let Some(slot) = self.iter.next() {
+            if let Some((id, ref value)) = *slot {
+                return Some((id, value));
+            }
         }
+        self.overflow.next().map(|(id, value)| (*id, value))
     }
+}
 
-{{#each component}}
-    {{#if type}}
+pub struct EntityDenseMapKeys<'a, T: 'a> {
+    {{internal}}iter: EntityDenseMapIter<'a, T>,
+}
 
-        {{#if container}}
-    pub fn bare_insert_{{id}}(&mut self, entity: EntityId, value: {{container}}<{{type}}>) {
-        self.{{id}}.insert(entity, value);
-        self.tracker.entry(entity).or_insert_with(ComponentTypeSet::new).insert_{{id}}();
+impl<'a, T: 'a> Iterator for EntityDenseMapKeys<'a, T> {
+    type Item = EntityId;
+    fn next(&mut self) -> Option<Self::Item> {
+        self.iter.next().map(|(id, _)| id)
     }
+}
 
-    pub fn bare_remove_{{id}}(&mut self, entity: EntityId) -> Option<{{container}}<{{type}}>> {
-        let ret = self.{{id}}.remove(entity);
-        let empty = self.tracker.get_mut(entity).map(|set| {
-            set.remove_{{id}}();
-            set.is_empty()
-        });
-        if let Some(true) = empty {
-            self.tracker.remove(entity);
-        }
+pub struct EntityDenseMapCopyIter<'a, T: 'a + Copy> {
+    {{internal}}iter: EntityDenseMapIter<'a, T>,
+}
 
-        ret
+impl<'a, T: 'a + Copy> Iterator for EntityDenseMapCopyIter<'a, T> {
+    type Item = (EntityId, T);
+    fn next(&mut self) -> Option<Self::Item> {
+        self.iter.next().map(|(id, v)| (id, *v))
     }
-        {{/if}}
+}
 
-    pub fn insert_{{id}}(&mut self, entity: EntityId, value: {{type}}) {
-        {{#if container}}
-        self.bare_insert_{{id}}(entity, {{container}}::new(value));
-        {{else}}
-        self.{{id}}.insert(entity, value);
-        {{/if}}
-        self.tracker.entry(entity).or_insert_with(ComponentTypeSet::new).insert_{{id}}();
-    }
+pub struct EntityDenseSet {
+    {{internal}}inner: Vec<Option<EntityId>>,
+    {{internal}}overflow: BTreeSet<EntityId>,
+    {{internal}}len: usize,
+}
 
-    pub fn contains_{{id}}(&self, entity: EntityId) -> bool {
-        self.{{id}}.contains_key(entity)
+impl EntityDenseSet {
+    pub fn new() -> Self {
+        EntityDenseSet {
+            inner: Vec::new(),
+            overflow: BTreeSet::new(),
+            len: 0,
+        }
     }
 
-        {{#if copy}}
-    pub fn {{id}}(&self, entity: EntityId) -> Option<{{type}}> {
-        self.{{id}}.get(entity).map(|r| *r)
+    pub fn insert(&mut self, entity: EntityId) {
+        if self.overflow.contains(&entity) {
+            return;
+        }
+
+        let index = entity_id::index(entity) as usize;
+        if index >= self.inner.len() && index < slot_capacity(self.len()) {
+            self.inner.resize(index + 1, None);
+        }
+
+        match self.inner.get_mut(index) {
+            Some(slot) if slot.map_or(true, |id| id == entity) => {
+                if slot.is_none() {
+                    self.len += 1;
+                }
+                *slot = Some(entity);
+            }
+            _ => {
+                self.overflow.insert(entity);
+            }
+        }
     }
-    pub fn {{id}}_ref(&self, entity: EntityId) -> Option<&{{type}}> {
-        self.{{id}}.get(entity)
+
+    pub fn remove(&mut self, entity: EntityId) -> bool {
+        match self.inner.get_mut(entity_id::index(entity) as usize) {
+            Some(slot) if *slot == Some(entity) => {
+                self.len -= 1;
+                *slot = None;
+                true
+            }
+            _ => self.overflow.remove(&entity),
+        }
     }
-        {{else}}
-            {{#if container}}
-    pub fn {{id}}(&self, entity: EntityId) -> Option<&{{container}}<{{type}}>> {
-        self.{{id}}.get(entity)
+
+    pub fn contains(&self, entity: EntityId) -> bool {
+        self.inner.get(entity_id::index(entity) as usize) == Some(&Some(entity)) || self.overflow.contains(&entity)
     }
-                {{#if RefCell}}
-    pub fn {{id}}_borrow(&self, entity: EntityId) -> Option<Ref<{{type}}>> {
-        self.{{id}}.get(entity).map(|e| e.borrow())
+
+    pub fn len(&self) -> usize {
+        self.len + self.overflow.len()
     }
-    pub fn {{id}}_borrow_mut(&self, entity: EntityId) -> Option<RefMut<{{type}}>> {
-        self.{{id}}.get(entity).map(|e| e.borrow_mut())
+
+    pub fn clear(&mut self) {
+        self.inner.clear();
+        self.overflow.clear();
+        self.len = 0;
     }
-                {{/if}}
-                {{#if UnsafeCell}}
-    pub fn {{id}}_unsafe_get_mut(&self, entity: EntityId) -> Option<*mut {{type}}> {
-        self.{{id}}.get(entity).map(|e| e.get())
+
+    pub fn iter(&self) -> EntityDenseSetIter {
+        EntityDenseSetIter {
+            iter: self.inner.iter(),
+            overflow: self.overflow.iter(),
+        }
     }
-    pub fn {{id}}_unsafe_get(&self, entity: EntityId) -> Option<*const {{type}}> {
-        self.{{id}}.get(entity).map(|e| e.get() as *const {{type}})
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
     }
-                {{/if}}
-            {{else}}
-    pub fn {{id}}(&self, entity: EntityId) -> Option<&{{type}}> {
-        self.{{id}}.get(entity)
+}
+
+pub struct EntityDenseSetIter<'a> {
+    {{internal}}iter: slice::Iter<'a, Option<EntityId>>,
+    {{internal}}overflow: btree_set::Iter<'a, EntityId>,
+}
+
+impl<'a> Iterator for EntityDenseSetIter<'a> {
+    type Item = EntityId;
+    fn next(&mut self) -> Option<Self::Item> {
+        while let Some(slot) = self.iter.next() {
+            if let Some(id) = *slot {
+                return Some(id);
+            }
+        }
+        self.overflow.next().cloned()
     }
-            {{/if}}
-        {{/if}}
+}
 
-        {{#if container}}
-    pub fn {{id}}_mut(&mut self, entity: EntityId) -> Option<&mut {{container}}<{{type}}>> {
-        self.{{id}}.get_mut(entity)
+// Storage for a component in a packed vector, with a second vector mapping
+// entity indices to positions in the packed vector. As with the dense
+// collections, the positions of other generations of an occupied index, and
+// of indices beyond the slot capacity, are kept in an overflow map.
+pub struct EntitySparseMap<T> {
+    {{internal}}positions: Vec<Option<usize>>,
+    {{internal}}overflow: BTreeMap<EntityId, usize>,
+    {{internal}}ids: Vec<EntityId>,
+    {{internal}}values: Vec<T>,
+}
+
+impl<T> EntitySparseMap<T> {
+    pub fn new() -> Self {
+        EntitySparseMap {
+            positions: Vec::new(),
+            overflow: BTreeMap::new(),
+            ids: Vec::new(),
+            values: Vec::new(),
+        }
     }
-        {{else}}
-    pub fn {{id}}_mut(&mut self, entity: EntityId) -> Option<&mut {{type}}> {
-        self.{{id}}.get_mut(entity)
+
+    {{internal}}fn position(&self, entity: EntityId) -> Option<usize> {
+        sparse_position(&self.positions, &self.overflow, &self.ids, entity)
     }
-        {{/if}}
-    {{else}}
-    pub fn insert_{{id}}(&mut self, entity: EntityId) {
-        self.{{id}}.insert(entity);
-        self.tracker.entry(entity).or_insert_with(ComponentTypeSet::new).insert_{{id}}();
+
+    pub fn insert(&mut self, entity: EntityId, value: T) {
+        if let Some(position) = self.position(entity) {
+            self.values[position] = value;
+        } else {
+            sparse_push(&mut self.positions, &mut self.overflow, &mut self.ids, entity);
+            self.values.push(value);
+        }
     }
 
-    pub fn contains_{{id}}(&self, entity: EntityId) -> bool {
-        self.{{id}}.contains(entity)
+    pub fn get(&self, entity: EntityId) -> Option<&T> {
+        self.position(entity).map(|position| &self.values[position])
     }
-    {{/if}}
 
-    {{#if container}}
-    pub fn remove_{{id}}(&mut self, entity: EntityId) -> Option<{{type}}> {
-        self.bare_remove_{{id}}(entity).map(|c| c.into_inner())
+    pub fn get_mut(&mut self, entity: EntityId) -> Option<&mut T> {
+        self.position(entity).map(move |position| &mut self.values[position])
     }
-    {{else}}
-    pub fn remove_{{id}}(&mut self, entity: EntityId)
-        {{#if type}}
-        -> Option<{{type}}>
-        {{else}}
-        -> bool
-        {{/if}}
-    {
-        let ret = self.{{id}}.remove(entity);
-        let empty = self.tracker.get_mut(entity).map(|set| {
-            set.remove_{{id}}();
-            set.is_empty()
-        });
-        if let Some(true) = empty {
-            self.tracker.remove(entity);
-        }
 
-        ret
+    pub fn contains_key(&self, entity: EntityId) -> bool {
+        self.position(entity).is_some()
     }
-    {{/if}}
 
-    pub fn remove_{{id}}_into(&mut self, entity: EntityId, action: &mut EcsAction) {
-    {{#if type}}
-        self.remove_{{id}}(entity).map(|component| {
-            action.insert_{{id}}(entity, component);
-        });
-    {{else}}
-        if self.remove_{{id}}(entity) {
-            action.insert_{{id}}(entity);
-        }
-    {{/if}}
+    pub fn remove(&mut self, entity: EntityId) -> Option<T> {
+        self.position(entity).map(|position| {
+            sparse_swap_remove(&mut self.positions, &mut self.overflow, &mut self.ids, entity, position);
+            self.values.swap_remove(position)
+        })
     }
 
-    pub fn move_{{id}}(&mut self, src: EntityId, dst: EntityId) {
-    {{#if type}}
-        {{#if container}}
-        self.bare_remove_{{id}}(src).map(|x| {
-            self.bare_insert_{{id}}(dst, x);
-        });
-        {{else}}
-        self.remove_{{id}}(src).map(|x| {
-            self.insert_{{id}}(dst, x);
-        });
-        {{/if}}
-    {{else}}
-        if self.remove_{{id}}(src) {
-            self.insert_{{id}}(dst);
-        }
-    {{/if}}
+    pub fn clear(&mut self) {
+        self.positions.clear();
+        self.overflow.clear();
+        self.ids.clear();
+        self.values.clear();
     }
 
-    pub fn swap_{{id}}(&mut self, a: EntityId, b: EntityId) {
-    {{#if type}}
-        {{#if container}}
+    pub fn len(&self) -> usize {
+        self.ids.len()
+    }
 
-        let a_component = self.bare_remove_{{id}}(a);
-        let b_component = self.bare_remove_{{id}}(b);
+    pub fn iter(&self) -> EntitySparseMapIter<T> {
+        EntitySparseMapIter {
+            ids: self.ids.iter(),
+            values: self.values.iter(),
+        }
+    }
 
-        if let Some(a_component) = a_component {
-            self.bare_insert_{{id}}(b, a_component);
+    pub fn keys(&self) -> EntitySparseMapKeys<T> {
+        EntitySparseMapKeys {
+            iter: self.iter(),
         }
+    }
+}
 
-        if let Some(b_component) = b_component {
-            self.bare_insert_{{id}}(a, b_component);
+impl<T: Copy> EntitySparseMap<T> {
+    pub fn copy_iter(&self) -> EntitySparseMapCopyIter<T> {
+        EntitySparseMapCopyIter {
+            iter: self.iter(),
         }
+    }
+}
 
-        {{else}}
+// The position of an entity in the packed vectors of a sparse collection
+{{internal}}fn sparse_position(positions: &[Option<usize>], overflow: &BTreeMap<EntityId, usize>,
+                              ids: &[EntityId], entity: EntityId) -> Option<usize> {
+    match positions.get(entity_id::index(entity) as usize) {
+        Some(&Some(position)) if ids[position] == entity => Some(position),
+        _ => overflow.get(&entity).cloned(),
+    }
+}
 
-        let a_component = self.remove_{{id}}(a);
-        let b_component = self.remove_{{id}}(b);
+// Adds an entity which isn't in a sparse collection to the end of its ids
+{{internal}}fn sparse_push(positions: &mut Vec<Option<usize>>, overflow: &mut BTreeMap<EntityId, usize>,
+                          ids: &mut Vec<EntityId>, entity: EntityId) {
+    let index = entity_id::index(entity) as usize;
+    if index >= positions.len() && index < slot_capacity(ids.len()) {
+        positions.resize(index + 1, None);
+    }
 
-        if let Some(a_component) = a_component {
-            self.insert_{{id}}(b, a_component);
+    let position = ids.len();
+    ids.push(entity);
+    match positions.get_mut(index) {
+        Some(slot) if slot.is_none() => *slot = Some(position),
+        _ => {
+            overflow.insert(entity, position);
         }
+    }
+}
 
-        if let Some(b_component) = b_component {
-            self.insert_{{id}}(a, b_component);
+// Removes the entity at a position of a sparse collection's ids, replacing
+// it with the last entity
+{{internal}}fn sparse_swap_remove(positions: &mut [Option<usize>], overflow: &mut BTreeMap<EntityId, usize>,
+                                 ids: &mut Vec<EntityId>, entity: EntityId, position: usize) {
+    match positions.get_mut(entity_id::index(entity) as usize) {
+        Some(slot) if *slot == Some(position) => *slot = None,
+        _ => {
+            overflow.remove(&entity);
         }
+    }
 
-        {{/if}}
-    {{else}}
-        let a_contains = self.contains_{{id}}(a);
+    ids.swap_remove(position);
 
-        if self.contains_{{id}}(b) {
-            self.insert_{{id}}(a);
-        } else {
-            self.remove_{{id}}(a);
+    // the last entity was moved into the vacated position
+    if let Some(&moved) = ids.get(position) {
+        let last = ids.len();
+        match positions.get_mut(entity_id::index(moved) as usize) {
+            Some(slot) if *slot == Some(last) => *slot = Some(position),
+            _ => {
+                overflow.insert(moved, position);
+            }
         }
+    }
+}
 
-        if a_contains {
-            self.insert_{{id}}(b);
-        } else {
-            self.remove_{{id}}(b);
+pub struct EntitySparseMapIter<'a, T: 'a> {
+    {{internal}}ids: slice::Iter<'a, EntityId>,
+    {{internal}}values: slice::Iter<'a, T>,
+}
+
+impl<'a, T: 'a> Iterator for EntitySparseMapIter<'a, T> {
+    type Item = (EntityId, &'a T);
+    fn next(&mut self) -> Option<Self::Item> {
+        match (self.ids.next(), self.values.next()) {
+            (Some(id), Some(value)) => Some((*id, value)),
+            _ => None,
         }
-    {{/if}}
     }
-{{/each}}
+}
 
-    pub fn remove_component(&mut self, entity: EntityId, component_type: ComponentType) {
-        match component_type {
-{{#each component}}
-            component_type::{{id_uppercase}} => { self.remove_{{id}}(entity); }
-{{/each}}
-            _ => { panic!("Invalid component type: {}", component_type); }
-        }
+pub struct EntitySparseMapKeys<'a, T: 'a> {
+    {{internal}}iter: EntitySparseMapIter<'a, T>,
+}
+
+impl<'a, T: 'a> Iterator for EntitySparseMapKeys<'a, T> {
+    type Item = EntityId;
+    fn next(&mut self) -> Option<Self::Item> {
+        self.iter.next().map(|(id, _)| id)
     }
+}
 
-    pub fn remove_components(&mut self, entity: EntityId, component_type_set: ComponentTypeSet) {
-        for component_type in component_type_set.iter() {
-            self.remove_component(entity, component_type);
-        }
+pub struct EntitySparseMapCopyIter<'a, T: 'a + Copy> {
+    {{internal}}iter: EntitySparseMapIter<'a, T>,
+}
+
+impl<'a, T: 'a + Copy> Iterator for EntitySparseMapCopyIter<'a, T> {
+    type Item = (EntityId, T);
+    fn next(&mut self) -> Option<Self::Item> {
+        self.iter.next().map(|(id, v)| (id, *v))
     }
+}
 
-    pub fn remove_entity(&mut self, entity: EntityId) {
-        if let Some(set) = self.tracker.remove(entity) {
-            for component_type in set.iter() {
-                self.remove_component(entity, component_type);
-            }
+pub struct EntitySparseSet {
+    {{internal}}positions: Vec<Option<usize>>,
+    {{internal}}overflow: BTreeMap<EntityId, usize>,
+    {{internal}}ids: Vec<EntityId>,
+}
+
+impl EntitySparseSet {
+    pub fn new() -> Self {
+        EntitySparseSet {
+            positions: Vec::new(),
+            overflow: BTreeMap::new(),
+            ids: Vec::new(),
         }
     }
 
-    pub fn entity(&self, id: EntityId) -> EntityRef {
-        EntityRef::new(id, self)
+    {{internal}}fn position(&self, entity: EntityId) -> Option<usize> {
+        sparse_position(&self.positions, &self.overflow, &self.ids, entity)
     }
 
-    pub fn entity_mut(&mut self, id: EntityId) -> EntityRefMut {
-        EntityRefMut::new(id, self)
+    pub fn insert(&mut self, entity: EntityId) {
+        if self.position(entity).is_none() {
+            sparse_push(&mut self.positions, &mut self.overflow, &mut self.ids, entity);
+        }
     }
 
-    pub fn post_action_entity<'a>(&'a self, id: EntityId, action: &'a EcsAction) -> PostActionEntityRef<'a> {
-        PostActionEntityRef::new(id, self, action)
+    pub fn remove(&mut self, entity: EntityId) -> bool {
+        if let Some(position) = self.position(entity) {
+            sparse_swap_remove(&mut self.positions, &mut self.overflow, &mut self.ids, entity, position);
+            true
+        } else {
+            false
+        }
     }
 
+    pub fn contains(&self, entity: EntityId) -> bool {
+        self.position(entity).is_some()
+    }
 
-    pub fn commit(&mut self, action: &mut EcsAction) {
-
-{{#each component}}
+    pub fn len(&self) -> usize {
+        self.ids.len()
+    }
 
-        if action.changed_components.contains_{{id}}() {
+    pub fn clear(&mut self) {
+        self.positions.clear();
+        self.overflow.clear();
+        self.ids.clear();
+    }
 
-    {{#if type}}
+    pub fn iter(&self) -> EntitySparseSetIter {
+        EntitySparseSetIter {
+            iter: self.ids.iter(),
+        }
+    }
 
-            for (id, component) in action.{{id}}.insertions.drain() {
-                self.insert_{{id}}(id, component);
-            }
+    pub fn is_empty(&self) -> bool {
+        self.ids.is_empty()
+    }
+}
 
-    {{else}}
+pub struct EntitySparseSetIter<'a> {
+    {{internal}}iter: slice::Iter<'a, EntityId>,
+}
 
-            for id in action.{{id}}.insertions.drain() {
-                self.insert_{{id}}(id);
-            }
-
-    {{/if}}
-
-            for id in action.{{id}}.removals.drain() {
-                self.remove_{{id}}(id);
-            }
-
-            for (a, b) in action.{{id}}.swaps.apply.drain(..) {
-                self.swap_{{id}}(a, b);
-            }
-            action.{{id}}.swaps.lookup.clear();
+impl<'a> Iterator for EntitySparseSetIter<'a> {
+    type Item = EntityId;
+    fn next(&mut self) -> Option<Self::Item> {
+        self.iter.next().map(|id_ref| *id_ref)
+    }
+}
 
-            for mv in action.{{id}}.moves.apply.drain(..) {
-                self.move_{{id}}(mv.source, mv.destination);
-            }
-            action.{{id}}.moves.lookup_to.clear();
-            action.{{id}}.moves.lookup_from.clear();
+impl FromIterator<EntityId> for EntitySet {
+    fn from_iter<I: IntoIterator<Item=EntityId>>(iter: I) -> Self {
+        let mut set = EntitySet::new();
+        for entity in iter {
+            set.insert(entity);
         }
-{{/each}}
-
-        action.changed_components.clear();
-        action.properties.clear();
+        set
     }
+}
 
-    pub fn commit_into(&mut self, from: &mut EcsAction, to: &mut EcsAction) {
-{{#each component}}
+impl FromIterator<EntityId> for EntityHashSet {
+    fn from_iter<I: IntoIterator<Item=EntityId>>(iter: I) -> Self {
+        let mut set = EntityHashSet::new();
+        for entity in iter {
+            set.insert(entity);
+        }
+        set
+    }
+}
 
-        if from.changed_components.contains_{{id}}() {
+impl FromIterator<EntityId> for EntityDenseSet {
+    fn from_iter<I: IntoIterator<Item=EntityId>>(iter: I) -> Self {
+        let mut set = EntityDenseSet::new();
+        for entity in iter {
+            set.insert(entity);
+        }
+        set
+    }
+}
 
-    {{#if type}}
+impl FromIterator<EntityId> for EntitySparseSet {
+    fn from_iter<I: IntoIterator<Item=EntityId>>(iter: I) -> Self {
+        let mut set = EntitySparseSet::new();
+        for entity in iter {
+            set.insert(entity);
+        }
+        set
+    }
+}
 
-            for (id, component) in from.{{id}}.insertions.drain() {
-                self.insert_{{id}}(id, component);
-            }
+// Entity collections, as seen by queries and by the iterators over the
+// changes an action will make. Collections which store their entities in
+// order can additionally be seeked, allowing queries to join them without
+// visiting every entity.
+{{internal}}trait EntityCollection {
+    fn contains(&self, entity: EntityId) -> bool;
+    fn len(&self) -> usize;
+    fn ids<'a>(&'a self) -> Box<dyn Iterator<Item=EntityId> + 'a>;
+    fn is_sorted(&self) -> bool {
+        false
+    }
+    // returns the first entity not less than the given entity
+    fn seek(&self, _entity: EntityId) -> Option<EntityId> {
+        unreachable!("Attempt to seek in unsorted entity collection")
+    }
+}
 
-    {{else}}
+{{internal}}trait ComponentMap<T>: EntityCollection {
+    fn get(&self, entity: EntityId) -> Option<&T>;
+}
 
-            for id in from.{{id}}.insertions.drain() {
-                self.insert_{{id}}(id);
-            }
+impl<T> EntityCollection for EntityMap<T> {
+    fn contains(&self, entity: EntityId) -> bool {
+        self.contains_key(entity)
+    }
+    fn len(&self) -> usize {
+        EntityMap::len(self)
+    }
+    fn ids<'a>(&'a self) -> Box<dyn Iterator<Item=EntityId> + 'a> {
+        Box::new(self.keys())
+    }
+    fn is_sorted(&self) -> bool {
+        true
+    }
+    fn seek(&self, entity: EntityId) -> Option<EntityId> {
+        EntityMap::seek(self, entity)
+    }
+}
 
-    {{/if}}
+impl<T> ComponentMap<T> for EntityMap<T> {
+    fn get(&self, entity: EntityId) -> Option<&T> {
+        EntityMap::get(self, entity)
+    }
+}
 
-            for id in from.{{id}}.removals.drain() {
-                self.remove_{{id}}_into(id, to);
-            }
+impl<T> EntityCollection for EntityHashMap<T> {
+    fn contains(&self, entity: EntityId) -> bool {
+        self.contains_key(entity)
+    }
+    fn len(&self) -> usize {
+        EntityHashMap::len(self)
+    }
+    fn ids<'a>(&'a self) -> Box<dyn Iterator<Item=EntityId> + 'a> {
+        Box::new(self.keys())
+    }
+}
 
-            for (a, b) in from.{{id}}.swaps.apply.drain(..) {
-                self.swap_{{id}}(a, b);
-            }
-            from.{{id}}.swaps.lookup.clear();
+impl<T> ComponentMap<T> for EntityHashMap<T> {
+    fn get(&self, entity: EntityId) -> Option<&T> {
+        EntityHashMap::get(self, entity)
+    }
+}
 
-            for mv in from.{{id}}.moves.apply.drain(..) {
-                self.move_{{id}}(mv.source, mv.destination);
-            }
-            from.{{id}}.moves.lookup_to.clear();
-            from.{{id}}.moves.lookup_from.clear();
-        }
-{{/each}}
+impl<T> EntityCollection for EntityDenseMap<T> {
+    fn contains(&self, entity: EntityId) -> bool {
+        self.contains_key(entity)
+    }
+    fn len(&self) -> usize {
+        EntityDenseMap::len(self)
+    }
+    fn ids<'a>(&'a self) -> Box<dyn Iterator<Item=EntityId> + 'a> {
+        Box::new(self.keys())
+    }
+}
 
-        from.changed_components.clear();
-        from.properties.clear();
+impl<T> ComponentMap<T> for EntityDenseMap<T> {
+    fn get(&self, entity: EntityId) -> Option<&T> {
+        EntityDenseMap::get(self, entity)
     }
+}
 
-    pub fn entity_iter<I: Iterator<Item=EntityId>>(&self, iter: I) -> EntityRefIter<I> {
-        EntityRefIter::new(self, iter)
+impl<T> EntityCollection for EntitySparseMap<T> {
+    fn contains(&self, entity: EntityId) -> bool {
+        self.contains_key(entity)
+    }
+    fn len(&self) -> usize {
+        EntitySparseMap::len(self)
     }
+    fn ids<'a>(&'a self) -> Box<dyn Iterator<Item=EntityId> + 'a> {
+        Box::new(self.keys())
+    }
+}
 
-{{#each component}}
-    {{#if type}}
+impl<T> ComponentMap<T> for EntitySparseMap<T> {
+    fn get(&self, entity: EntityId) -> Option<&T> {
+        EntitySparseMap::get(self, entity)
+    }
+}
 
-    pub fn {{id}}_id_iter(&self) ->
-        {{#if container}}
-            {{#if RefCell}}
-        EntityMapKeys<RefCell<{{type}}>>
-            {{/if}}
-            {{#if UnsafeCell}}
-        EntityMapKeys<UnsafeCell<{{type}}>>
-            {{/if}}
-        {{else}}
-        EntityMapKeys<{{type}}>
-        {{/if}}
+impl EntityCollection for EntitySet {
+    fn contains(&self, entity: EntityId) -> bool {
+        EntitySet::contains(self, entity)
+    }
+    fn len(&self) -> usize {
+        EntitySet::len(self)
+    }
+    fn ids<'a>(&'a self) -> Box<dyn Iterator<Item=EntityId> + 'a> {
+        Box::new(self.iter())
+    }
+    fn is_sorted(&self) -> bool {
+        true
+    }
+    fn seek(&self, entity: EntityId) -> Option<EntityId> {
+        EntitySet::seek(self, entity)
+    }
+}
 
-    {
-        self.{{id}}.keys()
+impl EntityCollection for EntityHashSet {
+    fn contains(&self, entity: EntityId) -> bool {
+        EntityHashSet::contains(self, entity)
+    }
+    fn len(&self) -> usize {
+        EntityHashSet::len(self)
     }
+    fn ids<'a>(&'a self) -> Box<dyn Iterator<Item=EntityId> + 'a> {
+        Box::new(self.iter())
+    }
+}
 
-    pub fn {{id}}_iter(&self) ->
-        {{#if container}}
-            {{#if RefCell}}
-        EntityMapIter<RefCell<{{type}}>>
-            {{/if}}
-            {{#if UnsafeCell}}
-        EntityMapIter<UnsafeCell<{{type}}>>
-            {{/if}}
-        {{else}}
-            {{#if copy}}
-        EntityMapCopyIter<{{type}}>
-            {{else}}
-        EntityMapIter<{{type}}>
-            {{/if}}
-        {{/if}}
+impl EntityCollection for EntityDenseSet {
+    fn contains(&self, entity: EntityId) -> bool {
+        EntityDenseSet::contains(self, entity)
+    }
+    fn len(&self) -> usize {
+        EntityDenseSet::len(self)
+    }
+    fn ids<'a>(&'a self) -> Box<dyn Iterator<Item=EntityId> + 'a> {
+        Box::new(self.iter())
+    }
+}
 
-    {
-        {{#if copy}}
-            self.{{id}}.copy_iter()
-        {{else}}
-            self.{{id}}.iter()
-        {{/if}}
+impl EntityCollection for EntitySparseSet {
+    fn contains(&self, entity: EntityId) -> bool {
+        EntitySparseSet::contains(self, entity)
+    }
+    fn len(&self) -> usize {
+        EntitySparseSet::len(self)
     }
+    fn ids<'a>(&'a self) -> Box<dyn Iterator<Item=EntityId> + 'a> {
+        Box::new(self.iter())
+    }
+}
 
-    {{else}}
+{{!-- module: types --}}
+pub const NUM_COMPONENTS: usize = {{num_components}};
 
-    pub fn {{id}}_id_iter(&self) -> EntitySetIter {
-        self.{{id}}.iter()
-    }
+{{internal}}const WORD_SIZE: usize = {{word_size}};
+{{internal}}const WORD_BITS: usize = {{word_bits}};
 
-    {{/if}}
+{{internal}}const COMPONENT_TYPE_SET_NUM_WORDS: usize = {{component_set_num_words}};
 
-{{/each}}
+pub type ComponentType = usize;
 
-    pub fn clear(&mut self) {
-{{#each component}}
-        self.{{id}}.clear();
-{{/each}}
-        self.tracker.clear();
-    }
-}
+pub mod component_type {
+    use std::usize;
+    use super::ComponentType;
 
-#[derive(Serialize, Deserialize)]
-pub struct SerializableEcsCtx {
 {{#each component}}
-    {{#if type}}
-    {{id}}: EntityMap<{{type}}>,
-    {{else}}
-    {{id}}: EntitySet,
-    {{/if}}
+    pub const {{id_uppercase}}: usize = {{index}};
 {{/each}}
-    tracker: EntityMap<ComponentTypeSet>,
-}
+    pub const INVALID_COMPONENT: usize = usize::MAX;
 
-impl From<SerializableEcsCtx> for EcsCtx {
-    fn from(ecs: SerializableEcsCtx) -> Self {
-        let SerializableEcsCtx {
+    pub fn name(component_type: ComponentType) -> &'static str {
+        match component_type {
 {{#each component}}
-    {{#if container}}
-            mut {{id}},
-    {{else}}
-            {{id}},
-    {{/if}}
+            {{id_uppercase}} => "{{id}}",
 {{/each}}
-            tracker,
-        } = ecs;
+            _ => panic!("Invalid component type: {}", component_type),
+        }
+    }
 
-        EcsCtx {
+    pub fn from_name(name: &str) -> Option<ComponentType> {
+        match name {
 {{#each component}}
-    {{#if type}}
-        {{#if container}}
-            {{#if RefCell}}
-            {{id}}: {
-                let mut map = EntityMap::new();
-                let keys: Vec<EntityId> = {{id}}.keys().collect();
-                for key in keys {
-                    if let Some(value) = {{id}}.remove(key) {
-                        map.insert(key, RefCell::new(value));
-                    }
-                }
-
-                map
-            },
-            {{/if}}
-            {{#if UnsafeCell}}
-            {{id}}: {
-                let mut map = EntityMap::new();
-                let keys: Vec<EntityId> = {{id}}.keys().collect();
-                for key in keys {
-                    if let Some(value) = {{id}}.remove(key) {
-                        map.insert(key, UnsafeCell::new(value));
-                    }
-                }
-
-                map
-            },
-            {{/if}}
-        {{else}}
-            {{id}}: {{id}},
-        {{/if}}
-    {{else}}
-            {{id}}: {{id}},
-    {{/if}}
+            "{{id}}" => Some({{id_uppercase}}),
 {{/each}}
-            tracker: tracker,
+            _ => None,
         }
     }
-}
 
-impl From<EcsCtx> for SerializableEcsCtx {
-    fn from(ecs: EcsCtx) -> Self {
-        let EcsCtx {
-{{#each component}}
-    {{#if container}}
-            mut {{id}},
-    {{else}}
-            {{id}},
-    {{/if}}
-{{/each}}
-            tracker,
-        } = ecs;
+    pub fn is_flag(component_type: ComponentType) -> bool {
+        type_name(component_type).is_none()
+    }
 
-        SerializableEcsCtx {
+    // The rust type of a component's value, as written in the spec, or None
+    // for flags
+    pub fn type_name(component_type: ComponentType) -> Option<&'static str> {
+        match component_type {
 {{#each component}}
     {{#if type}}
-        {{#if container}}
-            {{#if RefCell}}
-            {{id}}: {
-                let mut map = EntityMap::new();
-                let keys: Vec<EntityId> = {{id}}.keys().collect();
-                for key in keys {
-                    if let Some(cell) = {{id}}.remove(key) {
-                        map.insert(key, cell.into_inner());
-                    }
-                }
-
-                map
-            },
-            {{/if}}
-            {{#if UnsafeCell}}
-            {{id}}: {
-                let mut map = EntityMap::new();
-                let keys: Vec<EntityId> = {{id}}.keys().collect();
-                for key in keys {
-                    if let Some(cell) = {{id}}.remove(key) {
-                        let ptr = cell.get();
-                        assert!(!ptr.is_null(), "Attempt to serialize null UnsafeCell");
-                        map.insert(key, unsafe { cell.into_inner() });
-                    }
-                }
-
-                map
-            },
-            {{/if}}
-        {{else}}
-            {{id}}: {{id}},
-        {{/if}}
+            {{id_uppercase}} => Some("{{type}}"),
     {{else}}
-            {{id}}: {{id}},
+            {{id_uppercase}} => None,
     {{/if}}
 {{/each}}
-            tracker: tracker,
+            _ => panic!("Invalid component type: {}", component_type),
         }
     }
 }
 
-#[derive(Clone, Copy)]
-pub struct EntityRef<'a> {
-    id: EntityId,
-    ctx: &'a EcsCtx,
+#[derive(Serialize, Deserialize)]
+pub struct ComponentTypeSet {
+    {{internal}}bitfields: [usize; COMPONENT_TYPE_SET_NUM_WORDS],
 }
 
-impl<'a> EntityRef<'a> {
-    fn new(id: EntityId, ctx: &'a EcsCtx) -> Self {
-        EntityRef {
-            id: id,
-            ctx: ctx,
+pub struct ComponentTypeSetIter {
+    {{internal}}bitfields: [usize; COMPONENT_TYPE_SET_NUM_WORDS],
+    {{internal}}index: usize,
+}
+
+impl ComponentTypeSetIter {
+    {{internal}}fn new(bitfields: [usize; COMPONENT_TYPE_SET_NUM_WORDS]) -> Self {
+        ComponentTypeSetIter {
+            bitfields: bitfields,
+            index: 0,
         }
     }
+}
 
-    pub fn id(self) -> EntityId {
-        self.id
+impl Iterator for ComponentTypeSetIter {
+    type Item = ComponentType;
+    fn next(&mut self) -> Option<Self::Item> {
+        while self.index < COMPONENT_TYPE_SET_NUM_WORDS && self.bitfields[self.index] == 0 {
+            self.index += 1;
+        }
+        if self.index == COMPONENT_TYPE_SET_NUM_WORDS {
+            return None;
+        }
+
+        let trailing = self.bitfields[self.index].trailing_zeros() as usize;
+        self.bitfields[self.index] &= !(1 << trailing);
+        Some(self.index * WORD_BITS + trailing)
     }
+}
 
-    pub fn is_empty(self) -> bool {
-        if let Some(set) = self.ctx.tracker.get(self.id) {
-            set.is_empty()
-        } else {
-            true
+impl ComponentTypeSet {
+    pub fn new() -> Self {
+        ComponentTypeSet {
+            bitfields: [0; COMPONENT_TYPE_SET_NUM_WORDS],
         }
     }
 
-{{#each component}}
-    pub fn contains_{{id}}(self) -> bool {
-        self.ctx.contains_{{id}}(self.id)
-    }
-    {{#if type}}
-        {{#if copy}}
-    pub fn {{id}}(self) -> Option<{{type}}> {
-        self.ctx.{{id}}(self.id)
-    }
-    pub fn {{id}}_ref(self) -> Option<&'a {{type}}> {
-        self.ctx.{{id}}_ref(self.id)
-    }
-        {{else}}
-            {{#if container}}
-    pub fn {{id}}(self) -> Option<&'a {{container}}<{{type}}>> {
-        self.ctx.{{id}}(self.id)
-    }
-                {{#if RefCell}}
-    pub fn {{id}}_borrow(self) -> Option<Ref<'a, {{type}}>> {
-        self.ctx.{{id}}_borrow(self.id)
+    pub fn is_empty(&self) -> bool {
+        for b in &self.bitfields {
+            if *b != 0 {
+                return false;
+            }
+        }
+
+        true
     }
-    pub fn {{id}}_borrow_mut(self) -> Option<RefMut<'a, {{type}}>> {
-        self.ctx.{{id}}_borrow_mut(self.id)
+
+    pub fn clear(&mut self) {
+        for b in &mut self.bitfields {
+            *b = 0;
+        }
     }
-                {{/if}}
-                {{#if UnsafeCell}}
-    pub fn {{id}}_unsafe_get_mut(self) -> Option<*mut {{type}}> {
-        self.ctx.{{id}}_unsafe_get_mut(self.id)
+
+    pub fn iter(&self) -> ComponentTypeSetIter {
+        ComponentTypeSetIter::new(self.bitfields)
     }
-    pub fn {{id}}_unsafe_get(self) -> Option<*const {{type}}> {
-        self.ctx.{{id}}_unsafe_get(self.id)
+
+{{#each component}}
+    pub fn contains_{{id}}(&self) -> bool {
+        self.bitfields[{{set_index}}] & (1 << {{set_bit}}) != 0
     }
-                {{/if}}
-            {{else}}
-    pub fn {{id}}(self) -> Option<&'a {{type}}> {
-        self.ctx.{{id}}(self.id)
+
+    pub fn insert_{{id}}(&mut self) {
+        self.bitfields[{{set_index}}] |= 1 << {{set_bit}};
     }
-            {{/if}}
-        {{/if}}
-    {{/if}}
-{{/each}}
-}
 
-pub trait EntityPopulate {
-{{#each component}}
-    {{#if type}}
-    fn insert_{{id}}(&mut self, value: {{type}});
-    {{else}}
-    fn insert_{{id}}(&mut self);
-    {{/if}}
+    pub fn remove_{{id}}(&mut self) {
+        self.bitfields[{{set_index}}] &= !(1 << {{set_bit}});
+    }
 {{/each}}
 }
 
-pub struct EntityRefMut<'a> {
-    id: EntityId,
-    ctx: &'a mut EcsCtx,
+{{!-- module: ctx --}}
+// Entities ordered by the tick at which they last changed in some way
+pub struct ChangeLog {
+    {{internal}}ticks: EntityMap<u64>,
+    {{internal}}order: BTreeSet<(u64, EntityId)>,
 }
 
-impl<'a> EntityRefMut<'a> {
-    fn new(id: EntityId, ctx: &'a mut EcsCtx) -> Self {
-        EntityRefMut {
-            id: id,
-            ctx: ctx,
+impl ChangeLog {
+    {{internal}}fn new() -> Self {
+        ChangeLog {
+            ticks: EntityMap::new(),
+            order: BTreeSet::new(),
         }
     }
 
-    pub fn id(&self) -> EntityId {
-        self.id
+    {{internal}}fn stamp(&mut self, entity: EntityId, tick: u64) {
+        if let Some(previous) = self.ticks.get(entity).cloned() {
+            self.order.remove(&(previous, entity));
+        }
+        self.ticks.insert(entity, tick);
+        self.order.insert((tick, entity));
     }
 
-    pub fn is_empty(&self) -> bool {
-        if let Some(set) = self.ctx.tracker.get(self.id) {
-            set.is_empty()
-        } else {
-            true
+    {{internal}}fn unstamp(&mut self, entity: EntityId) {
+        if let Some(tick) = self.ticks.remove(entity) {
+            self.order.remove(&(tick, entity));
         }
     }
 
-    pub fn destroy(self) {
-        self.ctx.remove_entity(self.id);
-    }
-{{#each component}}
-    pub fn contains_{{id}}(&self) -> bool {
-        self.ctx.contains_{{id}}(self.id)
+    // forgets the entities stamped at or before the given tick
+    {{internal}}fn forget(&mut self, tick: u64) {
+        let forgotten: Vec<(u64, EntityId)> = self.order.range(..=(tick, EntityId::max_value())).cloned().collect();
+        for stamp in forgotten {
+            self.order.remove(&stamp);
+            self.ticks.remove(stamp.1);
+        }
     }
 
-    {{#if type}}
-        {{#if container}}
-    pub fn bare_remove_{{id}}(&mut self) -> Option<{{container}}<{{type}}>> {
-        self.ctx.bare_remove_{{id}}(self.id)
+    {{internal}}fn clear(&mut self) {
+        self.ticks.clear();
+        self.order.clear();
     }
-        {{/if}}
-    {{/if}}
 
-    pub fn remove_{{id}}(&mut self)
-    {{#if type}}
-        -> Option<{{type}}>
-    {{else}}
-        -> bool
-    {{/if}}
-    {
-        self.ctx.remove_{{id}}(self.id)
-    }
-    {{#if type}}
-        {{#if copy}}
-    pub fn {{id}}(&self) -> Option<{{type}}> {
-        self.ctx.{{id}}(self.id)
+    pub fn tick(&self, entity: EntityId) -> Option<u64> {
+        self.ticks.get(entity).cloned()
     }
-    pub fn {{id}}_ref(&self) -> Option<&{{type}}> {
-        self.ctx.{{id}}_ref(self.id)
+
+    {{internal}}fn since<'a>(&'a self, tick: u64, components: &'a dyn EntityCollection, present: bool) -> ChangeIter<'a> {
+        ChangeIter {
+            iter: self.order.range((Bound::Excluded((tick, EntityId::max_value())), Bound::Unbounded)),
+            components: components,
+            present: present,
+        }
     }
-        {{else}}
-            {{#if container}}
-    pub fn {{id}}(&self) -> Option<&{{container}}<{{type}}>> {
-        self.ctx.{{id}}(self.id)
+}
+
+// Iterates over the entities in a change log which currently have (or
+// don't have) the component, in the order they changed
+pub struct ChangeIter<'a> {
+    {{internal}}iter: btree_set::Range<'a, (u64, EntityId)>,
+    {{internal}}components: &'a dyn EntityCollection,
+    {{internal}}present: bool,
+}
+
+impl<'a> Iterator for ChangeIter<'a> {
+    type Item = EntityId;
+    fn next(&mut self) -> Option<Self::Item> {
+        while let Some(&(_, entity)) = self.iter.next() {
+            if self.components.contains(entity) == self.present {
+                return Some(entity);
+            }
+        }
+        None
     }
-                {{#if RefCell}}
-    pub fn {{id}}_borrow(&self) -> Option<Ref<{{type}}>> {
-        self.ctx.{{id}}_borrow(self.id)
+}
+
+// The changes to a single component. An entity which loses and regains the
+// component within a single tick is considered changed rather than added.
+pub struct ComponentChanges {
+    {{internal}}changed: ChangeLog,
+    {{internal}}added: ChangeLog,
+    {{internal}}removed: ChangeLog,
+}
+
+impl ComponentChanges {
+    {{internal}}fn new() -> Self {
+        ComponentChanges {
+            changed: ChangeLog::new(),
+            added: ChangeLog::new(),
+            removed: ChangeLog::new(),
+        }
     }
-    pub fn {{id}}_borrow_mut(&self) -> Option<RefMut<{{type}}>> {
-        self.ctx.{{id}}_borrow_mut(self.id)
+
+    {{internal}}fn insert(&mut self, entity: EntityId, tick: u64, present: bool) {
+        if !present && self.removed.tick(entity) != Some(tick) {
+            self.added.stamp(entity, tick);
+        }
+        self.changed.stamp(entity, tick);
     }
-                {{/if}}
-                {{#if UnsafeCell}}
-    pub fn {{id}}_unsafe_get_mut(&self) -> Option<*mut {{type}}> {
-        self.ctx.{{id}}_unsafe_get_mut(self.id)
+
+    {{internal}}fn modify(&mut self, entity: EntityId, tick: u64) {
+        self.changed.stamp(entity, tick);
     }
-    pub fn {{id}}_unsafe_get(&self) -> Option<*const {{type}}> {
-        self.ctx.{{id}}_unsafe_get(self.id)
+
+    {{internal}}fn remove(&mut self, entity: EntityId, tick: u64) {
+        self.removed.stamp(entity, tick);
     }
-                {{/if}}
-            {{else}}
-    pub fn {{id}}(&self) -> Option<&{{type}}> {
-        self.ctx.{{id}}(self.id)
+
+    {{internal}}fn forget(&mut self, tick: u64) {
+        self.changed.forget(tick);
+        self.added.forget(tick);
+        self.removed.forget(tick);
     }
-            {{/if}}
-        {{/if}}
-        {{#if container}}
-    pub fn {{id}}_mut(&mut self) -> Option<&mut {{container}}<{{type}}>> {
-        self.ctx.{{id}}_mut(self.id)
+
+    // Forgets the changes and additions of an entity which no longer has the
+    // component. Only its removal can still be reported.
+    {{internal}}fn prune(&mut self, entity: EntityId) {
+        self.changed.unstamp(entity);
+        self.added.unstamp(entity);
     }
-        {{else}}
-    pub fn {{id}}_mut(&mut self) -> Option<&mut {{type}}> {
-        self.ctx.{{id}}_mut(self.id)
+
+    {{internal}}fn clear(&mut self) {
+        self.changed.clear();
+        self.added.clear();
+        self.removed.clear();
     }
-        {{/if}}
-    {{/if}}
-{{/each}}
 }
 
-impl<'a> EntityPopulate for EntityRefMut<'a> {
+{{internal}}struct EcsChanges {
 {{#each component}}
-    {{#if type}}
-    fn insert_{{id}}(&mut self, value: {{type}}) {
-        self.ctx.insert_{{id}}(self.id, value);
-    }
-    {{else}}
-    fn insert_{{id}}(&mut self) {
-        self.ctx.insert_{{id}}(self.id);
-    }
-    {{/if}}
+    {{../internal}}{{id}}: ComponentChanges,
 {{/each}}
 }
 
-impl<'a> EntityPopulate for ActionEntityRefMut<'a> {
+impl EcsChanges {
+    {{internal}}fn new() -> Self {
+        EcsChanges {
 {{#each component}}
-    {{#if type}}
-    fn insert_{{id}}(&mut self, value: {{type}}) {
-        self.action.insert_{{id}}(self.id, value);
-    }
-    {{else}}
-    fn insert_{{id}}(&mut self) {
-        self.action.insert_{{id}}(self.id);
-    }
-    {{/if}}
+            {{id}}: ComponentChanges::new(),
 {{/each}}
-}
-
-struct FlagMoveIter<'a> {
-    components: &'a EntitySet,
-    iter: btree_map::Iter<'a, EntityId, EntityId>,
-}
-
-impl<'a> FlagMoveIter<'a> {
-    fn new(components: &'a EntitySet, iter: btree_map::Iter<'a, EntityId, EntityId>) -> Self {
-        FlagMoveIter {
-            components: components,
-            iter: iter,
         }
     }
-}
 
-struct FlagMovePositiveIter<'a>(FlagMoveIter<'a>);
+    {{internal}}fn forget(&mut self, tick: u64) {
+{{#each component}}
+        self.{{id}}.forget(tick);
+{{/each}}
+    }
 
-impl<'a> FlagMovePositiveIter<'a> {
-    fn new(components: &'a EntitySet, iter: btree_map::Iter<'a, EntityId, EntityId>) -> Self {
-        FlagMovePositiveIter(FlagMoveIter::new(components, iter))
+    {{internal}}fn prune(&mut self, entity: EntityId) {
+{{#each component}}
+        self.{{id}}.prune(entity);
+{{/each}}
     }
-}
 
-impl<'a> Iterator for FlagMovePositiveIter<'a> {
-    type Item = EntityId;
-    fn next(&mut self) -> Option<Self::Item> {
-        while let Some((dest, src)) = self.0.iter.next() {
-            // for each flag being moved into dest
-            if self.0.components.contains(*src) {
-                // if the flag was set in the source
-                if !self.0.components.contains(*dest) {
-                    // if the flag wasn't set in the destination
-                    return Some(*dest);
-                }
-            }
-        }
-        None
+    {{internal}}fn clear(&mut self) {
+{{#each component}}
+        self.{{id}}.clear();
+{{/each}}
     }
 }
 
-struct FlagMoveNegativeIter<'a>(FlagMoveIter<'a>);
-
-impl<'a> FlagMoveNegativeIter<'a> {
-    fn new(components: &'a EntitySet, iter: btree_map::Iter<'a, EntityId, EntityId>) -> Self {
-        FlagMoveNegativeIter(FlagMoveIter::new(components, iter))
-    }
+// Callbacks notified of the changes a commit makes to a component. A
+// component which is overwritten is reported as replaced, rather than as
+// removed and inserted.
+pub struct ComponentObservers<T> {
+    {{internal}}insert: Vec<Box<dyn FnMut(EntityId, &T) + Send + Sync>>,
+    {{internal}}remove: Vec<Box<dyn FnMut(EntityId, &T) + Send + Sync>>,
+    {{internal}}replace: Vec<Box<dyn FnMut(EntityId, &T, &T) + Send + Sync>>,
 }
 
-impl<'a> Iterator for FlagMoveNegativeIter<'a> {
-    type Item = EntityId;
-    fn next(&mut self) -> Option<Self::Item> {
-        while let Some((src, _)) = self.0.iter.next() {
-            // for each flag being moved out of src
-            if self.0.components.contains(*src) {
-                // if the flag was originally set
-                return Some(*src);
-            }
+impl<T> ComponentObservers<T> {
+    {{internal}}fn new() -> Self {
+        ComponentObservers {
+            insert: Vec::new(),
+            remove: Vec::new(),
+            replace: Vec::new(),
         }
-        None
     }
-}
 
-struct FlagSwapNegativeIter<'a>(FlagMoveIter<'a>);
-
-impl<'a> FlagSwapNegativeIter<'a> {
-    fn new(components: &'a EntitySet, iter: btree_map::Iter<'a, EntityId, EntityId>) -> Self {
-        FlagSwapNegativeIter(FlagMoveIter::new(components, iter))
+    {{internal}}fn is_empty(&self) -> bool {
+        self.insert.is_empty() && self.remove.is_empty() && self.replace.is_empty()
     }
-}
 
-impl<'a> Iterator for FlagSwapNegativeIter<'a> {
-    type Item = EntityId;
-    fn next(&mut self) -> Option<Self::Item> {
-        while let Some((src, dest)) = self.0.iter.next() {
-            // for each flag being moved out of src
-            if self.0.components.contains(*src) {
-                // if the flag was originally set
-                if !self.0.components.contains(*dest) {
-                    // if the flag being swapped with is clear
-                    return Some(*src);
+    {{internal}}fn changed(&mut self, entity: EntityId, old: Option<&T>, new: Option<&T>) {
+        match (old, new) {
+            (None, Some(new)) => {
+                for observer in self.insert.iter_mut() {
+                    observer(entity, new);
+                }
+            }
+            (Some(old), None) => {
+                for observer in self.remove.iter_mut() {
+                    observer(entity, old);
+                }
+            }
+            (Some(old), Some(new)) => {
+                for observer in self.replace.iter_mut() {
+                    observer(entity, old, new);
                 }
             }
+            (None, None) => (),
         }
-        None
     }
 }
 
-struct TypedMoveIter<'a, T: 'a> {
-    components: &'a EntityMap<T>,
-    iter: btree_map::Iter<'a, EntityId, EntityId>,
+pub struct FlagObservers {
+    {{internal}}insert: Vec<Box<dyn FnMut(EntityId) + Send + Sync>>,
+    {{internal}}remove: Vec<Box<dyn FnMut(EntityId) + Send + Sync>>,
 }
 
-impl<'a, T: 'a> TypedMoveIter<'a, T> {
-    fn new(components: &'a EntityMap<T>, iter: btree_map::Iter<'a, EntityId, EntityId>) -> Self {
-        TypedMoveIter {
-            components: components,
-            iter: iter,
+impl FlagObservers {
+    {{internal}}fn new() -> Self {
+        FlagObservers {
+            insert: Vec::new(),
+            remove: Vec::new(),
         }
     }
-}
 
-struct TypedMovePositiveIter<'a, T: 'a>(TypedMoveIter<'a, T>);
-
-impl<'a, T: 'a> TypedMovePositiveIter<'a, T> {
-    fn new(components: &'a EntityMap<T>, iter: btree_map::Iter<'a, EntityId, EntityId>) -> Self {
-        TypedMovePositiveIter(TypedMoveIter::new(components, iter))
+    {{internal}}fn changed(&mut self, entity: EntityId, old: bool, new: bool) {
+        let observers = match (old, new) {
+            (false, true) => &mut self.insert,
+            (true, false) => &mut self.remove,
+            _ => return,
+        };
+        for observer in observers.iter_mut() {
+            observer(entity);
+        }
     }
 }
 
-impl<'a, T: 'a> Iterator for TypedMovePositiveIter<'a, T> {
-    type Item = (EntityId, &'a T);
-    fn next(&mut self) -> Option<Self::Item> {
-        while let Some((dest, src)) = self.0.iter.next() {
-            // for all components being moved in
-            if let Some(value) = self.0.components.get(*src) {
-                // if that component has a value
-                return Some((*dest, value));
+{{internal}}struct EcsObservers {
+{{#each component}}
+    {{#if type}}
+        {{#if container}}
+    {{../internal}}{{id}}: ComponentObservers<{{container}}<{{type}}>>,
+        {{else}}
+    {{../internal}}{{id}}: ComponentObservers<{{type}}>,
+        {{/if}}
+    {{else}}
+    {{../internal}}{{id}}: FlagObservers,
+    {{/if}}
+{{/each}}
+}
+
+impl EcsObservers {
+    {{internal}}fn new() -> Self {
+        EcsObservers {
+{{#each component}}
+    {{#if type}}
+            {{id}}: ComponentObservers::new(),
+    {{else}}
+            {{id}}: FlagObservers::new(),
+    {{/if}}
+{{/each}}
+        }
+    }
+}
+
+pub struct EcsCtx {
+{{#each component}}
+    {{#if type}}
+        {{#if container}}
+    {{../internal}}{{id}}: {{storage_type}}<{{container}}<{{type}}>>,
+        {{else}}
+    {{../internal}}{{id}}: {{storage_type}}<{{type}}>,
+        {{/if}}
+    {{else}}
+    {{../internal}}{{id}}: {{storage_type}},
+    {{/if}}
+{{/each}}
+    {{internal}}tracker: EntityMap<ComponentTypeSet>,
+    {{internal}}allocator: EntityIdAllocator,
+    {{internal}}tick: u64,
+    {{internal}}changes: EcsChanges,
+    {{internal}}observers: EcsObservers,
+}
+
+impl EcsCtx {
+    pub fn new() -> Self {
+        EcsCtx {
+{{#each component}}
+            {{id}}: {{storage_type}}::new(),
+{{/each}}
+            tracker: EntityMap::new(),
+            allocator: EntityIdAllocator::new(),
+            tick: 0,
+            changes: EcsChanges::new(),
+            observers: EcsObservers::new(),
+        }
+    }
+
+    // Returns a tick which changes made after this call are newer than.
+    // Changes are stamped with the tick current at the time they are made,
+    // so passing the returned tick to the `_since` methods later yields the
+    // entities changed in between.
+    pub fn change_tick(&mut self) -> u64 {
+        let tick = self.tick;
+        self.tick += 1;
+
+        tick
+    }
+
+    // Discards the record of changes which the `_since` methods wouldn't
+    // report when given `tick`. Nothing else discards changes, other than
+    // removing an entity (which keeps only its removals), so callers of the
+    // `_since` methods should call this with the oldest tick they still need,
+    // or the record grows with every change made.
+    pub fn forget_changes(&mut self, tick: u64) {
+        self.changes.forget(tick);
+    }
+
+    // Returns an id for a new entity. Ids chosen by the caller rather than
+    // returned by spawn are skipped over while their entities have
+    // components, and stay allocated to those entities. Spawned ids must not
+    // otherwise be mixed with ids chosen by the caller, as the allocator can't
+    // tell which ids the caller has in mind for entities without components.
+    pub fn spawn(&mut self) -> EntityId {
+        loop {
+            let entity = self.allocator.allocate();
+            if !self.tracker.contains_key(entity) {
+                return entity;
+            }
+        }
+    }
+
+    pub fn despawn(&mut self, entity: EntityId) -> bool {
+        if self.is_alive(entity) {
+            self.remove_entity(entity);
+            true
+        } else {
+            false
+        }
+    }
+
+    pub fn is_alive(&self, entity: EntityId) -> bool {
+        self.allocator.is_alive(entity)
+    }
+
+{{#each component}}
+    {{#if type}}
+
+        {{#if container}}
+    pub fn bare_insert_{{id}}(&mut self, entity: EntityId, value: {{container}}<{{type}}>) {
+        let present = self.{{id}}.contains_key(entity);
+        self.{{id}}.insert(entity, value);
+        self.changes.{{id}}.insert(entity, self.tick, present);
+        self.tracker.entry(entity).or_insert_with(ComponentTypeSet::new).insert_{{id}}();
+    }
+
+    pub fn bare_remove_{{id}}(&mut self, entity: EntityId) -> Option<{{container}}<{{type}}>> {
+        let ret = self.{{id}}.remove(entity);
+        if ret.is_some() {
+            self.changes.{{id}}.remove(entity, self.tick);
+        }
+        let empty = self.tracker.get_mut(entity).map(|set| {
+            set.remove_{{id}}();
+            set.is_empty()
+        });
+        if let Some(true) = empty {
+            self.tracker.remove(entity);
+        }
+
+        ret
+    }
+        {{/if}}
+
+    pub fn insert_{{id}}(&mut self, entity: EntityId, value: {{type}}) {
+{{~> ctx_insert}}
+        {{#if container}}
+        self.bare_insert_{{id}}(entity, {{container}}::new(value));
+        {{else}}
+        let present = self.{{id}}.contains_key(entity);
+        self.{{id}}.insert(entity, value);
+        self.changes.{{id}}.insert(entity, self.tick, present);
+        {{/if}}
+        self.tracker.entry(entity).or_insert_with(ComponentTypeSet::new).insert_{{id}}();
+    }
+
+    pub fn contains_{{id}}(&self, entity: EntityId) -> bool {
+        self.{{id}}.contains_key(entity)
+    }
+
+        {{#if copy}}
+    pub fn {{id}}(&self, entity: EntityId) -> Option<{{type}}> {
+        self.{{id}}.get(entity).map(|r| *r)
+    }
+    pub fn {{id}}_ref(&self, entity: EntityId) -> Option<&{{type}}> {
+        self.{{id}}.get(entity)
+    }
+        {{else}}
+            {{#if container}}
+    pub fn {{id}}(&self, entity: EntityId) -> Option<&{{container}}<{{type}}>> {
+        self.{{id}}.get(entity)
+    }
+                {{#if RefCell}}
+    pub fn {{id}}_borrow(&self, entity: EntityId) -> Option<Ref<{{type}}>> {
+        self.{{id}}.get(entity).map(|e| e.borrow())
+    }
+    pub fn {{id}}_borrow_mut(&self, entity: EntityId) -> Option<RefMut<{{type}}>> {
+        self.{{id}}.get(entity).map(|e| e.borrow_mut())
+    }
+                {{/if}}
+                {{#if UnsafeCell}}
+    pub fn {{id}}_unsafe_get_mut(&self, entity: EntityId) -> Option<*mut {{type}}> {
+        self.{{id}}.get(entity).map(|e| e.get())
+    }
+    pub fn {{id}}_unsafe_get(&self, entity: EntityId) -> Option<*const {{type}}> {
+        self.{{id}}.get(entity).map(|e| e.get() as *const {{type}})
+    }
+                {{/if}}
+            {{else}}
+    pub fn {{id}}(&self, entity: EntityId) -> Option<&{{type}}> {
+        self.{{id}}.get(entity)
+    }
+            {{/if}}
+        {{/if}}
+
+        {{#if container}}
+    pub fn {{id}}_mut(&mut self, entity: EntityId) -> Option<&mut {{container}}<{{type}}>> {
+        if self.{{id}}.contains_key(entity) {
+            self.changes.{{id}}.modify(entity, self.tick);
+        }
+        self.{{id}}.get_mut(entity)
+    }
+        {{else}}
+    pub fn {{id}}_mut(&mut self, entity: EntityId) -> Option<&mut {{type}}> {
+        if self.{{id}}.contains_key(entity) {
+            self.changes.{{id}}.modify(entity, self.tick);
+        }
+        self.{{id}}.get_mut(entity)
+    }
+        {{/if}}
+    {{else}}
+    pub fn insert_{{id}}(&mut self, entity: EntityId) {
+{{~> ctx_insert}}
+        let present = self.{{id}}.contains(entity);
+        self.{{id}}.insert(entity);
+        self.changes.{{id}}.insert(entity, self.tick, present);
+        self.tracker.entry(entity).or_insert_with(ComponentTypeSet::new).insert_{{id}}();
+    }
+
+    pub fn contains_{{id}}(&self, entity: EntityId) -> bool {
+        self.{{id}}.contains(entity)
+    }
+    {{/if}}
+
+    {{#if container}}
+    pub fn remove_{{id}}(&mut self, entity: EntityId) -> Option<{{type}}> {
+        self.bare_remove_{{id}}(entity).map(|c| c.into_inner())
+    }
+    {{else}}
+    pub fn remove_{{id}}(&mut self, entity: EntityId)
+        {{#if type}}
+        -> Option<{{type}}>
+        {{else}}
+        -> bool
+        {{/if}}
+    {
+        let ret = self.{{id}}.remove(entity);
+        {{#if type}}
+        if ret.is_some() {
+        {{else}}
+        if ret {
+        {{/if}}
+            self.changes.{{id}}.remove(entity, self.tick);
+        }
+        let empty = self.tracker.get_mut(entity).map(|set| {
+            set.remove_{{id}}();
+            set.is_empty()
+        });
+        if let Some(true) = empty {
+            self.tracker.remove(entity);
+        }
+
+        ret
+    }
+    {{/if}}
+
+    pub fn remove_{{id}}_into(&mut self, entity: EntityId, action: &mut EcsAction) {
+    {{#if type}}
+        self.remove_{{id}}(entity).map(|component| {
+            action.insert_{{id}}(entity, component);
+        });
+    {{else}}
+        if self.remove_{{id}}(entity) {
+            action.insert_{{id}}(entity);
+        }
+    {{/if}}
+    }
+
+    pub fn move_{{id}}(&mut self, src: EntityId, dst: EntityId) {
+    {{#if type}}
+        {{#if container}}
+        self.bare_remove_{{id}}(src).map(|x| {
+            self.bare_insert_{{id}}(dst, x);
+        });
+        {{else}}
+        self.remove_{{id}}(src).map(|x| {
+            self.insert_{{id}}(dst, x);
+        });
+        {{/if}}
+    {{else}}
+        if self.remove_{{id}}(src) {
+            self.insert_{{id}}(dst);
+        }
+    {{/if}}
+    }
+
+    pub fn swap_{{id}}(&mut self, a: EntityId, b: EntityId) {
+    {{#if type}}
+        {{#if container}}
+
+        let a_component = self.bare_remove_{{id}}(a);
+        let b_component = self.bare_remove_{{id}}(b);
+
+        if let Some(a_component) = a_component {
+            self.bare_insert_{{id}}(b, a_component);
+        }
+
+        if let Some(b_component) = b_component {
+            self.bare_insert_{{id}}(a, b_component);
+        }
+
+        {{else}}
+
+        let a_component = self.remove_{{id}}(a);
+        let b_component = self.remove_{{id}}(b);
+
+        if let Some(a_component) = a_component {
+            self.insert_{{id}}(b, a_component);
+        }
+
+        if let Some(b_component) = b_component {
+            self.insert_{{id}}(a, b_component);
+        }
+
+        {{/if}}
+    {{else}}
+        let a_contains = self.contains_{{id}}(a);
+
+        if self.contains_{{id}}(b) {
+            self.insert_{{id}}(a);
+        } else {
+            self.remove_{{id}}(a);
+        }
+
+        if a_contains {
+            self.insert_{{id}}(b);
+        } else {
+            self.remove_{{id}}(b);
+        }
+    {{/if}}
+    }
+{{/each}}
+
+    pub fn remove_component(&mut self, entity: EntityId, component_type: ComponentType) {
+        match component_type {
+{{#each component}}
+            component_type::{{id_uppercase}} => { self.remove_{{id}}(entity); }
+{{/each}}
+            _ => { panic!("Invalid component type: {}", component_type); }
+        }
+    }
+
+    pub fn remove_components(&mut self, entity: EntityId, component_type_set: ComponentTypeSet) {
+        for component_type in component_type_set.iter() {
+            self.remove_component(entity, component_type);
+        }
+    }
+
+    pub fn remove_entity(&mut self, entity: EntityId) {
+        if let Some(set) = self.tracker.remove(entity) {
+            for component_type in set.iter() {
+                self.remove_component(entity, component_type);
+            }
+        }
+        self.changes.prune(entity);
+        self.allocator.free(entity);
+    }
+
+    pub fn entity(&self, id: EntityId) -> EntityRef {
+        EntityRef::new(id, self)
+    }
+
+    pub fn entity_mut(&mut self, id: EntityId) -> EntityRefMut {
+        EntityRefMut::new(id, self)
+    }
+
+    pub fn post_action_entity<'a>(&'a self, id: EntityId, action: &'a EcsAction) -> PostActionEntityRef<'a> {
+        PostActionEntityRef::new(id, self, action)
+    }
+
+
+    {{internal}}fn allocate_reserved(&mut self, action: &mut EcsAction) -> Vec<EntityId> {
+        let reserved = (0..action.num_reserved_entities).map(|_| self.spawn()).collect();
+        action.num_reserved_entities = 0;
+
+        reserved
+    }
+
+    // Checks that the outcome of committing an action doesn't depend on the
+    // order in which commit applies its changes, and that each of its moves
+    // has a component to move. Ordered actions apply their changes in the
+    // order they were recorded, so can't be order dependent.
+    pub fn validate(&self, action: &EcsAction) -> Result<(), Vec<ActionConflict>> {
+        let mut conflicts = Vec::new();
+
+{{#each component}}
+        if action.changed_components.contains_{{id}}() {
+            let operations = action.{{id}}.operations();
+            let mut changes: BTreeMap<EntityId, usize> = BTreeMap::new();
+            for operation in operations.iter() {
+                match *operation {
+                    Operation::Insert(id, _) | Operation::Remove(id) => {
+                        *changes.entry(id).or_insert(0) += 1;
+                    }
+                    Operation::Swap(a, b) | Operation::Move(a, b) => {
+                        // swapping or moving a component with itself
+                        // changes one entity
+                        *changes.entry(a).or_insert(0) += 1;
+                        if b != a {
+                            *changes.entry(b).or_insert(0) += 1;
+                        }
+                    }
+                }
+            }
+
+            if !action.ordered {
+                for (&entity, &count) in changes.iter() {
+                    if count > 1 {
+                        conflicts.push(ActionConflict::OrderDependent {
+                            entity: entity,
+                            component_type: component_type::{{id_uppercase}},
+                        });
+                    }
+                }
+            }
+            for operation in operations.iter() {
+                if let Operation::Move(src, _) = *operation {
+                    if changes[&src] == 1 && !self.contains_{{id}}(src) {
+                        conflicts.push(ActionConflict::MissingSource {
+                            entity: src,
+                            component_type: component_type::{{id_uppercase}},
+                        });
+                    }
+                }
+            }
+        }
+{{/each}}
+
+        if conflicts.is_empty() {
+            Ok(())
+        } else {
+            Err(conflicts)
+        }
+    }
+
+    // Commits the action only if it is valid. An invalid action is left
+    // unchanged.
+    pub fn try_commit(&mut self, action: &mut EcsAction) -> Result<Vec<EntityId>, Vec<ActionConflict>> {
+        self.validate(action)?;
+
+        Ok(self.commit(action))
+    }
+
+    // Returns the ids allocated for the entities reserved by the action, in
+    // the order they were reserved
+    pub fn commit(&mut self, action: &mut EcsAction) -> Vec<EntityId> {
+        let reserved = self.allocate_reserved(action);
+
+{{#each component}}
+        if action.changed_components.contains_{{id}}() {
+            self.commit_{{id}}(&mut action.{{id}}, &reserved, None{{#if type}}, None{{/if}});
+        }
+{{/each}}
+
+        action.changed_components.clear();
+        action.properties.clear();
+
+        reserved
+    }
+
+    pub fn commit_into(&mut self, from: &mut EcsAction, to: &mut EcsAction) -> Vec<EntityId> {
+        let reserved = self.allocate_reserved(from);
+
+{{#each component}}
+        if from.changed_components.contains_{{id}}() {
+            self.commit_{{id}}(&mut from.{{id}}, &reserved, Some(to){{#if type}}, None{{/if}});
+        }
+{{/each}}
+
+        from.changed_components.clear();
+        from.properties.clear();
+
+        reserved
+    }
+
+    // Commits an action, and fills `inverse` with an action which, when
+    // committed, restores every component the action changed to its prior
+    // state. Entities allocated for ids reserved by the action stay alive.
+    pub fn commit_with_inverse(&mut self, action: &mut EcsAction, inverse: &mut EcsAction) -> Vec<EntityId> {
+        let reserved = self.allocate_reserved(action);
+
+{{#each component}}
+
+        if action.changed_components.contains_{{id}}() {
+
+            let mut values = ValueTracker::new();
+            for id in action.{{id}}.changed_entities.iter() {
+                let id = resolve_entity(&reserved, id);
+                values.touch(id, self.contains_{{id}}(id));
+            }
+
+    {{#if type}}
+            // the values are kept as the commit discards them, so observers
+            // see the same changes as with any other commit
+            let mut discarded = DiscardedValues::new(values);
+            self.commit_{{id}}(&mut action.{{id}}, &reserved, None, Some(&mut discarded));
+
+            let plan = discarded.tracker.restore_plan();
+            for (position, owner) in plan.restorations {
+                match discarded.values.remove(&owner) {
+                    Some(component) => inverse.insert_{{id}}(position, component),
+                    None => inverse.remove_{{id}}(position),
+                }
+            }
+    {{else}}
+            for &operation in action.{{id}}.operations() {
+                match operation {
+                    Operation::Insert(id, _) => {
+                        values.insert(resolve_entity(&reserved, id));
+                    }
+                    Operation::Remove(id) => {
+                        values.remove(resolve_entity(&reserved, id));
+                    }
+                    Operation::Swap(a, b) => values.swap(resolve_entity(&reserved, a), resolve_entity(&reserved, b)),
+                    Operation::Move(src, dst) => {
+                        values.mv(resolve_entity(&reserved, src), resolve_entity(&reserved, dst));
+                    }
+                }
+            }
+
+            self.commit_{{id}}(&mut action.{{id}}, &reserved, None);
+
+            let plan = values.restore_plan();
+            for (position, owner) in plan.restorations {
+                if values.discarded.contains(&owner) {
+                    inverse.insert_{{id}}(position);
+                } else {
+                    inverse.remove_{{id}}(position);
+                }
+            }
+    {{/if}}
+            for (a, b) in plan.swaps {
+                inverse.swap_{{id}}(a, b);
+            }
+        }
+{{/each}}
+
+        action.changed_components.clear();
+        action.properties.clear();
+
+        reserved
+    }
+
+{{#each component}}
+    // Applies the changes an action makes to a single component, notifying
+    // its observers of each change, and optionally recording the removed
+    // components in another action, or keeping the values the action
+    // discards.
+    {{#if type}}
+    {{../internal}}fn commit_{{id}}(&mut self, profile: &mut TypedActionProfile<{{type}}>, reserved: &[EntityId],
+                      mut removed_into: Option<&mut EcsAction>, mut discarded: Option<&mut DiscardedValues<{{type}}>>) {
+        for operation in profile.operations().to_vec() {
+            match operation {
+                Operation::Insert(id, superseded) => {
+                    let component = match profile.take_insertion(id, superseded) {
+                        Some(component) => component,
+                        None => continue,
+                    };
+                    let id = resolve_entity(reserved, id);
+                    let owner = discarded.as_mut().and_then(|discarded| discarded.tracker.insert(id));
+                    if self.observers.{{id}}.is_empty() && owner.is_none() {
+                        self.insert_{{id}}(id, component);
+                    } else {
+                        let old = self.{{#if container}}bare_{{/if}}remove_{{id}}(id);
+                        self.insert_{{id}}(id, component);
+                        self.observers.{{id}}.changed(id, old.as_ref(), self.{{id}}.get(id));
+                        if let (Some(discarded), Some(old)) = (discarded.as_mut(), old) {
+                            discarded.keep(owner, old{{#if container}}.into_inner(){{/if}});
+                        }
+                    }
+                }
+                Operation::Remove(id) => {
+                    let id = resolve_entity(reserved, id);
+                    let owner = discarded.as_mut().and_then(|discarded| discarded.tracker.remove(id));
+                    if let Some(component) = self.{{#if container}}bare_{{/if}}remove_{{id}}(id) {
+                        self.observers.{{id}}.changed(id, Some(&component), None);
+                        if let Some(ref mut action) = removed_into {
+                            action.insert_{{id}}(id, component{{#if container}}.into_inner(){{/if}});
+                        } else if let Some(ref mut discarded) = discarded {
+                            discarded.keep(owner, component{{#if container}}.into_inner(){{/if}});
+                        }
+                    }
+                }
+                Operation::Swap(a, b) => {
+                    let (a, b) = (resolve_entity(reserved, a), resolve_entity(reserved, b));
+                    if let Some(ref mut discarded) = discarded {
+                        discarded.tracker.swap(a, b);
+                    }
+                    self.swap_{{id}}(a, b);
+                    if a != b {
+                        let (at_a, at_b) = (self.{{id}}.get(a), self.{{id}}.get(b));
+                        self.observers.{{id}}.changed(a, at_b, at_a);
+                        self.observers.{{id}}.changed(b, at_a, at_b);
+                    }
+                }
+                Operation::Move(src, dst) => {
+                    let (src, dst) = (resolve_entity(reserved, src), resolve_entity(reserved, dst));
+                    let owner = discarded.as_mut().and_then(|discarded| discarded.tracker.mv(src, dst));
+                    if (self.observers.{{id}}.is_empty() && owner.is_none()) || src == dst {
+                        self.move_{{id}}(src, dst);
+                    } else if let Some(component) = self.{{#if container}}bare_{{/if}}remove_{{id}}(src) {
+                        self.observers.{{id}}.changed(src, Some(&component), None);
+                        let old = self.{{#if container}}bare_{{/if}}remove_{{id}}(dst);
+                        self.{{#if container}}bare_{{/if}}insert_{{id}}(dst, component);
+                        self.observers.{{id}}.changed(dst, old.as_ref(), self.{{id}}.get(dst));
+                        if let (Some(discarded), Some(old)) = (discarded.as_mut(), old) {
+                            discarded.keep(owner, old{{#if container}}.into_inner(){{/if}});
+                        }
+                    }
+                }
+            }
+        }
+
+        profile.clear();
+    }
+    {{else}}
+    {{../internal}}fn commit_{{id}}(&mut self, profile: &mut FlagActionProfile, reserved: &[EntityId], mut removed_into: Option<&mut EcsAction>) {
+        for &operation in profile.operations() {
+            match operation {
+                Operation::Insert(id, _) => {
+                    let id = resolve_entity(reserved, id);
+                    let old = self.contains_{{id}}(id);
+                    self.insert_{{id}}(id);
+                    self.observers.{{id}}.changed(id, old, true);
+                }
+                Operation::Remove(id) => {
+                    let id = resolve_entity(reserved, id);
+                    if self.remove_{{id}}(id) {
+                        self.observers.{{id}}.changed(id, true, false);
+                        if let Some(ref mut action) = removed_into {
+                            action.insert_{{id}}(id);
+                        }
+                    }
+                }
+                Operation::Swap(a, b) => {
+                    let (a, b) = (resolve_entity(reserved, a), resolve_entity(reserved, b));
+                    self.swap_{{id}}(a, b);
+                    if a != b {
+                        let (at_a, at_b) = (self.contains_{{id}}(a), self.contains_{{id}}(b));
+                        self.observers.{{id}}.changed(a, at_b, at_a);
+                        self.observers.{{id}}.changed(b, at_a, at_b);
+                    }
+                }
+                Operation::Move(src, dst) => {
+                    let (src, dst) = (resolve_entity(reserved, src), resolve_entity(reserved, dst));
+                    let (had_src, had_dst) = (self.contains_{{id}}(src), self.contains_{{id}}(dst));
+                    self.move_{{id}}(src, dst);
+                    if src != dst {
+                        let (has_src, has_dst) = (self.contains_{{id}}(src), self.contains_{{id}}(dst));
+                        self.observers.{{id}}.changed(src, had_src, has_src);
+                        self.observers.{{id}}.changed(dst, had_dst, has_dst);
+                    }
+                }
+            }
+        }
+
+        profile.clear();
+    }
+    {{/if}}
+
+{{/each}}
+    // Commits each action in turn. Applied to a snapshot of a ctx, the
+    // actions committed to it since the snapshot was taken reproduce its
+    // current state, including the ids allocated to reserved entities.
+    pub fn replay<I: IntoIterator<Item=EcsAction>>(&mut self, actions: I) {
+        for mut action in actions {
+            self.commit(&mut action);
+        }
+    }
+
+    pub fn entity_iter<I: Iterator<Item=EntityId>>(&self, iter: I) -> EntityRefIter<I> {
+        EntityRefIter::new(self, iter)
+    }
+
+    pub fn query(&self) -> Query<QueryId> {
+        Query::new(self)
+    }
+
+{{#each component}}
+    {{#if type}}
+        {{#if container}}
+    pub fn on_insert_{{id}}<F: FnMut(EntityId, &{{container}}<{{type}}>) + Send + Sync + 'static>(&mut self, observer: F) {
+        self.observers.{{id}}.insert.push(Box::new(observer));
+    }
+    pub fn on_remove_{{id}}<F: FnMut(EntityId, &{{container}}<{{type}}>) + Send + Sync + 'static>(&mut self, observer: F) {
+        self.observers.{{id}}.remove.push(Box::new(observer));
+    }
+    pub fn on_replace_{{id}}<F: FnMut(EntityId, &{{container}}<{{type}}>, &{{container}}<{{type}}>) + Send + Sync + 'static>(&mut self, observer: F) {
+        self.observers.{{id}}.replace.push(Box::new(observer));
+    }
+        {{else}}
+    pub fn on_insert_{{id}}<F: FnMut(EntityId, &{{type}}) + Send + Sync + 'static>(&mut self, observer: F) {
+        self.observers.{{id}}.insert.push(Box::new(observer));
+    }
+    pub fn on_remove_{{id}}<F: FnMut(EntityId, &{{type}}) + Send + Sync + 'static>(&mut self, observer: F) {
+        self.observers.{{id}}.remove.push(Box::new(observer));
+    }
+    pub fn on_replace_{{id}}<F: FnMut(EntityId, &{{type}}, &{{type}}) + Send + Sync + 'static>(&mut self, observer: F) {
+        self.observers.{{id}}.replace.push(Box::new(observer));
+    }
+        {{/if}}
+    {{else}}
+    pub fn on_insert_{{id}}<F: FnMut(EntityId) + Send + Sync + 'static>(&mut self, observer: F) {
+        self.observers.{{id}}.insert.push(Box::new(observer));
+    }
+    pub fn on_remove_{{id}}<F: FnMut(EntityId) + Send + Sync + 'static>(&mut self, observer: F) {
+        self.observers.{{id}}.remove.push(Box::new(observer));
+    }
+    {{/if}}
+
+    // The entities changed since a tick returned by change_tick. Changes are
+    // recorded until forgotten with forget_changes.
+    pub fn {{id}}_changed_since(&self, tick: u64) -> ChangeIter {
+        self.changes.{{id}}.changed.since(tick, &self.{{id}}, true)
+    }
+
+    pub fn {{id}}_added_since(&self, tick: u64) -> ChangeIter {
+        self.changes.{{id}}.added.since(tick, &self.{{id}}, true)
+    }
+
+    pub fn {{id}}_removed_since(&self, tick: u64) -> ChangeIter {
+        self.changes.{{id}}.removed.since(tick, &self.{{id}}, false)
+    }
+
+{{/each}}
+    pub fn contains_component(&self, entity: EntityId, component_type: ComponentType) -> bool {
+        component_collection(self, component_type).contains(entity)
+    }
+
+    pub fn component_ids<'a>(&'a self, component_type: ComponentType) -> Box<dyn Iterator<Item=EntityId> + 'a> {
+        component_collection(self, component_type).ids()
+    }
+
+{{#each component}}
+    {{#if type}}
+
+    pub fn {{id}}_id_iter(&self) ->
+        {{#if container}}
+            {{#if RefCell}}
+        {{storage_type}}Keys<RefCell<{{type}}>>
+            {{/if}}
+            {{#if UnsafeCell}}
+        {{storage_type}}Keys<UnsafeCell<{{type}}>>
+            {{/if}}
+        {{else}}
+        {{storage_type}}Keys<{{type}}>
+        {{/if}}
+
+    {
+        self.{{id}}.keys()
+    }
+
+    pub fn {{id}}_iter(&self) ->
+        {{#if container}}
+            {{#if RefCell}}
+        {{storage_type}}Iter<RefCell<{{type}}>>
+            {{/if}}
+            {{#if UnsafeCell}}
+        {{storage_type}}Iter<UnsafeCell<{{type}}>>
+            {{/if}}
+        {{else}}
+            {{#if copy}}
+        {{storage_type}}CopyIter<{{type}}>
+            {{else}}
+        {{storage_type}}Iter<{{type}}>
+            {{/if}}
+        {{/if}}
+
+    {
+        {{#if copy}}
+            self.{{id}}.copy_iter()
+        {{else}}
+            self.{{id}}.iter()
+        {{/if}}
+    }
+
+    {{else}}
+
+    pub fn {{id}}_id_iter(&self) -> {{storage_type}}Iter {
+        self.{{id}}.iter()
+    }
+
+    {{/if}}
+
+{{/each}}
+
+    pub fn clear(&mut self) {
+{{#each component}}
+        self.{{id}}.clear();
+{{/each}}
+        self.tracker.clear();
+        self.allocator.clear();
+        self.changes.clear();
+    }
+{{~> ctx}}
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct SerializableEcsCtx {
+{{#each component}}
+    {{#if type}}
+    {{../internal}}{{id}}: EntityMap<{{type}}>,
+    {{else}}
+    {{../internal}}{{id}}: EntitySet,
+    {{/if}}
+{{/each}}
+    {{internal}}tracker: EntityMap<ComponentTypeSet>,
+    #[serde(default)]
+    {{internal}}allocator: EntityIdAllocator,
+}
+
+impl From<SerializableEcsCtx> for EcsCtx {
+    fn from(ecs: SerializableEcsCtx) -> Self {
+        let SerializableEcsCtx {
+{{#each component}}
+    {{#if container}}
+            mut {{id}},
+    {{else}}
+        {{#if type}}
+            {{#if btree}}
+            {{id}},
+            {{else}}
+            mut {{id}},
+            {{/if}}
+        {{else}}
+            {{id}},
+        {{/if}}
+    {{/if}}
+{{/each}}
+            tracker,
+            allocator,
+        } = ecs;
+
+        EcsCtx {
+{{#each component}}
+    {{#if type}}
+        {{#if container}}
+            {{#if RefCell}}
+            {{id}}: {
+                let mut map = {{storage_type}}::new();
+                let keys: Vec<EntityId> = {{id}}.keys().collect();
+                for key in keys {
+                    if let Some(value) = {{id}}.remove(key) {
+                        map.insert(key, RefCell::new(value));
+                    }
+                }
+
+                map
+            },
+            {{/if}}
+            {{#if UnsafeCell}}
+            {{id}}: {
+                let mut map = {{storage_type}}::new();
+                let keys: Vec<EntityId> = {{id}}.keys().collect();
+                for key in keys {
+                    if let Some(value) = {{id}}.remove(key) {
+                        map.insert(key, UnsafeCell::new(value));
+                    }
+                }
+
+                map
+            },
+            {{/if}}
+        {{else}}
+            {{#if btree}}
+            {{id}}: {{id}},
+            {{else}}
+            {{id}}: {
+                let mut map = {{storage_type}}::new();
+                let keys: Vec<EntityId> = {{id}}.keys().collect();
+                for key in keys {
+                    if let Some(value) = {{id}}.remove(key) {
+                        map.insert(key, value);
+                    }
+                }
+
+                map
+            },
+            {{/if}}
+        {{/if}}
+    {{else}}
+        {{#if btree}}
+            {{id}}: {{id}},
+        {{else}}
+            {{id}}: {{id}}.iter().collect(),
+        {{/if}}
+    {{/if}}
+{{/each}}
+            tracker: tracker,
+            allocator: allocator,
+            tick: 0,
+            changes: EcsChanges::new(),
+            observers: EcsObservers::new(),
+        }
+    }
+}
+
+impl From<EcsCtx> for SerializableEcsCtx {
+    fn from(ecs: EcsCtx) -> Self {
+        let EcsCtx {
+{{#each component}}
+    {{#if container}}
+            mut {{id}},
+    {{else}}
+        {{#if type}}
+            {{#if btree}}
+            {{id}},
+            {{else}}
+            mut {{id}},
+            {{/if}}
+        {{else}}
+            {{id}},
+        {{/if}}
+    {{/if}}
+{{/each}}
+            tracker,
+            allocator,
+            ..
+        } = ecs;
+
+        SerializableEcsCtx {
+{{#each component}}
+    {{#if type}}
+        {{#if container}}
+            {{#if RefCell}}
+            {{id}}: {
+                let mut map = EntityMap::new();
+                let keys: Vec<EntityId> = {{id}}.keys().collect();
+                for key in keys {
+                    if let Some(cell) = {{id}}.remove(key) {
+                        map.insert(key, cell.into_inner());
+                    }
+                }
+
+                map
+            },
+            {{/if}}
+            {{#if UnsafeCell}}
+            {{id}}: {
+                let mut map = EntityMap::new();
+                let keys: Vec<EntityId> = {{id}}.keys().collect();
+                for key in keys {
+                    if let Some(cell) = {{id}}.remove(key) {
+                        let ptr = cell.get();
+                        assert!(!ptr.is_null(), "Attempt to serialize null UnsafeCell");
+                        map.insert(key, unsafe { cell.into_inner() });
+                    }
+                }
+
+                map
+            },
+            {{/if}}
+        {{else}}
+            {{#if btree}}
+            {{id}}: {{id}},
+            {{else}}
+            {{id}}: {
+                let mut map = EntityMap::new();
+                let keys: Vec<EntityId> = {{id}}.keys().collect();
+                for key in keys {
+                    if let Some(value) = {{id}}.remove(key) {
+                        map.insert(key, value);
+                    }
+                }
+
+                map
+            },
+            {{/if}}
+        {{/if}}
+    {{else}}
+        {{#if btree}}
+            {{id}}: {{id}},
+        {{else}}
+            {{id}}: {{id}}.iter().collect(),
+        {{/if}}
+    {{/if}}
+{{/each}}
+            tracker: tracker,
+            allocator: allocator,
+        }
+    }
+}
+
+{{!-- module: entity_ref --}}
+#[derive(Clone, Copy)]
+pub struct EntityRef<'a> {
+    {{internal}}id: EntityId,
+    {{internal}}ctx: &'a EcsCtx,
+}
+
+impl<'a> EntityRef<'a> {
+    {{internal}}fn new(id: EntityId, ctx: &'a EcsCtx) -> Self {
+        EntityRef {
+            id: id,
+            ctx: ctx,
+        }
+    }
+
+    pub fn id(self) -> EntityId {
+        self.id
+    }
+
+    pub fn is_alive(self) -> bool {
+        self.ctx.is_alive(self.id)
+    }
+
+    pub fn is_empty(self) -> bool {
+        if let Some(set) = self.ctx.tracker.get(self.id) {
+            set.is_empty()
+        } else {
+            true
+        }
+    }
+
+{{#each component}}
+    pub fn contains_{{id}}(self) -> bool {
+        self.ctx.contains_{{id}}(self.id)
+    }
+    {{#if type}}
+        {{#if copy}}
+    pub fn {{id}}(self) -> Option<{{type}}> {
+        self.ctx.{{id}}(self.id)
+    }
+    pub fn {{id}}_ref(self) -> Option<&'a {{type}}> {
+        self.ctx.{{id}}_ref(self.id)
+    }
+        {{else}}
+            {{#if container}}
+    pub fn {{id}}(self) -> Option<&'a {{container}}<{{type}}>> {
+        self.ctx.{{id}}(self.id)
+    }
+                {{#if RefCell}}
+    pub fn {{id}}_borrow(self) -> Option<Ref<'a, {{type}}>> {
+        self.ctx.{{id}}_borrow(self.id)
+    }
+    pub fn {{id}}_borrow_mut(self) -> Option<RefMut<'a, {{type}}>> {
+        self.ctx.{{id}}_borrow_mut(self.id)
+    }
+                {{/if}}
+                {{#if UnsafeCell}}
+    pub fn {{id}}_unsafe_get_mut(self) -> Option<*mut {{type}}> {
+        self.ctx.{{id}}_unsafe_get_mut(self.id)
+    }
+    pub fn {{id}}_unsafe_get(self) -> Option<*const {{type}}> {
+        self.ctx.{{id}}_unsafe_get(self.id)
+    }
+                {{/if}}
+            {{else}}
+    pub fn {{id}}(self) -> Option<&'a {{type}}> {
+        self.ctx.{{id}}(self.id)
+    }
+            {{/if}}
+        {{/if}}
+    {{/if}}
+{{/each}}
+{{~> entity_ref}}
+}
+
+pub trait EntityPopulate {
+{{#each component}}
+    {{#if type}}
+    fn insert_{{id}}(&mut self, value: {{type}});
+    {{else}}
+    fn insert_{{id}}(&mut self);
+    {{/if}}
+{{/each}}
+}
+
+pub struct EntityRefMut<'a> {
+    {{internal}}id: EntityId,
+    {{internal}}ctx: &'a mut EcsCtx,
+}
+
+impl<'a> EntityRefMut<'a> {
+    {{internal}}fn new(id: EntityId, ctx: &'a mut EcsCtx) -> Self {
+        EntityRefMut {
+            id: id,
+            ctx: ctx,
+        }
+    }
+
+    pub fn id(&self) -> EntityId {
+        self.id
+    }
+
+    pub fn is_alive(&self) -> bool {
+        self.ctx.is_alive(self.id)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        if let Some(set) = self.ctx.tracker.get(self.id) {
+            set.is_empty()
+        } else {
+            true
+        }
+    }
+
+    pub fn destroy(self) {
+        self.ctx.remove_entity(self.id);
+    }
+{{#each component}}
+    pub fn contains_{{id}}(&self) -> bool {
+        self.ctx.contains_{{id}}(self.id)
+    }
+
+    {{#if type}}
+        {{#if container}}
+    pub fn bare_remove_{{id}}(&mut self) -> Option<{{container}}<{{type}}>> {
+        self.ctx.bare_remove_{{id}}(self.id)
+    }
+        {{/if}}
+    {{/if}}
+
+    pub fn remove_{{id}}(&mut self)
+    {{#if type}}
+        -> Option<{{type}}>
+    {{else}}
+        -> bool
+    {{/if}}
+    {
+        self.ctx.remove_{{id}}(self.id)
+    }
+    {{#if type}}
+        {{#if copy}}
+    pub fn {{id}}(&self) -> Option<{{type}}> {
+        self.ctx.{{id}}(self.id)
+    }
+    pub fn {{id}}_ref(&self) -> Option<&{{type}}> {
+        self.ctx.{{id}}_ref(self.id)
+    }
+        {{else}}
+            {{#if container}}
+    pub fn {{id}}(&self) -> Option<&{{container}}<{{type}}>> {
+        self.ctx.{{id}}(self.id)
+    }
+                {{#if RefCell}}
+    pub fn {{id}}_borrow(&self) -> Option<Ref<{{type}}>> {
+        self.ctx.{{id}}_borrow(self.id)
+    }
+    pub fn {{id}}_borrow_mut(&self) -> Option<RefMut<{{type}}>> {
+        self.ctx.{{id}}_borrow_mut(self.id)
+    }
+                {{/if}}
+                {{#if UnsafeCell}}
+    pub fn {{id}}_unsafe_get_mut(&self) -> Option<*mut {{type}}> {
+        self.ctx.{{id}}_unsafe_get_mut(self.id)
+    }
+    pub fn {{id}}_unsafe_get(&self) -> Option<*const {{type}}> {
+        self.ctx.{{id}}_unsafe_get(self.id)
+    }
+                {{/if}}
+            {{else}}
+    pub fn {{id}}(&self) -> Option<&{{type}}> {
+        self.ctx.{{id}}(self.id)
+    }
+            {{/if}}
+        {{/if}}
+        {{#if container}}
+    pub fn {{id}}_mut(&mut self) -> Option<&mut {{container}}<{{type}}>> {
+        self.ctx.{{id}}_mut(self.id)
+    }
+        {{else}}
+    pub fn {{id}}_mut(&mut self) -> Option<&mut {{type}}> {
+        self.ctx.{{id}}_mut(self.id)
+    }
+        {{/if}}
+    {{/if}}
+{{/each}}
+}
+
+impl<'a> EntityPopulate for EntityRefMut<'a> {
+{{#each component}}
+    {{#if type}}
+    fn insert_{{id}}(&mut self, value: {{type}}) {
+        self.ctx.insert_{{id}}(self.id, value);
+    }
+    {{else}}
+    fn insert_{{id}}(&mut self) {
+        self.ctx.insert_{{id}}(self.id);
+    }
+    {{/if}}
+{{/each}}
+}
+
+impl<'a> EntityPopulate for ActionEntityRefMut<'a> {
+{{#each component}}
+    {{#if type}}
+    fn insert_{{id}}(&mut self, value: {{type}}) {
+        self.action.insert_{{id}}(self.id, value);
+    }
+    {{else}}
+    fn insert_{{id}}(&mut self) {
+        self.action.insert_{{id}}(self.id);
+    }
+    {{/if}}
+{{/each}}
+}
+
+{{!-- module: action --}}
+#[derive(Serialize, Deserialize)]
+{{internal}}struct SwapTable {
+    {{internal}}apply: Vec<(EntityId, EntityId)>,
+}
+
+impl SwapTable {
+    {{internal}}fn new() -> Self {
+        SwapTable {
+            apply: Vec::new(),
+        }
+    }
+    {{internal}}fn clear(&mut self) {
+        self.apply.clear();
+    }
+    {{internal}}fn swap(&mut self, a: EntityId, b: EntityId) {
+        self.apply.push((a, b));
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+{{internal}}struct MoveProfile {
+    {{internal}}source: EntityId,
+    {{internal}}destination: EntityId,
+}
+
+impl MoveProfile {
+    {{internal}}fn new(source: EntityId, destination: EntityId) -> Self {
+        MoveProfile {
+            source: source,
+            destination: destination,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+{{internal}}struct MoveTable {
+    {{internal}}apply: Vec<MoveProfile>,
+}
+
+impl MoveTable {
+    {{internal}}fn new() -> Self {
+        MoveTable {
+            apply: Vec::new(),
+        }
+    }
+    {{internal}}fn clear(&mut self) {
+        self.apply.clear();
+    }
+    {{internal}}fn mv(&mut self, source: EntityId, destination: EntityId) {
+        self.apply.push(MoveProfile::new(source, destination));
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct FlagActionProfile {
+    {{internal}}insertions: EntityHashSet,
+    {{internal}}removals: EntityHashSet,
+    {{internal}}swaps: SwapTable,
+    {{internal}}moves: MoveTable,
+    {{internal}}changed_entities: EntitySet,
+    {{internal}}log: Vec<Operation>,
+    #[serde(skip)]
+    {{internal}}operations: OnceLock<Vec<Operation>>,
+}
+
+impl FlagActionProfile {
+    {{internal}}fn new() -> Self {
+        FlagActionProfile {
+            insertions: EntityHashSet::new(),
+            removals: EntityHashSet::new(),
+            swaps: SwapTable::new(),
+            moves: MoveTable::new(),
+            changed_entities: EntitySet::new(),
+            log: Vec::new(),
+            operations: OnceLock::new(),
+        }
+    }
+
+    // The profile's operations in the order commit applies them: the order
+    // they were recorded in for ordered actions, and otherwise insertions,
+    // then removals, then swaps, then moves. The order of an unordered
+    // profile's operations is worked out when first needed after a change.
+    {{internal}}fn operations(&self) -> &[Operation] {
+        if !self.log.is_empty() {
+            return &self.log;
+        }
+
+        self.operations.get_or_init(|| {
+            let mut operations = Vec::new();
+            operations.extend(self.insertions.iter().map(|id| Operation::Insert(id, None)));
+            operations.extend(self.removals.iter().map(Operation::Remove));
+            operations.extend(self.swaps.apply.iter().map(|&(a, b)| Operation::Swap(a, b)));
+            operations.extend(self.moves.apply.iter().map(|mv| Operation::Move(mv.source, mv.destination)));
+
+            operations
+        })
+    }
+
+    // Notes that the action changes the component of an entity
+    {{internal}}fn touch(&mut self, entity: EntityId) {
+        self.changed_entities.insert(entity);
+        self.operations.take();
+    }
+
+    {{internal}}fn outcome<F: Fn(EntityId) -> bool>(&self, entity: EntityId, has_previous: F) -> Outcome {
+        if !self.changed_entities.contains(entity) {
+            return Outcome::Unchanged(entity);
+        }
+
+        trace_operations(self.operations(), entity, &has_previous)
+    }
+
+    {{internal}}fn log_insertion(&mut self, entity: EntityId) {
+        self.log.push(Operation::Insert(entity, None));
+    }
+
+    // Whether the entity has the flag after the action, given the entities
+    // which have it before the action, or None if the action leaves it as
+    // it was
+    {{internal}}fn change(&self, entity: EntityId, components: &dyn EntityCollection) -> Option<bool> {
+        match self.outcome(entity, |id| components.contains(id)) {
+            Outcome::Unchanged(id) if id == entity => None,
+            Outcome::Unchanged(id) => Some(components.contains(id)),
+            Outcome::Inserted(..) => Some(true),
+            Outcome::Removed => Some(false),
+        }
+    }
+
+    {{internal}}fn positive_iter<'a>(&'a self, components: &'a dyn EntityCollection) -> FlagActionPositiveIter<'a> {
+        FlagActionPositiveIter {
+            profile: self,
+            components: components,
+            entities: self.changed_entities.iter(),
+        }
+    }
+
+    {{internal}}fn negative_iter<'a>(&'a self, components: &'a dyn EntityCollection) -> FlagActionNegativeIter<'a> {
+        FlagActionNegativeIter {
+            profile: self,
+            components: components,
+            entities: self.changed_entities.iter(),
+        }
+    }
+
+    {{internal}}fn clear(&mut self) {
+        self.insertions.clear();
+        self.removals.clear();
+        self.swaps.clear();
+        self.moves.clear();
+        self.changed_entities.clear();
+        self.log.clear();
+        self.operations.take();
+    }
+
+    pub fn insertion_iter(&self) -> EntityHashSetIter {
+        self.insertions.iter()
+    }
+
+    pub fn removal_iter(&self) -> EntityHashSetIter {
+        self.removals.iter()
+    }
+
+    pub fn contains(&self, id: EntityId) -> bool {
+        self.insertions.contains(id)
+    }
+}
+
+// Iterators over the entities whose flag is set or cleared by an action, as
+// reported by PostActionEntityRef::change_{id}
+pub struct FlagActionPositiveIter<'a> {
+    {{internal}}profile: &'a FlagActionProfile,
+    {{internal}}components: &'a dyn EntityCollection,
+    {{internal}}entities: EntitySetIter<'a>,
+}
+
+impl<'a> Iterator for FlagActionPositiveIter<'a> {
+    type Item = EntityId;
+    fn next(&mut self) -> Option<Self::Item> {
+        while let Some(entity) = self.entities.next() {
+            if let Some(true) = self.profile.change(entity, self.components) {
+                return Some(entity);
+            }
+        }
+        None
+    }
+}
+
+pub struct FlagActionNegativeIter<'a> {
+    {{internal}}profile: &'a FlagActionProfile,
+    {{internal}}components: &'a dyn EntityCollection,
+    {{internal}}entities: EntitySetIter<'a>,
+}
+
+impl<'a> Iterator for FlagActionNegativeIter<'a> {
+    type Item = EntityId;
+    fn next(&mut self) -> Option<Self::Item> {
+        while let Some(entity) = self.entities.next() {
+            if let Some(false) = self.profile.change(entity, self.components) {
+                return Some(entity);
+            }
+        }
+        None
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct TypedActionProfile<T> {
+    {{internal}}insertions: EntityHashMap<T>,
+    {{internal}}removals: EntityHashSet,
+    {{internal}}swaps: SwapTable,
+    {{internal}}moves: MoveTable,
+    {{internal}}changed_entities: EntitySet,
+    {{internal}}log: Vec<Operation>,
+    {{internal}}superseded: Vec<Option<T>>,
+    #[serde(skip)]
+    {{internal}}operations: OnceLock<Vec<Operation>>,
+}
+
+impl<T> TypedActionProfile<T> {
+    {{internal}}fn new() -> Self {
+        TypedActionProfile {
+            insertions: EntityHashMap::new(),
+            removals: EntityHashSet::new(),
+            swaps: SwapTable::new(),
+            moves: MoveTable::new(),
+            changed_entities: EntitySet::new(),
+            log: Vec::new(),
+            superseded: Vec::new(),
+            operations: OnceLock::new(),
+        }
+    }
+
+    // The profile's operations in the order commit applies them: the order
+    // they were recorded in for ordered actions, and otherwise insertions,
+    // then removals, then swaps, then moves. The order of an unordered
+    // profile's operations is worked out when first needed after a change.
+    {{internal}}fn operations(&self) -> &[Operation] {
+        if !self.log.is_empty() {
+            return &self.log;
+        }
+
+        self.operations.get_or_init(|| {
+            let mut operations = Vec::new();
+            operations.extend(self.insertions.keys().map(|id| Operation::Insert(id, None)));
+            operations.extend(self.removals.iter().map(Operation::Remove));
+            operations.extend(self.swaps.apply.iter().map(|&(a, b)| Operation::Swap(a, b)));
+            operations.extend(self.moves.apply.iter().map(|mv| Operation::Move(mv.source, mv.destination)));
+
+            operations
+        })
+    }
+
+    // Notes that the action changes the component of an entity
+    {{internal}}fn touch(&mut self, entity: EntityId) {
+        self.changed_entities.insert(entity);
+        self.operations.take();
+    }
+
+    {{internal}}fn outcome<F: Fn(EntityId) -> bool>(&self, entity: EntityId, has_previous: F) -> Outcome {
+        if !self.changed_entities.contains(entity) {
+            return Outcome::Unchanged(entity);
+        }
+
+        trace_operations(self.operations(), entity, &has_previous)
+    }
+
+    {{internal}}fn log_insertion(&mut self, entity: EntityId) {
+        // a value already inserted at the entity may still be moved or
+        // swapped elsewhere by the operations logged after it
+        if let Some(value) = self.insertions.remove(entity) {
+            let index = self.superseded.len();
+            self.superseded.push(Some(value));
+            let previous = self.log.iter().rposition(|operation| match *operation {
+                Operation::Insert(id, None) => id == entity,
+                _ => false,
+            });
+            if let Some(position) = previous {
+                self.log[position] = Operation::Insert(entity, Some(index));
+            }
+        }
+        self.log.push(Operation::Insert(entity, None));
+    }
+
+    {{internal}}fn insertion(&self, entity: EntityId, superseded: Option<usize>) -> Option<&T> {
+        match superseded {
+            Some(index) => self.superseded[index].as_ref(),
+            None => self.insertions.get(entity),
+        }
+    }
+
+    {{internal}}fn take_insertion(&mut self, entity: EntityId, superseded: Option<usize>) -> Option<T> {
+        match superseded {
+            Some(index) => self.superseded[index].take(),
+            None => self.insertions.remove(entity),
+        }
+    }
+
+    pub fn insertion_iter(&self) -> EntityHashMapIter<T> {
+        self.insertions.iter()
+    }
+
+    pub fn removal_iter(&self) -> EntityHashSetIter {
+        self.removals.iter()
+    }
+
+    // The change the action makes to a component, given the components of
+    // the entities before the action
+    {{internal}}fn change<'a>(&'a self, entity: EntityId, components: &'a dyn ComponentMap<T>) -> Option<Change<&'a T>> {
+        match self.outcome(entity, |id| components.contains(id)) {
+            Outcome::Unchanged(id) if id == entity => None,
+            Outcome::Unchanged(id) => Some(match components.get(id) {
+                Some(component) => Change::Insert(component),
+                None => Change::Remove,
+            }),
+            Outcome::Inserted(id, superseded) => self.insertion(id, superseded).map(Change::Insert),
+            Outcome::Removed => Some(Change::Remove),
+        }
+    }
+
+    {{internal}}fn positive_iter<'a>(&'a self, components: &'a dyn ComponentMap<T>) -> TypedActionPositiveIter<'a, T> {
+        TypedActionPositiveIter {
+            profile: self,
+            components: components,
+            entities: self.changed_entities.iter(),
+        }
+    }
+
+    {{internal}}fn negative_iter<'a>(&'a self, components: &'a dyn ComponentMap<T>) -> TypedActionNegativeIter<'a, T> {
+        TypedActionNegativeIter {
+            profile: self,
+            components: components,
+            entities: self.changed_entities.iter(),
+        }
+    }
+
+    {{internal}}fn clear(&mut self) {
+        self.insertions.clear();
+        self.removals.clear();
+        self.swaps.clear();
+        self.moves.clear();
+        self.changed_entities.clear();
+        self.log.clear();
+        self.superseded.clear();
+        self.operations.take();
+    }
+
+    pub fn get(&self, id: EntityId) -> Option<&T> {
+        self.insertions.get(id)
+    }
+
+    pub fn get_mut(&mut self, id: EntityId) -> Option<&mut T> {
+        self.insertions.get_mut(id)
+    }
+
+    pub fn contains(&self, id: EntityId) -> bool {
+        self.insertions.contains_key(id)
+    }
+}
+
+impl<T> TypedActionProfile<T> {
+    // The change the action makes to a component stored in a container,
+    // given the containers of the entities before the action
+    {{internal}}fn container_change<'a, C>(&'a self, entity: EntityId, containers: &'a dyn ComponentMap<C>) -> Option<Change<ContainerComponentRef<'a, C, T>>> {
+        match self.outcome(entity, |id| containers.contains(id)) {
+            Outcome::Unchanged(id) if id == entity => None,
+            Outcome::Unchanged(id) => Some(match containers.get(id) {
+                Some(component) => Change::Insert(ContainerComponentRef::Contained(component)),
+                None => Change::Remove,
+            }),
+            Outcome::Inserted(id, superseded) => {
+                self.insertion(id, superseded).map(|component| Change::Insert(ContainerComponentRef::Raw(component)))
+            }
+            Outcome::Removed => Some(Change::Remove),
+        }
+    }
+
+    {{internal}}fn container_positive_iter<'a, C>(&'a self, containers: &'a dyn ComponentMap<C>) -> ContainerActionPositiveIter<'a, C, T> {
+        ContainerActionPositiveIter {
+            profile: self,
+            containers: containers,
+            entities: self.changed_entities.iter(),
+        }
+    }
+
+    {{internal}}fn container_negative_iter<'a, C>(&'a self, containers: &'a dyn ComponentMap<C>) -> ContainerActionNegativeIter<'a, C, T> {
+        ContainerActionNegativeIter {
+            profile: self,
+            containers: containers,
+            entities: self.changed_entities.iter(),
+        }
+    }
+}
+
+impl<T: Copy> TypedActionProfile<T> {
+    pub fn insertion_copy_iter(&self) -> EntityHashMapCopyIter<T> {
+        self.insertions.copy_iter()
+    }
+
+    pub fn get_copy(&self, id: EntityId) -> Option<T> {
+        self.get(id).map(|r| *r)
+    }
+}
+// Iterators over the entities whose component is inserted or removed by an
+// action, as reported by PostActionEntityRef::change_{id}
+pub struct TypedActionPositiveIter<'a, T: 'a> {
+    {{internal}}profile: &'a TypedActionProfile<T>,
+    {{internal}}components: &'a dyn ComponentMap<T>,
+    {{internal}}entities: EntitySetIter<'a>,
+}
+
+impl<'a, T: 'a> Iterator for TypedActionPositiveIter<'a, T> {
+    type Item = (EntityId, &'a T);
+    fn next(&mut self) -> Option<Self::Item> {
+        while let Some(entity) = self.entities.next() {
+            if let Some(Change::Insert(component)) = self.profile.change(entity, self.components) {
+                return Some((entity, component));
+            }
+        }
+        None
+    }
+}
+
+pub struct TypedActionNegativeIter<'a, T: 'a> {
+    {{internal}}profile: &'a TypedActionProfile<T>,
+    {{internal}}components: &'a dyn ComponentMap<T>,
+    {{internal}}entities: EntitySetIter<'a>,
+}
+
+impl<'a, T: 'a> Iterator for TypedActionNegativeIter<'a, T> {
+    type Item = EntityId;
+    fn next(&mut self) -> Option<Self::Item> {
+        while let Some(entity) = self.entities.next() {
+            if let Some(Change::Remove) = self.profile.change(entity, self.components) {
+                return Some(entity);
+            }
+        }
+        None
+    }
+}
+
+// Iterators over the entities whose container component is inserted or
+// removed by an action, as reported by PostActionEntityRef::change_{id}
+pub struct ContainerActionPositiveIter<'a, C: 'a, T: 'a> {
+    {{internal}}profile: &'a TypedActionProfile<T>,
+    {{internal}}containers: &'a dyn ComponentMap<C>,
+    {{internal}}entities: EntitySetIter<'a>,
+}
+
+impl<'a, C: 'a, T: 'a> Iterator for ContainerActionPositiveIter<'a, C, T> {
+    type Item = (EntityId, ContainerComponentRef<'a, C, T>);
+    fn next(&mut self) -> Option<Self::Item> {
+        while let Some(entity) = self.entities.next() {
+            if let Some(Change::Insert(component)) = self.profile.container_change(entity, self.containers) {
+                return Some((entity, component));
+            }
+        }
+        None
+    }
+}
+
+pub struct ContainerActionNegativeIter<'a, C: 'a, T: 'a> {
+    {{internal}}profile: &'a TypedActionProfile<T>,
+    {{internal}}containers: &'a dyn ComponentMap<C>,
+    {{internal}}entities: EntitySetIter<'a>,
+}
+
+impl<'a, C: 'a, T: 'a> Iterator for ContainerActionNegativeIter<'a, C, T> {
+    type Item = EntityId;
+    fn next(&mut self) -> Option<Self::Item> {
+        while let Some(entity) = self.entities.next() {
+            if let Some(Change::Remove) = self.profile.container_change(entity, self.containers) {
+                return Some(entity);
+            }
+        }
+        None
+    }
+}
+
+// A reason an action can't be committed by EcsCtx::try_commit
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ActionConflict {
+    // the entity's component is changed by several of the action's
+    // insertions, removals, swaps and moves
+    OrderDependent {
+        entity: EntityId,
+        component_type: ComponentType,
+    },
+    // the source of a move doesn't have the component
+    MissingSource {
+        entity: EntityId,
+        component_type: ComponentType,
+    },
+}
+
+impl ::std::fmt::Display for ActionConflict {
+    fn fmt(&self, f: &mut ::std::fmt::Formatter) -> ::std::fmt::Result {
+        match *self {
+            ActionConflict::OrderDependent { entity, component_type } => {
+                write!(f, "{} of entity {} is changed more than once", component_type::name(component_type), entity)
+            }
+            ActionConflict::MissingSource { entity, component_type } => {
+                write!(f, "{} moved from entity {} which doesn't have it", component_type::name(component_type), entity)
+            }
+        }
+    }
+}
+
+// An entity whose component is changed by both of two actions being merged
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MergeConflict {
+    pub entity: EntityId,
+    pub component_type: ComponentType,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct EcsAction {
+
+{{#each component}}
+    {{#if type}}
+    {{../internal}}{{id}}: TypedActionProfile<{{type}}>,
+    {{else}}
+    {{../internal}}{{id}}: FlagActionProfile,
+    {{/if}}
+{{/each}}
+    {{internal}}changed_components: ComponentTypeSet,
+    {{internal}}properties: EcsActionProperties,
+    {{internal}}num_reserved_entities: u32,
+    {{internal}}ordered: bool,
+}
+
+impl Default for EcsAction {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl EcsAction {
+    pub fn new() -> Self {
+        EcsAction {
+{{#each component}}
+    {{#if type}}
+            {{id}}: TypedActionProfile::new(),
+    {{else}}
+            {{id}}: FlagActionProfile::new(),
+    {{/if}}
+{{/each}}
+            changed_components: ComponentTypeSet::new(),
+            properties: EcsActionProperties::new(),
+            num_reserved_entities: 0,
+            ordered: false,
+        }
+    }
+
+    // An action which commits its changes in the order they were recorded,
+    // rather than all insertions, then removals, then swaps, then moves
+    pub fn ordered() -> Self {
+        let mut action = Self::new();
+        action.ordered = true;
+
+        action
+    }
+
+    pub fn is_ordered(&self) -> bool {
+        self.ordered
+    }
+
+    // Commits changes recorded from now on in the order they're recorded.
+    // Changes already recorded keep the order commit would have given them.
+    pub fn set_ordered(&mut self) {
+        if self.ordered {
+            return;
+        }
+{{#each component}}
+        if self.changed_components.contains_{{id}}() {
+            self.{{id}}.log = self.{{id}}.operations().to_vec();
+        }
+{{/each}}
+        self.ordered = true;
+    }
+
+    // Reserves an id for a new entity. Components of the reserved entity
+    // are recorded as normal, and it becomes a real entity with an id from
+    // the EcsCtx's allocator when the action is committed. Reserved ids
+    // stored inside component values are not translated on commit.
+    pub fn reserve_entity(&mut self) -> EntityId {
+        let id = entity_id::new(self.num_reserved_entities, entity_id::RESERVED_GENERATION);
+        self.num_reserved_entities += 1;
+
+        id
+    }
+
+    pub fn is_reserved(&self, id: EntityId) -> bool {
+        entity_id::is_reserved(id) && entity_id::index(id) < self.num_reserved_entities
+    }
+
+    pub fn clear(&mut self) {
+{{#each component}}
+        if self.changed_components.contains_{{id}}() {
+            self.{{id}}.clear();
+        }
+{{/each}}
+        self.changed_components.clear();
+        self.properties.clear();
+        self.num_reserved_entities = 0;
+    }
+
+{{#each component}}
+    {{#if type}}
+    pub fn insert_{{id}}(&mut self, entity: EntityId, value: {{type}}) {
+{{~> action_insert}}
+        if self.ordered {
+            self.{{id}}.log_insertion(entity);
+        }
+        self.{{id}}.insertions.insert(entity, value);
+        self.{{id}}.touch(entity);
+        self.changed_components.insert_{{id}}();
+    }
+    pub fn {{id}}_profile(&self) -> &TypedActionProfile<{{type}}> {
+        &self.{{id}}
+    }
+    pub fn {{id}}_profile_mut(&mut self) -> &mut TypedActionProfile<{{type}}> {
+        &mut self.{{id}}
+    }
+    pub fn {{id}}_mut(&mut self, id: EntityId) -> Option<&mut {{type}}> {
+        self.{{id}}_profile_mut().get_mut(id)
+    }
+    pub fn contains_{{id}}(&self, id: EntityId) -> bool {
+        self.{{id}}_profile().contains(id)
+    }
+        {{#if copy}}
+    pub fn {{id}}(&self, id: EntityId) -> Option<{{type}}> {
+        self.{{id}}_ref(id).map(|r| *r)
+    }
+    pub fn {{id}}_ref(&self, id: EntityId) -> Option<&{{type}}> {
+        self.{{id}}_profile().get(id)
+    }
+        {{else}}
+    pub fn {{id}}(&self, id: EntityId) -> Option<&{{type}}> {
+        self.{{id}}_profile().get(id)
+    }
+        {{/if}}
+        {{#if container}}
+    pub fn {{id}}_positive_iter<'a>(&'a self, ecs: &'a EcsCtx) -> ContainerActionPositiveIter<'a, {{container}}<{{type}}>, {{type}}> {
+        self.{{id}}.container_positive_iter(&ecs.{{id}})
+    }
+    pub fn {{id}}_negative_iter<'a>(&'a self, ecs: &'a EcsCtx) -> ContainerActionNegativeIter<'a, {{container}}<{{type}}>, {{type}}> {
+        self.{{id}}.container_negative_iter(&ecs.{{id}})
+    }
+        {{else}}
+    pub fn {{id}}_positive_iter<'a>(&'a self, ecs: &'a EcsCtx) -> TypedActionPositiveIter<'a, {{type}}> {
+        self.{{id}}.positive_iter(&ecs.{{id}})
+    }
+    pub fn {{id}}_negative_iter<'a>(&'a self, ecs: &'a EcsCtx) -> TypedActionNegativeIter<'a, {{type}}> {
+        self.{{id}}.negative_iter(&ecs.{{id}})
+    }
+        {{/if}}
+    {{else}}
+    pub fn contains_{{id}}(&self, id: EntityId) -> bool {
+        self.{{id}}.contains(id)
+    }
+    pub fn {{id}}_positive_iter<'a>(&'a self, ecs: &'a EcsCtx) -> FlagActionPositiveIter<'a> {
+        self.{{id}}.positive_iter(&ecs.{{id}})
+    }
+    pub fn {{id}}_negative_iter<'a>(&'a self, ecs: &'a EcsCtx) -> FlagActionNegativeIter<'a> {
+        self.{{id}}.negative_iter(&ecs.{{id}})
+    }
+    pub fn insert_{{id}}(&mut self, entity: EntityId) {
+{{~> action_insert}}
+        if self.ordered {
+            self.{{id}}.log_insertion(entity);
+        }
+        self.{{id}}.insertions.insert(entity);
+        self.{{id}}.touch(entity);
+        self.changed_components.insert_{{id}}();
+    }
+    pub fn {{id}}_profile(&self) -> &FlagActionProfile {
+        &self.{{id}}
+    }
+    {{/if}}
+    pub fn remove_{{id}}(&mut self, entity: EntityId) {
+        if self.ordered {
+            self.{{id}}.log.push(Operation::Remove(entity));
+        }
+        self.{{id}}.removals.insert(entity);
+        self.{{id}}.touch(entity);
+        self.changed_components.insert_{{id}}();
+    }
+    pub fn move_{{id}}(&mut self, source: EntityId, destination: EntityId) {
+        if self.ordered {
+            self.{{id}}.log.push(Operation::Move(source, destination));
+        }
+        self.{{id}}.moves.mv(source, destination);
+        self.{{id}}.touch(source);
+        self.{{id}}.touch(destination);
+        self.changed_components.insert_{{id}}();
+    }
+    pub fn swap_{{id}}(&mut self, a: EntityId, b: EntityId) {
+        if self.ordered {
+            self.{{id}}.log.push(Operation::Swap(a, b));
+        }
+        self.{{id}}.swaps.swap(a, b);
+        self.{{id}}.touch(a);
+        self.{{id}}.touch(b);
+        self.changed_components.insert_{{id}}();
+    }
+{{/each}}
+    pub fn remove_entity(&mut self, entity: EntityRef) {
+        self.remove_entity_by_id(entity.id, entity.ctx);
+    }
+    pub fn remove_entity_by_id(&mut self, entity: EntityId, ecs: &EcsCtx) {
+        ecs.tracker.get(entity).map(|components| {
+            for component in components.iter() {
+                self.remove_component(entity, component);
             }
+        });
+    }
+    pub fn remove_component(&mut self, entity: EntityId, component_type: ComponentType) {
+        match component_type {
+{{#each component}}
+            component_type::{{id_uppercase}} => self.remove_{{id}}(entity),
+{{/each}}
+            _ => panic!("Invalid component type: {}", component_type),
         }
-        None
     }
-}
+{{#each action_property}}
+    {{#if type}}
+        {{#if copy}}
+    pub fn {{id}}(&self) -> Option<{{type}}> {
+        self.properties.{{id}}()
+    }
+    pub fn {{id}}_ref(&self) -> Option<&{{type}}> {
+        self.properties.{{id}}_ref()
+    }
+        {{else}}
+    pub fn {{id}}(&self) -> Option<&{{type}}> {
+        self.properties.{{id}}()
+    }
+        {{/if}}
+    pub fn set_{{id}}(&mut self, value: {{type}}) {
+        self.properties.insert_{{id}}(value);
+    }
+    {{else}}
+    pub fn contains_{{id}}(&self) -> bool {
+        self.properties.contains_{{id}}()
+    }
+    pub fn set_{{id}}(&mut self) {
+        self.properties.insert_{{id}}();
+    }
+    {{/if}}
+    pub fn clear_{{id}}(&mut self) ->
+    {{#if type}}
+        Option<{{type}}>
+    {{else}}
+        bool
+    {{/if}}
+    {
+        self.properties.remove_{{id}}()
+    }
+{{/each}}
 
-struct TypedMoveNegativeIter<'a, T: 'a>(TypedMoveIter<'a, T>);
+    // Adds the changes made by `other` to this action. Committing the merged
+    // action has the same effect as committing this action and then `other`,
+    // provided no entity has a component changed by both. If any do, nothing
+    // is merged, and the conflicting entities and components are returned.
+    // Action properties set by `other` replace those set by this action, and
+    // ids reserved by `other` are renumbered to follow those reserved here.
+    // Merging an ordered action makes this action ordered.
+    pub fn merge(&mut self, mut other: EcsAction) -> Result<(), Vec<MergeConflict>> {
+        let conflicts = self.merge_conflicts(&other);
+        if !conflicts.is_empty() {
+            return Err(conflicts);
+        }
 
-impl<'a, T: 'a> TypedMoveNegativeIter<'a, T> {
-    fn new(components: &'a EntityMap<T>, iter: btree_map::Iter<'a, EntityId, EntityId>) -> Self {
-        TypedMoveNegativeIter(TypedMoveIter::new(components, iter))
-    }
-}
+        let offset = self.num_reserved_entities;
+        if other.ordered {
+            self.set_ordered();
+        }
 
-impl<'a, T: 'a> Iterator for TypedMoveNegativeIter<'a, T> {
-    type Item = EntityId;
-    fn next(&mut self) -> Option<Self::Item> {
-        while let Some((src, _)) = self.0.iter.next() {
-            // for all components being moved out
-            if self.0.components.contains_key(*src) {
-                // if the component had a value to begin with
-                return Some(*src);
+{{#each component}}
+        if other.changed_components.contains_{{id}}() {
+            for operation in other.{{id}}.operations().to_vec() {
+                match operation {
+    {{#if type}}
+                    Operation::Insert(id, superseded) => {
+                        if let Some(component) = other.{{id}}.take_insertion(id, superseded) {
+                            self.insert_{{id}}(offset_reserved(id, offset), component);
+                        }
+                    }
+    {{else}}
+                    Operation::Insert(id, _) => self.insert_{{id}}(offset_reserved(id, offset)),
+    {{/if}}
+                    Operation::Remove(id) => self.remove_{{id}}(offset_reserved(id, offset)),
+                    Operation::Swap(a, b) => self.swap_{{id}}(offset_reserved(a, offset), offset_reserved(b, offset)),
+                    Operation::Move(src, dst) => self.move_{{id}}(offset_reserved(src, offset), offset_reserved(dst, offset)),
+                }
             }
         }
-        None
-    }
-}
+{{/each}}
+
+{{#each action_property}}
+    {{#if type}}
+        if let Some(value) = other.properties.remove_{{id}}() {
+            self.properties.insert_{{id}}(value);
+        }
+    {{else}}
+        if other.properties.contains_{{id}}() {
+            self.properties.insert_{{id}}();
+        }
+    {{/if}}
+{{/each}}
 
-// There is no positive swap iterator, as if a value is moving into
-// an entity, it doesn't matter if there was already a value of that
-// component present. When moving a component out of an entity, that
-// component will definitely not be present after the move. With a
-// swap, the presence of a value dependends on the value of the component
-// in the entity being swapped with.
-struct TypedSwapNegativeIter<'a, T: 'a>(TypedMoveIter<'a, T>);
+        self.num_reserved_entities += other.num_reserved_entities;
 
-impl<'a, T: 'a> TypedSwapNegativeIter<'a, T> {
-    fn new(components: &'a EntityMap<T>, iter: btree_map::Iter<'a, EntityId, EntityId>) -> Self {
-        TypedSwapNegativeIter(TypedMoveIter::new(components, iter))
+        Ok(())
     }
-}
 
-impl<'a, T: 'a> Iterator for TypedSwapNegativeIter<'a, T> {
-    type Item = EntityId;
-    fn next(&mut self) -> Option<Self::Item> {
-        while let Some((src, dest)) = self.0.iter.next() {
-            // for all components being moved out
-            if self.0.components.contains_key(*src) {
-                // if the component had a value to begin with
-                if !self.0.components.contains_key(*dest) {
-                    // the entity being swapped with didn't have this component
-                    return Some(*src);
+    // The entities and components which both this action and `other` change
+    pub fn merge_conflicts(&self, other: &EcsAction) -> Vec<MergeConflict> {
+        let offset = self.num_reserved_entities;
+        let mut conflicts = Vec::new();
+
+{{#each component}}
+        if self.changed_components.contains_{{id}}() && other.changed_components.contains_{{id}}() {
+            for id in other.{{id}}.changed_entities.iter() {
+                let id = offset_reserved(id, offset);
+                if self.{{id}}.changed_entities.contains(id) {
+                    conflicts.push(MergeConflict {
+                        entity: id,
+                        component_type: component_type::{{id_uppercase}},
+                    });
                 }
             }
         }
-        None
+{{/each}}
+
+        conflicts
+    }
+
+    pub fn entity(&self, id: EntityId) -> ActionEntityRef {
+        ActionEntityRef::new(id, self)
+    }
+
+    pub fn entity_mut(&mut self, id: EntityId) -> ActionEntityRefMut {
+        ActionEntityRefMut::new(id, self)
     }
+{{~> action}}
 }
 
-struct SwapTable {
-    lookup: BTreeMap<EntityId, EntityId>,
-    apply: Vec<(EntityId, EntityId)>,
+{{!-- module: entity_ref --}}
+#[derive(Clone, Copy)]
+pub enum ContainerComponentRef<'a, ContainerType: 'a, Type: 'a> {
+    Contained(&'a ContainerType),
+    Raw(&'a Type),
 }
 
-impl SwapTable {
-    fn new() -> Self {
-        SwapTable {
-            lookup: BTreeMap::new(),
-            apply: Vec::new(),
+#[derive(Clone, Copy)]
+pub enum Change<T> {
+    Insert(T),
+    Remove,
+}
+
+#[derive(Clone, Copy)]
+pub struct PostActionEntityRef<'a> {
+    {{internal}}id: EntityId,
+    {{internal}}ecs: &'a EcsCtx,
+    {{internal}}action: &'a EcsAction,
+}
+
+impl<'a> PostActionEntityRef<'a> {
+    {{internal}}fn new(id: EntityId, ecs: &'a EcsCtx, action: &'a EcsAction) -> Self {
+        PostActionEntityRef {
+            id: id,
+            ecs: ecs,
+            action: action,
         }
     }
-    fn clear(&mut self) {
-        self.lookup.clear();
-        self.apply.clear();
-    }
-    fn swap(&mut self, a: EntityId, b: EntityId) {
-        self.lookup.insert(a, b);
-        self.lookup.insert(b, a);
-        self.apply.push((a, b));
+
+    pub fn id(self) -> EntityId {
+        self.id
     }
-    fn swaps_with(&self, entity: EntityId) -> Option<EntityId> {
-        self.lookup.get(&entity).map(|r| *r)
+
+    pub fn to_entity_ref(self) -> EntityRef<'a> {
+        EntityRef::new(self.id, self.ecs)
     }
-    fn typed_positive_iter<'a, T>(&'a self, components: &'a EntityMap<T>) -> TypedMovePositiveIter<'a, T> {
-        TypedMovePositiveIter::new(components, self.lookup.iter())
+
+    pub fn is_alive(self) -> bool {
+        self.ecs.is_alive(self.id) || self.action.is_reserved(self.id)
     }
-    fn typed_negative_iter<'a, T>(&'a self, components: &'a EntityMap<T>) -> TypedSwapNegativeIter<'a, T> {
-        TypedSwapNegativeIter::new(components, self.lookup.iter())
+
+{{#each component}}
+    {{#if type}}
+        {{#if container}}
+    pub fn {{id}}(self) -> Option<ContainerComponentRef<'a, {{container}}<{{type}}>, {{type}}>> {
+        if let Some(change) = self.change_{{id}}() {
+            if let Change::Insert(component) = change {
+                Some(component)
+            } else {
+                None
+            }
+        } else {
+            self.current_{{id}}().map(ContainerComponentRef::Contained)
+        }
     }
-    fn flag_positive_iter<'a>(&'a self, components: &'a EntitySet) -> FlagMovePositiveIter<'a> {
-        FlagMovePositiveIter::new(components, self.lookup.iter())
+    pub fn current_{{id}}(self) -> Option<&'a {{container}}<{{type}}>> {
+        self.ecs.{{id}}(self.id)
     }
-    fn flag_negative_iter<'a>(&'a self, components: &'a EntitySet) -> FlagSwapNegativeIter<'a> {
-        FlagSwapNegativeIter::new(components, self.lookup.iter())
+    pub fn change_{{id}}(self) -> Option<Change<ContainerComponentRef<'a, {{container}}<{{type}}>, {{type}}>>> {
+        self.action.{{id}}.container_change(self.id, &self.ecs.{{id}})
     }
-}
-
-struct MoveProfile {
-    source: EntityId,
-    destination: EntityId,
-}
-
-impl MoveProfile {
-    fn new(source: EntityId, destination: EntityId) -> Self {
-        MoveProfile {
-            source: source,
-            destination: destination,
+        {{else}}
+            {{#if copy}}
+    pub fn {{id}}(self) -> Option<{{type}}> {
+        if let Some(change) = self.change_{{id}}() {
+            if let Change::Insert(component) = change {
+                Some(component)
+            } else {
+                None
+            }
+        } else {
+            self.current_{{id}}()
         }
     }
-}
-
-struct MoveTable {
-    lookup_from: BTreeMap<EntityId, EntityId>,
-    lookup_to: BTreeMap<EntityId, EntityId>,
-    apply: Vec<MoveProfile>,
-}
-
-impl MoveTable {
-    fn new() -> Self {
-        MoveTable {
-            lookup_from: BTreeMap::new(),
-            lookup_to: BTreeMap::new(),
-            apply: Vec::new(),
+    pub fn current_{{id}}(self) -> Option<{{type}}> {
+        self.ecs.{{id}}(self.id)
+    }
+    pub fn change_{{id}}(self) -> Option<Change<{{type}}>> {
+        self.change_{{id}}_ref().map(|change| match change {
+            Change::Insert(component) => Change::Insert(*component),
+            Change::Remove => Change::Remove,
+        })
+    }
+    pub fn {{id}}_ref(self) -> Option<&'a {{type}}> {
+        if let Some(change) = self.change_{{id}}_ref() {
+            if let Change::Insert(component) = change {
+                Some(component)
+            } else {
+                None
+            }
+        } else {
+            self.current_{{id}}_ref()
         }
     }
-    fn moves_in(&self, entity: EntityId) -> Option<EntityId> {
-        self.lookup_to.get(&entity).map(|r| *r)
+    pub fn current_{{id}}_ref(self) -> Option<&'a {{type}}> {
+        self.ecs.{{id}}_ref(self.id)
     }
-    fn moves_out(&self, entity: EntityId) -> Option<EntityId> {
-        self.lookup_from.get(&entity).map(|r| *r)
+    pub fn change_{{id}}_ref(self) -> Option<Change<&'a {{type}}>> {
+        self.action.{{id}}.change(self.id, &self.ecs.{{id}})
     }
-    fn clear(&mut self) {
-        self.lookup_from.clear();
-        self.lookup_to.clear();
-        self.apply.clear();
+            {{else}}
+    pub fn {{id}}(self) -> Option<&'a {{type}}> {
+        if let Some(change) = self.change_{{id}}() {
+            if let Change::Insert(component) = change {
+                Some(component)
+            } else {
+                None
+            }
+        } else {
+            self.current_{{id}}()
+        }
     }
-    fn mv(&mut self, source: EntityId, destination: EntityId) {
-        self.lookup_from.insert(source, destination);
-        self.lookup_to.insert(destination, source);
-        self.apply.push(MoveProfile::new(source, destination));
+    pub fn current_{{id}}(self) -> Option<&'a {{type}}> {
+        self.ecs.{{id}}(self.id)
     }
-    fn typed_positive_iter<'a, T>(&'a self, components: &'a EntityMap<T>) -> TypedMovePositiveIter<'a, T> {
-        TypedMovePositiveIter::new(components, self.lookup_to.iter())
+    pub fn change_{{id}}(self) -> Option<Change<&'a {{type}}>> {
+        self.action.{{id}}.change(self.id, &self.ecs.{{id}})
     }
-    fn typed_negative_iter<'a, T>(&'a self, components: &'a EntityMap<T>) -> TypedMoveNegativeIter<'a, T> {
-        TypedMoveNegativeIter::new(components, self.lookup_from.iter())
+            {{/if}}
+        {{/if}}
+    {{else}}
+    pub fn contains_{{id}}(self) -> bool {
+        self.change_{{id}}().unwrap_or_else(|| self.current_contains_{{id}}())
     }
-    fn flag_positive_iter<'a>(&'a self, components: &'a EntitySet) -> FlagMovePositiveIter<'a> {
-        FlagMovePositiveIter::new(components, self.lookup_to.iter())
+
+    pub fn current_contains_{{id}}(self) -> bool {
+        self.ecs.contains_{{id}}(self.id)
     }
-    fn flag_negative_iter<'a>(&'a self, components: &'a EntitySet) -> FlagMoveNegativeIter<'a> {
-        FlagMoveNegativeIter::new(components, self.lookup_from.iter())
+
+    // returns true iff the flag will be set after this action
+    pub fn change_{{id}}(self) -> Option<bool> {
+        self.action.{{id}}.change(self.id, &self.ecs.{{id}})
     }
+    {{/if}}
+{{/each}}
 }
 
-pub struct FlagActionProfile {
-    insertions: EntityHashSet,
-    removals: EntityHashSet,
-    swaps: SwapTable,
-    moves: MoveTable,
-    changed_entities: EntitySet,
+pub struct ActionEntityRefMut<'a> {
+    {{internal}}id: EntityId,
+    {{internal}}action: &'a mut EcsAction,
 }
 
-impl FlagActionProfile {
-    fn new() -> Self {
-        FlagActionProfile {
-            insertions: EntityHashSet::new(),
-            removals: EntityHashSet::new(),
-            swaps: SwapTable::new(),
-            moves: MoveTable::new(),
-            changed_entities: EntitySet::new(),
+impl<'a> ActionEntityRefMut<'a> {
+    {{internal}}fn new(id: EntityId, action: &'a mut EcsAction) -> Self {
+        ActionEntityRefMut {
+            id: id,
+            action: action,
         }
     }
 
-    fn positive_iter<'a>(&'a self, components: &'a EntitySet) -> FlagActionPositiveIter<'a> {
-        FlagActionPositiveIter {
-            insertion_iter: self.insertions.iter(),
-            move_iter: self.moves.flag_positive_iter(components),
-            swap_iter: self.swaps.flag_positive_iter(components),
-        }
+    pub fn id(&self) -> EntityId {
+        self.id
     }
 
-    fn negative_iter<'a>(&'a self, components: &'a EntitySet) -> FlagActionNegativeIter<'a> {
-        FlagActionNegativeIter {
-            removal_iter: self.removals.iter(),
-            move_iter: self.moves.flag_negative_iter(components),
-            swap_iter: self.swaps.flag_negative_iter(components),
+{{#each component}}
+    pub fn remove_{{id}}(&mut self) {
+        self.action.remove_{{id}}(self.id);
+    }
+
+    {{#if type}}
+    pub fn {{id}}_mut(&mut self, id: EntityId) -> Option<&mut {{type}}> {
+        self.action.{{id}}_mut(id)
+    }
+    {{/if}}
+{{/each}}
+}
+
+pub struct ActionEntityRef<'a> {
+    {{internal}}id: EntityId,
+    {{internal}}action: &'a EcsAction,
+}
+
+impl<'a> ActionEntityRef<'a> {
+    {{internal}}fn new(id: EntityId, action: &'a EcsAction) -> Self {
+        ActionEntityRef {
+            id: id,
+            action: action,
         }
     }
 
-    fn clear(&mut self) {
-        self.insertions.clear();
-        self.removals.clear();
-        self.swaps.clear();
-        self.moves.clear();
-        self.changed_entities.clear();
+    pub fn id(self) -> EntityId {
+        self.id
+    }
+
+{{#each component}}
+    pub fn contains_{{id}}(self) -> bool {
+        self.action.contains_{{id}}(self.id)
     }
-
-    pub fn insertion_iter(&self) -> EntityHashSetIter {
-        self.insertions.iter()
+    {{#if type}}
+        {{#if copy}}
+    pub fn {{id}}(self) -> Option<{{type}}> {
+        self.action.{{id}}(self.id)
     }
-
-    pub fn removal_iter(&self) -> EntityHashSetIter {
-        self.removals.iter()
+    pub fn {{id}}_ref(self) -> Option<&'a {{type}}> {
+        self.action.{{id}}_ref(self.id)
     }
-
-    pub fn contains(&self, id: EntityId) -> bool {
-        self.insertions.contains(id)
+        {{else}}
+    pub fn {{id}}(self) -> Option<&'a {{type}}> {
+        self.action.{{id}}(self.id)
     }
+        {{/if}}
+    {{/if}}
+{{/each}}
 }
 
-pub struct FlagActionPositiveIter<'a> {
-    insertion_iter: EntityHashSetIter<'a>,
-    move_iter: FlagMovePositiveIter<'a>,
-    swap_iter: FlagMovePositiveIter<'a>,
-}
+{{!-- module: types --}}
+pub const NUM_ACTION_PROPERTIES: usize = {{num_action_properties}};
+{{internal}}const ACTION_PROPERTY_TYPE_SET_NUM_WORDS: usize = {{component_set_num_words}};
 
-impl<'a> Iterator for FlagActionPositiveIter<'a> {
-    type Item = EntityId;
-    fn next(&mut self) -> Option<Self::Item> {
-        self.insertion_iter.next().or_else(
-            || self.move_iter.next().or_else(
-                || self.swap_iter.next()))
-    }
-}
+pub type ActionPropertyType = usize;
 
-pub struct FlagActionNegativeIter<'a> {
-    removal_iter: EntityHashSetIter<'a>,
-    move_iter: FlagMoveNegativeIter<'a>,
-    swap_iter: FlagSwapNegativeIter<'a>,
+pub mod action_property_type {
+    use std::usize;
+
+{{#each action_property}}
+    pub const {{id_uppercase}}: usize = {{index}};
+{{/each}}
 }
 
-impl<'a> Iterator for FlagActionNegativeIter<'a> {
-    type Item = EntityId;
-    fn next(&mut self) -> Option<Self::Item> {
-        self.removal_iter.next().or_else(
-            || self.move_iter.next().or_else(
-                || self.swap_iter.next()))
-    }
+#[derive(Serialize, Deserialize)]
+pub struct ActionPropertyTypeSet {
+    {{internal}}bitfields: [usize; ACTION_PROPERTY_TYPE_SET_NUM_WORDS],
 }
 
-pub struct TypedActionProfile<T> {
-    insertions: EntityHashMap<T>,
-    removals: EntityHashSet,
-    swaps: SwapTable,
-    moves: MoveTable,
-    changed_entities: EntitySet,
+pub struct ActionPropertyTypeSetIter {
+    {{internal}}bitfields: [usize; ACTION_PROPERTY_TYPE_SET_NUM_WORDS],
+    {{internal}}index: usize,
 }
 
-impl<T> TypedActionProfile<T> {
-     fn new() -> Self {
-        TypedActionProfile {
-            insertions: EntityHashMap::new(),
-            removals: EntityHashSet::new(),
-            swaps: SwapTable::new(),
-            moves: MoveTable::new(),
-            changed_entities: EntitySet::new(),
+impl ActionPropertyTypeSetIter {
+    {{internal}}fn new(bitfields: [usize; ACTION_PROPERTY_TYPE_SET_NUM_WORDS]) -> Self {
+        ActionPropertyTypeSetIter {
+            bitfields: bitfields,
+            index: 0,
         }
     }
+}
 
-    pub fn insertion_iter(&self) -> EntityHashMapIter<T> {
-        self.insertions.iter()
-    }
+impl Iterator for ActionPropertyTypeSetIter {
+    type Item = ActionPropertyType;
+    fn next(&mut self) -> Option<Self::Item> {
+        while self.index < ACTION_PROPERTY_TYPE_SET_NUM_WORDS && self.bitfields[self.index] == 0 {
+            self.index += 1;
+        }
+        if self.index == ACTION_PROPERTY_TYPE_SET_NUM_WORDS {
+            return None;
+        }
 
-    pub fn removal_iter(&self) -> EntityHashSetIter {
-        self.removals.iter()
+        let trailing = self.bitfields[self.index].trailing_zeros() as usize;
+        self.bitfields[self.index] &= !(1 << trailing);
+        Some(self.index * WORD_BITS + trailing)
     }
+}
 
-    fn positive_iter<'a>(&'a self, components: &'a EntityMap<T>) -> TypedActionPositiveIter<'a, T> {
-        TypedActionPositiveIter {
-            insertion_iter: self.insertions.iter(),
-            move_iter: self.moves.typed_positive_iter(components),
-            swap_iter: self.swaps.typed_positive_iter(components),
+impl ActionPropertyTypeSet {
+    pub fn new() -> Self {
+        ActionPropertyTypeSet {
+            bitfields: [0; COMPONENT_TYPE_SET_NUM_WORDS],
         }
     }
 
-    fn negative_iter<'a>(&'a self, components: &'a EntityMap<T>) -> TypedActionNegativeIter<'a, T> {
-        TypedActionNegativeIter {
-            removal_iter: self.removals.iter(),
-            move_iter: self.moves.typed_negative_iter(components),
-            swap_iter: self.swaps.typed_negative_iter(components),
+    pub fn is_empty(&self) -> bool {
+        for b in &self.bitfields {
+            if *b != 0 {
+                return false;
+            }
         }
-    }
-
-    fn clear(&mut self) {
-        self.insertions.clear();
-        self.removals.clear();
-        self.swaps.clear();
-        self.moves.clear();
-        self.changed_entities.clear();
-    }
-
-    pub fn get(&self, id: EntityId) -> Option<&T> {
-        self.insertions.get(id)
-    }
 
-    pub fn get_mut(&mut self, id: EntityId) -> Option<&mut T> {
-        self.insertions.get_mut(id)
+        true
     }
 
-    pub fn contains(&self, id: EntityId) -> bool {
-        self.insertions.contains_key(id)
+    pub fn clear(&mut self) {
+        for b in &mut self.bitfields {
+            *b = 0;
+        }
     }
-}
 
-impl<T: Copy> TypedActionProfile<T> {
-    pub fn insertion_copy_iter(&self) -> EntityHashMapCopyIter<T> {
-        self.insertions.copy_iter()
+    pub fn iter(&self) -> ActionPropertyTypeSetIter {
+        ActionPropertyTypeSetIter::new(self.bitfields)
     }
 
-    pub fn get_copy(&self, id: EntityId) -> Option<T> {
-        self.get(id).map(|r| *r)
+{{#each action_property}}
+    pub fn contains_{{id}}(&self) -> bool {
+        self.bitfields[{{set_index}}] & (1 << {{set_bit}}) != 0
     }
-}
-pub struct TypedActionPositiveIter<'a, T: 'a> {
-    insertion_iter: EntityHashMapIter<'a, T>,
-    move_iter: TypedMovePositiveIter<'a, T>,
-    swap_iter: TypedMovePositiveIter<'a, T>,
-}
 
-impl<'a, T: 'a> Iterator for TypedActionPositiveIter<'a, T> {
-    type Item = (EntityId, &'a T);
-    fn next(&mut self) -> Option<Self::Item> {
-        self.insertion_iter.next().or_else(
-            || self.move_iter.next().or_else(
-                || self.swap_iter.next()))
+    pub fn insert_{{id}}(&mut self) {
+        self.bitfields[{{set_index}}] |= 1 << {{set_bit}};
     }
-}
-
-pub struct TypedActionNegativeIter<'a, T: 'a> {
-    removal_iter: EntityHashSetIter<'a>,
-    move_iter: TypedMoveNegativeIter<'a, T>,
-    swap_iter: TypedSwapNegativeIter<'a, T>,
-}
 
-impl<'a, T: 'a> Iterator for TypedActionNegativeIter<'a, T> {
-    type Item = EntityId;
-    fn next(&mut self) -> Option<Self::Item> {
-        self.removal_iter.next().or_else(
-            || self.move_iter.next().or_else(
-                || self.swap_iter.next()))
+    pub fn remove_{{id}}(&mut self) {
+        self.bitfields[{{set_index}}] &= !(1 << {{set_bit}});
     }
+{{/each}}
 }
 
-pub struct EcsAction {
-
-{{#each component}}
+{{!-- module: action --}}
+#[derive(Serialize, Deserialize)]
+pub struct EcsActionProperties {
+    {{internal}}property_types: ActionPropertyTypeSet,
+{{#each action_property}}
     {{#if type}}
-    {{id}}: TypedActionProfile<{{type}}>,
+    pub {{id}}: Option<{{type}}>,
     {{else}}
-    {{id}}: FlagActionProfile,
+    pub {{id}}: bool,
     {{/if}}
 {{/each}}
-    changed_components: ComponentTypeSet,
-    properties: EcsActionProperties,
 }
 
-impl Default for EcsAction {
-    fn default() -> Self {
-        Self::new()
-    }
-}
-
-impl EcsAction {
+impl EcsActionProperties {
     pub fn new() -> Self {
-        EcsAction {
-{{#each component}}
+        EcsActionProperties {
+            property_types: ActionPropertyTypeSet::new(),
+{{#each action_property}}
     {{#if type}}
-            {{id}}: TypedActionProfile::new(),
+            {{id}}: None,
     {{else}}
-            {{id}}: FlagActionProfile::new(),
+            {{id}}: false,
     {{/if}}
 {{/each}}
-            changed_components: ComponentTypeSet::new(),
-            properties: EcsActionProperties::new(),
         }
     }
 
     pub fn clear(&mut self) {
-{{#each component}}
-        if self.changed_components.contains_{{id}}() {
-            self.{{id}}.clear();
-        }
-{{/each}}
-        self.changed_components.clear();
-        self.properties.clear();
-    }
-
-{{#each component}}
-    {{#if type}}
-    pub fn insert_{{id}}(&mut self, entity: EntityId, value: {{type}}) {
-        self.{{id}}.insertions.insert(entity, value);
-        self.{{id}}.changed_entities.insert(entity);
-        self.changed_components.insert_{{id}}();
-    }
-    pub fn {{id}}_profile(&self) -> &TypedActionProfile<{{type}}> {
-        &self.{{id}}
-    }
-    pub fn {{id}}_profile_mut(&mut self) -> &mut TypedActionProfile<{{type}}> {
-        &mut self.{{id}}
-    }
-    pub fn {{id}}_mut(&mut self, id: EntityId) -> Option<&mut {{type}}> {
-        self.{{id}}_profile_mut().get_mut(id)
-    }
-    pub fn contains_{{id}}(&self, id: EntityId) -> bool {
-        self.{{id}}_profile().contains(id)
-    }
-        {{#if copy}}
-    pub fn {{id}}(&self, id: EntityId) -> Option<{{type}}> {
-        self.{{id}}_ref(id).map(|r| *r)
-    }
-    pub fn {{id}}_ref(&self, id: EntityId) -> Option<&{{type}}> {
-        self.{{id}}_profile().get(id)
-    }
-        {{else}}
-    pub fn {{id}}(&self, id: EntityId) -> Option<&{{type}}> {
-        self.{{id}}_profile().get(id)
-    }
-        {{/if}}
-        {{#unless container}}
-    pub fn {{id}}_positive_iter<'a>(&'a self, ecs: &'a EcsCtx) -> TypedActionPositiveIter<'a, {{type}}> {
-        self.{{id}}.positive_iter(&ecs.{{id}})
-    }
-    pub fn {{id}}_negative_iter<'a>(&'a self, ecs: &'a EcsCtx) -> TypedActionNegativeIter<'a, {{type}}> {
-        self.{{id}}.negative_iter(&ecs.{{id}})
-    }
-        {{/unless}}
-    {{else}}
-    pub fn contains_{{id}}(&self, id: EntityId) -> bool {
-        self.{{id}}.contains(id)
-    }
-    pub fn {{id}}_positive_iter<'a>(&'a self, ecs: &'a EcsCtx) -> FlagActionPositiveIter<'a> {
-        self.{{id}}.positive_iter(&ecs.{{id}})
-    }
-    pub fn {{id}}_negative_iter<'a>(&'a self, ecs: &'a EcsCtx) -> FlagActionNegativeIter<'a> {
-        self.{{id}}.negative_iter(&ecs.{{id}})
-    }
-    pub fn insert_{{id}}(&mut self, entity: EntityId) {
-        self.{{id}}.insertions.insert(entity);
-        self.{{id}}.changed_entities.insert(entity);
-        self.changed_components.insert_{{id}}();
-    }
-    pub fn {{id}}_profile(&self) -> &FlagActionProfile {
-        &self.{{id}}
-    }
-    {{/if}}
-    pub fn remove_{{id}}(&mut self, entity: EntityId) {
-        self.{{id}}.removals.insert(entity);
-        self.{{id}}.changed_entities.insert(entity);
-        self.changed_components.insert_{{id}}();
-    }
-    pub fn move_{{id}}(&mut self, source: EntityId, destination: EntityId) {
-        self.{{id}}.moves.mv(source, destination);
-        self.{{id}}.changed_entities.insert(source);
-        self.{{id}}.changed_entities.insert(destination);
-        self.changed_components.insert_{{id}}();
-    }
-    pub fn swap_{{id}}(&mut self, a: EntityId, b: EntityId) {
-        self.{{id}}.swaps.swap(a, b);
-        self.{{id}}.changed_entities.insert(a);
-        self.{{id}}.changed_entities.insert(b);
-        self.changed_components.insert_{{id}}();
-    }
-{{/each}}
-    pub fn remove_entity(&mut self, entity: EntityRef) {
-        self.remove_entity_by_id(entity.id, entity.ctx);
-    }
-    pub fn remove_entity_by_id(&mut self, entity: EntityId, ecs: &EcsCtx) {
-        ecs.tracker.get(entity).map(|components| {
-            for component in components.iter() {
-                self.remove_component(entity, component);
-            }
-        });
-    }
-    pub fn remove_component(&mut self, entity: EntityId, component_type: ComponentType) {
-        match component_type {
-{{#each component}}
-            component_type::{{id_uppercase}} => self.remove_{{id}}(entity),
+        for property_type in self.property_types.iter() {
+            match property_type {
+{{#each action_property}}
+                action_property_type::{{id_uppercase}} => {
+    {{#if type}}
+                    self.{{id}} = None;
+    {{else}}
+                    self.{{id}} = false;
+    {{/if}}
+                }
 {{/each}}
-            _ => panic!("Invalid component type: {}", component_type),
+                _ => panic!("Invalid action property type: {}", property_type),
+            }
         }
+        self.property_types.clear();
     }
+
 {{#each action_property}}
     {{#if type}}
+    pub fn insert_{{id}}(&mut self, value: {{type}}) {
+        self.{{id}} = Some(value);
+        self.property_types.insert_{{id}}();
+    }
         {{#if copy}}
     pub fn {{id}}(&self) -> Option<{{type}}> {
-        self.properties.{{id}}()
+        self.{{id}}
     }
     pub fn {{id}}_ref(&self) -> Option<&{{type}}> {
-        self.properties.{{id}}_ref()
+        self.{{id}}.as_ref()
     }
         {{else}}
     pub fn {{id}}(&self) -> Option<&{{type}}> {
-        self.properties.{{id}}()
+        self.{{id}}.as_ref()
     }
         {{/if}}
-    pub fn set_{{id}}(&mut self, value: {{type}}) {
-        self.properties.insert_{{id}}(value);
+    pub fn contains_{{id}}(&self) -> bool {
+        self.{{id}}.is_some()
     }
     {{else}}
-    pub fn contains_{{id}}(&self) -> bool {
-        self.properties.contains_{{id}}()
+    pub fn insert_{{id}}(&mut self) {
+        self.{{id}} = true;
+        self.property_types.insert_{{id}}();
     }
-    pub fn set_{{id}}(&mut self) {
-        self.properties.insert_{{id}}();
+    pub fn contains_{{id}}(&self) -> bool {
+        self.{{id}}
     }
     {{/if}}
-    pub fn clear_{{id}}(&mut self) ->
+    pub fn remove_{{id}}(&mut self) ->
     {{#if type}}
         Option<{{type}}>
     {{else}}
         bool
     {{/if}}
     {
-        self.properties.remove_{{id}}()
+    {{#if type}}
+        self.{{id}}.take()
+    {{else}}
+        mem::replace(&mut self.{{id}}, false)
+    {{/if}}
     }
 {{/each}}
+}
 
-    pub fn entity(&self, id: EntityId) -> ActionEntityRef {
-        ActionEntityRef::new(id, self)
-    }
+{{!-- module: entity_ref --}}
+pub struct EntityRefIter<'a, I: Iterator<Item=EntityId>> {
+    {{internal}}ctx: &'a EcsCtx,
+    {{internal}}iter: I,
+}
 
-    pub fn entity_mut(&mut self, id: EntityId) -> ActionEntityRefMut {
-        ActionEntityRefMut::new(id, self)
+impl<'a, I: Iterator<Item=EntityId>> EntityRefIter<'a, I> {
+    {{internal}}fn new(ctx: &'a EcsCtx, iter: I) -> Self {
+        EntityRefIter {
+            ctx: ctx,
+            iter: iter,
+        }
     }
 }
 
-#[derive(Clone, Copy)]
-pub enum ContainerComponentRef<'a, ContainerType: 'a, Type: 'a> {
-    Contained(&'a ContainerType),
-    Raw(&'a Type),
+impl<'a, I: Iterator<Item=EntityId>> Iterator for EntityRefIter<'a, I> {
+    type Item = EntityRef<'a>;
+    fn next(&mut self) -> Option<Self::Item> {
+        self.iter.next().map(|id| self.ctx.entity(id))
+    }
 }
 
-#[derive(Clone, Copy)]
-pub enum Change<T> {
-    Insert(T),
-    Remove,
+{{!-- module: ctx --}}
+{{internal}}fn component_collection(ctx: &EcsCtx, component_type: ComponentType) -> &dyn EntityCollection {
+    match component_type {
+{{#each component}}
+        component_type::{{id_uppercase}} => &ctx.{{id}},
+{{/each}}
+        _ => panic!("Invalid component type: {}", component_type),
+    }
 }
 
-#[derive(Clone, Copy)]
-pub struct PostActionEntityRef<'a> {
-    id: EntityId,
-    ecs: &'a EcsCtx,
-    action: &'a EcsAction,
+// Determines what a query yields for each matching entity
+pub trait QueryFetch<'a> {
+    type Item;
+    fn fetch(&self, entity: EntityId) -> Self::Item;
 }
 
-impl<'a> PostActionEntityRef<'a> {
-    fn new(id: EntityId, ecs: &'a EcsCtx, action: &'a EcsAction) -> Self {
-        PostActionEntityRef {
-            id: id,
-            ecs: ecs,
-            action: action,
-        }
+pub struct QueryId;
+
+impl<'a> QueryFetch<'a> for QueryId {
+    type Item = EntityId;
+    fn fetch(&self, entity: EntityId) -> Self::Item {
+        entity
     }
+}
 
-    pub fn id(self) -> EntityId {
-        self.id
+pub struct QueryComponent<'a, F, T: 'a> {
+    {{internal}}fetch: F,
+    {{internal}}components: &'a dyn ComponentMap<T>,
+}
+
+impl<'a, F: QueryFetch<'a>, T: 'a> QueryFetch<'a> for QueryComponent<'a, F, T> {
+    type Item = (F::Item, &'a T);
+    fn fetch(&self, entity: EntityId) -> Self::Item {
+        let component = self.components.get(entity).expect("Query matched entity without component");
+        (self.fetch.fetch(entity), component)
     }
+}
 
-    pub fn to_entity_ref(self) -> EntityRef<'a> {
-        EntityRef::new(self.id, self.ecs)
+// Iterates over the entities with all of the "with" components and none of
+// the "without" components. Each typed "with" component adds a reference to
+// that component to the yielded items, so that the query
+// `ctx.query().with_a().with_b()` yields `((id, &a), &b)`.
+pub struct Query<'a, F> {
+    {{internal}}ctx: &'a EcsCtx,
+    {{internal}}fetch: F,
+    {{internal}}with: ComponentTypeSet,
+    {{internal}}without: ComponentTypeSet,
+}
+
+impl<'a> Query<'a, QueryId> {
+    {{internal}}fn new(ctx: &'a EcsCtx) -> Self {
+        Query {
+            ctx: ctx,
+            fetch: QueryId,
+            with: ComponentTypeSet::new(),
+            without: ComponentTypeSet::new(),
+        }
     }
+}
 
+impl<'a, F: QueryFetch<'a>> Query<'a, F> {
 {{#each component}}
     {{#if type}}
         {{#if container}}
-    pub fn {{id}}(self) -> Option<ContainerComponentRef<'a, {{container}}<{{type}}>, {{type}}>> {
-        if let Some(change) = self.change_{{id}}() {
-            if let Change::Insert(component) = change {
-                Some(component)
-            } else {
-                None
-            }
-        } else {
-            self.current_{{id}}().map(ContainerComponentRef::Contained)
+    pub fn with_{{id}}(self) -> Query<'a, QueryComponent<'a, F, {{container}}<{{type}}>>> {
+        {{else}}
+    pub fn with_{{id}}(self) -> Query<'a, QueryComponent<'a, F, {{type}}>> {
+        {{/if}}
+        let Query { ctx, fetch, mut with, without } = self;
+        with.insert_{{id}}();
+        Query {
+            ctx: ctx,
+            fetch: QueryComponent {
+                fetch: fetch,
+                components: &ctx.{{id}},
+            },
+            with: with,
+            without: without,
         }
     }
-    pub fn current_{{id}}(self) -> Option<&'a {{container}}<{{type}}>> {
-        self.ecs.{{id}}(self.id)
+    {{else}}
+    pub fn with_{{id}}(mut self) -> Self {
+        self.with.insert_{{id}}();
+        self
     }
-    pub fn change_{{id}}(self) -> Option<Change<ContainerComponentRef<'a, {{container}}<{{type}}>, {{type}}>>> {
-        let profile = &self.action.{{id}};
+    {{/if}}
 
-        // check if component is explicitly inserted
-        if let Some(component) = profile.insertions.get(self.id) {
-            return Some(Change::Insert(ContainerComponentRef::Raw(component)));
-        }
+    pub fn without_{{id}}(mut self) -> Self {
+        self.without.insert_{{id}}();
+        self
+    }
+{{/each}}
+}
 
-        // check if component is explicitly removed
-        if profile.removals.contains(self.id) {
-            return Some(Change::Remove);
+impl<'a, F: QueryFetch<'a>> IntoIterator for Query<'a, F> {
+    type Item = F::Item;
+    type IntoIter = QueryIter<'a, F>;
+    fn into_iter(self) -> Self::IntoIter {
+        let ctx = self.ctx;
+        let mut with: Vec<&'a dyn EntityCollection> = self.with.iter().map(|t| component_collection(ctx, t)).collect();
+        if with.is_empty() {
+            // every entity with at least one component is in the tracker
+            with.push(&ctx.tracker);
         }
 
-        // check if component is swapped in
-        if let Some(other) = profile.swaps.swaps_with(self.id) {
-            if let Some(component) = self.ecs.{{id}}(other) {
-                return Some(Change::Insert(ContainerComponentRef::Contained(component)));
-            } else {
-                return Some(Change::Remove);
-            }
-        }
+        // the smallest collection drives the join
+        with.sort_by_key(|components| components.len());
 
-        // check if component is moved in
-        if let Some(src) = profile.moves.moves_in(self.id) {
-            if let Some(component) = self.ecs.{{id}}(src) {
-                return Some(Change::Insert(ContainerComponentRef::Contained(component)));
-            }
+        // unless every collection can be seeked, visit each entity in the
+        // smallest collection and probe the others
+        let probe = if with.iter().all(|components| components.is_sorted()) {
+            None
+        } else {
+            Some(with[0].ids())
+        };
+
+        QueryIter {
+            fetch: self.fetch,
+            with: with,
+            without: self.without.iter().map(|t| component_collection(ctx, t)).collect(),
+            next: Some(0),
+            probe: probe,
         }
+    }
+}
 
-        // check if component is moved out
-        if profile.moves.moves_out(self.id).is_some() {
-            return Some(Change::Remove);
-        }
+pub struct QueryIter<'a, F> {
+    {{internal}}fetch: F,
+    {{internal}}with: Vec<&'a dyn EntityCollection>,
+    {{internal}}without: Vec<&'a dyn EntityCollection>,
+    {{internal}}next: Option<EntityId>,
+    {{internal}}probe: Option<Box<dyn Iterator<Item=EntityId> + 'a>>,
+}
 
-        None
-    }
-        {{else}}
-            {{#if copy}}
-    pub fn {{id}}(self) -> Option<{{type}}> {
-        if let Some(change) = self.change_{{id}}() {
-            if let Change::Insert(component) = change {
-                Some(component)
+impl<'a, F: QueryFetch<'a>> QueryIter<'a, F> {
+    fn next_match(&self, from: EntityId) -> Option<EntityId> {
+        let mut candidate = self.with[0].seek(from)?;
+        let mut i = 1;
+        while i < self.with.len() {
+            let found = self.with[i].seek(candidate)?;
+            if found == candidate {
+                i += 1;
             } else {
-                None
+                // no entity between candidate and found can match
+                candidate = self.with[0].seek(found)?;
+                i = 1;
             }
-        } else {
-            self.current_{{id}}()
         }
+
+        Some(candidate)
     }
-    pub fn current_{{id}}(self) -> Option<{{type}}> {
-        self.ecs.{{id}}(self.id)
-    }
-    pub fn change_{{id}}(self) -> Option<Change<{{type}}>> {
-        let profile = &self.action.{{id}};
+}
 
-        // check if component is explicitly inserted
-        if let Some(component) = profile.insertions.get(self.id) {
-            return Some(Change::Insert(*component));
+impl<'a, F: QueryFetch<'a>> Iterator for QueryIter<'a, F> {
+    type Item = F::Item;
+    fn next(&mut self) -> Option<Self::Item> {
+        if let Some(ref mut probe) = self.probe {
+            let with = &self.with[1..];
+            let without = &self.without;
+            let entity = probe.find(|&entity| {
+                with.iter().all(|components| components.contains(entity)) &&
+                    !without.iter().any(|components| components.contains(entity))
+            })?;
+
+            return Some(self.fetch.fetch(entity));
         }
 
-        // check if component is explicitly removed
-        if profile.removals.contains(self.id) {
-            return Some(Change::Remove);
-        }
+        while let Some(from) = self.next {
+            let entity = match self.next_match(from) {
+                Some(entity) => entity,
+                None => {
+                    self.next = None;
+                    return None;
+                }
+            };
 
-        // check if component is swapped in
-        if let Some(other) = profile.swaps.swaps_with(self.id) {
-            if let Some(component) = self.ecs.{{id}}(other) {
-                return Some(Change::Insert(component));
-            } else {
-                return Some(Change::Remove);
-            }
-        }
+            self.next = entity.checked_add(1);
 
-        // check if component is moved in
-        if let Some(src) = profile.moves.moves_in(self.id) {
-            if let Some(component) = self.ecs.{{id}}(src) {
-                return Some(Change::Insert(component));
+            if !self.without.iter().any(|components| components.contains(entity)) {
+                return Some(self.fetch.fetch(entity));
             }
         }
 
-        // check if component is moved out
-        if profile.moves.moves_out(self.id).is_some() {
-            return Some(Change::Remove);
-        }
-
         None
     }
-    pub fn {{id}}_ref(self) -> Option<&'a {{type}}> {
-        if let Some(change) = self.change_{{id}}_ref() {
-            if let Change::Insert(component) = change {
-                Some(component)
-            } else {
-                None
-            }
-        } else {
-            self.current_{{id}}_ref()
-        }
-    }
-    pub fn current_{{id}}_ref(self) -> Option<&'a {{type}}> {
-        self.ecs.{{id}}_ref(self.id)
-    }
-    pub fn change_{{id}}_ref(self) -> Option<Change<&'a {{type}}>> {
-        let profile = &self.action.{{id}};
+}
 
-        // check if component is explicitly inserted
-        if let Some(component) = profile.insertions.get(self.id) {
-            return Some(Change::Insert(component));
-        }
+{{#if dynamic}}
 
-        // check if component is explicitly removed
-        if profile.removals.contains(self.id) {
-            return Some(Change::Remove);
-        }
+// Dynamic access to components by name, with values represented as json.
+// Flags are represented as `true`.
+#[derive(Debug)]
+pub enum DynamicError {
+    UnknownComponent(String),
+    Value(::serde_json::Error),
+}
 
-        // check if component is swapped in
-        if let Some(other) = profile.swaps.swaps_with(self.id) {
-            if let Some(component) = self.ecs.{{id}}_ref(other) {
-                return Some(Change::Insert(component));
-            } else {
-                return Some(Change::Remove);
-            }
+impl ::std::fmt::Display for DynamicError {
+    fn fmt(&self, f: &mut ::std::fmt::Formatter) -> ::std::fmt::Result {
+        match *self {
+            DynamicError::UnknownComponent(ref name) => write!(f, "unknown component: {}", name),
+            DynamicError::Value(ref error) => write!(f, "invalid component value: {}", error),
         }
+    }
+}
 
-        // check if component is moved in
-        if let Some(src) = profile.moves.moves_in(self.id) {
-            if let Some(component) = self.ecs.{{id}}_ref(src) {
-                return Some(Change::Insert(component));
-            }
-        }
+impl ::std::error::Error for DynamicError {}
 
-        // check if component is moved out
-        if profile.moves.moves_out(self.id).is_some() {
-            return Some(Change::Remove);
-        }
+{{internal}}fn dynamic_component_type(name: &str) -> Result<ComponentType, DynamicError> {
+    component_type::from_name(name).ok_or_else(|| DynamicError::UnknownComponent(name.to_string()))
+}
 
-        None
-    }
-            {{else}}
-    pub fn {{id}}(self) -> Option<&'a {{type}}> {
-        if let Some(change) = self.change_{{id}}() {
-            if let Change::Insert(component) = change {
-                Some(component)
-            } else {
-                None
+{{internal}}fn dynamic_from_value<T: ::serde::de::DeserializeOwned>(value: ::serde_json::Value) -> Result<T, DynamicError> {
+    ::serde_json::from_value(value).map_err(DynamicError::Value)
+}
+
+{{internal}}fn dynamic_to_value<T: ::serde::Serialize>(value: &T) -> Result<::serde_json::Value, DynamicError> {
+    ::serde_json::to_value(value).map_err(DynamicError::Value)
+}
+
+impl EcsCtx {
+    pub fn get_dynamic(&self, entity: EntityId, name: &str) -> Result<Option<::serde_json::Value>, DynamicError> {
+        match dynamic_component_type(name)? {
+{{#each component}}
+            component_type::{{id_uppercase}} => {
+    {{#if type}}
+        {{#if RefCell}}
+                match self.{{id}}.get(entity) {
+                    Some(cell) => dynamic_to_value(&*cell.borrow()).map(Some),
+                    None => Ok(None),
+                }
+        {{/if}}
+        {{#if UnsafeCell}}
+                // the same caveats apply as to {{id}}_unsafe_get
+                match self.{{id}}.get(entity) {
+                    Some(cell) => dynamic_to_value(unsafe { &*cell.get() }).map(Some),
+                    None => Ok(None),
+                }
+        {{/if}}
+        {{#unless container}}
+                match self.{{id}}.get(entity) {
+                    Some(value) => dynamic_to_value(value).map(Some),
+                    None => Ok(None),
+                }
+        {{/unless}}
+    {{else}}
+                if self.contains_{{id}}(entity) {
+                    Ok(Some(::serde_json::Value::Bool(true)))
+                } else {
+                    Ok(None)
+                }
+    {{/if}}
             }
-        } else {
-            self.current_{{id}}()
+{{/each}}
+            component_type => panic!("Invalid component type: {}", component_type),
         }
     }
-    pub fn current_{{id}}(self) -> Option<&'a {{type}}> {
-        self.ecs.{{id}}(self.id)
-    }
-    pub fn change_{{id}}(self) -> Option<Change<&'a {{type}}>> {
-        let profile = &self.action.{{id}};
 
-        // check if component is explicitly inserted
-        if let Some(component) = profile.insertions.get(self.id) {
-            return Some(Change::Insert(component));
+    pub fn insert_dynamic(&mut self, entity: EntityId, name: &str, value: ::serde_json::Value) -> Result<(), DynamicError> {
+        match dynamic_component_type(name)? {
+{{#each component}}
+    {{#if type}}
+            component_type::{{id_uppercase}} => self.insert_{{id}}(entity, dynamic_from_value(value)?),
+    {{else}}
+            component_type::{{id_uppercase}} => self.insert_{{id}}(entity),
+    {{/if}}
+{{/each}}
+            component_type => panic!("Invalid component type: {}", component_type),
         }
 
-        // check if component is explicitly removed
-        if profile.removals.contains(self.id) {
-            return Some(Change::Remove);
-        }
+        Ok(())
+    }
 
-        // check if component is swapped in
-        if let Some(other) = profile.swaps.swaps_with(self.id) {
-            if let Some(component) = self.ecs.{{id}}(other) {
-                return Some(Change::Insert(component));
-            } else {
-                return Some(Change::Remove);
-            }
-        }
+    // Returns the removed value, if the entity had the component
+    pub fn remove_dynamic(&mut self, entity: EntityId, name: &str) -> Result<Option<::serde_json::Value>, DynamicError> {
+        let value = self.get_dynamic(entity, name)?;
+        self.remove_component(entity, dynamic_component_type(name)?);
 
-        // check if component is moved in
-        if let Some(src) = profile.moves.moves_in(self.id) {
-            if let Some(component) = self.ecs.{{id}}(src) {
-                return Some(Change::Insert(component));
-            }
-        }
+        Ok(value)
+    }
+}
 
-        // check if component is moved out
-        if profile.moves.moves_out(self.id).is_some() {
-            return Some(Change::Remove);
-        }
+{{/if}}
+{{!-- module: action --}}
+{{#if dynamic}}
 
-        None
-    }
-            {{/if}}
-        {{/if}}
+impl EcsAction {
+    pub fn insert_dynamic(&mut self, entity: EntityId, name: &str, value: ::serde_json::Value) -> Result<(), DynamicError> {
+        match dynamic_component_type(name)? {
+{{#each component}}
+    {{#if type}}
+            component_type::{{id_uppercase}} => self.insert_{{id}}(entity, dynamic_from_value(value)?),
     {{else}}
-    pub fn contains_{{id}}(self) -> bool {
-        self.change_{{id}}().unwrap_or_else(|| self.current_contains_{{id}}())
+            component_type::{{id_uppercase}} => self.insert_{{id}}(entity),
+    {{/if}}
+{{/each}}
+            component_type => panic!("Invalid component type: {}", component_type),
+        }
+
+        Ok(())
     }
 
-    pub fn current_contains_{{id}}(self) -> bool {
-        self.ecs.contains_{{id}}(self.id)
+    pub fn remove_dynamic(&mut self, entity: EntityId, name: &str) -> Result<(), DynamicError> {
+        let component_type = dynamic_component_type(name)?;
+        self.remove_component(entity, component_type);
+
+        Ok(())
     }
+}
 
-    // returns true iff the flag will be set after this action
-    pub fn change_{{id}}(self) -> Option<bool> {
-        let profile = &self.action.{{id}};
+{{/if}}
+"#;
 
-        // check if component is explicitly inserted
-        if profile.insertions.contains(self.id) {
-            return Some(true);
-        }
+// The root of an ecs split into modules. Only the types most code needs are
+// re-exported; everything else is reachable through its module.
+const ROOT_TEMPLATE: &str = r#"// Automatically generated. Do not edit.
 
-        // check if component is explicitly removed
-        if profile.removals.contains(self.id) {
-            return Some(false);
-        }
+{{#each modules}}
+#[allow(unused_imports)]
+pub mod {{ this }} {
+    include!("{{ ../root }}/{{ this }}.rs");
+}
 
-        // check if component is swapped in or out
-        if let Some(other) = profile.swaps.swaps_with(self.id) {
-            if self.ecs.contains_{{id}}(other) {
-                return Some(true);
-            } else {
-                return Some(false);
-            }
-        }
+{{/each}}
+#[allow(unused_imports)]
+pub use self::types::{EntityId, ComponentType, ActionPropertyType, NUM_COMPONENTS, NUM_ACTION_PROPERTIES};
+#[allow(unused_imports)]
+pub use self::types::{entity_id, component_type, action_property_type};
+#[allow(unused_imports)]
+pub use self::ctx::{EcsCtx, SerializableEcsCtx};
+{{#if dynamic}}
+#[allow(unused_imports)]
+pub use self::ctx::DynamicError;
+{{/if}}
+#[allow(unused_imports)]
+pub use self::action::{EcsAction, ActionConflict, MergeConflict};
+#[allow(unused_imports)]
+pub use self::entity_ref::{EntityRef, EntityRefMut, PostActionEntityRef, ActionEntityRef, ActionEntityRefMut, EntityPopulate};
+"#;
+
+const MODULES: &[&str] = &["types", "storage", "ctx", "action", "entity_ref"];
 
-        // check if component is moved in
-        if let Some(src) = profile.moves.moves_in(self.id) {
-            if self.ecs.contains_{{id}}(src) {
-                return Some(true);
-            }
-        }
+const EXTENSION_POINTS: &[&str] = &["ctx", "ctx_insert", "action", "action_insert", "entity_ref"];
+
+const MODULE_MARKER: &str = "{{!-- module: ";
+
+// Splits TEMPLATE at its module markers into a template for each module.
+// Everything before the first marker is shared by all modules.
+fn module_templates() -> Vec<(&'static str, String)> {
+    let first_marker = TEMPLATE.find(MODULE_MARKER).expect("template has module markers");
+    let prelude = TEMPLATE[..first_marker].replace("#![allow(unused_imports)]\n", "");
 
-        // check if component is moved out
-        if profile.moves.moves_out(self.id).is_some() {
-            return Some(false);
+    let mut templates: Vec<(&str, String)> = MODULES.iter().map(|&module| {
+        let mut template = prelude.clone();
+        for other in MODULES.iter().filter(|&&other| other != module) {
+            template.push_str(&format!("use super::{}::*;\n", other));
         }
+        (module, template)
+    }).collect();
 
-        None
+    for chunk in TEMPLATE[first_marker..].split(MODULE_MARKER).skip(1) {
+        let end = chunk.find(" --}}").expect("module markers are closed");
+        let (_, template) = templates.iter_mut()
+            .find(|&&mut (module, _)| module == &chunk[..end])
+            .expect("module markers name known modules");
+        template.push_str(&chunk[end + " --}}".len()..]);
     }
-    {{/if}}
-{{/each}}
+
+    templates
 }
 
-pub struct ActionEntityRefMut<'a> {
-    id: EntityId,
-    action: &'a mut EcsAction,
+// The data templates are rendered with. `internal` is the visibility of
+// items only used within the generated code.
+fn render_data(spec: &json::Object, internal: &str) -> Json {
+    let mut model = schema::model(&schema::schema(spec));
+    model.insert("internal".to_string(), Json::String(internal.to_string()));
+
+    Json::Object(model)
 }
 
-impl<'a> ActionEntityRefMut<'a> {
-    fn new(id: EntityId, action: &'a mut EcsAction) -> Self {
-        ActionEntityRefMut {
-            id: id,
-            action: action,
-        }
-    }
+fn render(template: &str, data: &Json, extensions: &[(String, String)]) -> Result<String, GenerateError> {
+    let mut handlebars = Handlebars::new();
 
-    pub fn id(&self) -> EntityId {
-        self.id
-    }
+    // prevent xml escaping
+    handlebars.register_escape_fn(|input| input.to_string());
 
-{{#each component}}
-    pub fn remove_{{id}}(&mut self) {
-        self.action.remove_{{id}}(self.id);
+    for (point, _) in extensions {
+        if !EXTENSION_POINTS.contains(&point.as_str()) {
+            return Err(GenerateError::ExtensionPoint(point.clone()));
+        }
     }
 
-    {{#if type}}
-    pub fn {{id}}_mut(&mut self, id: EntityId) -> Option<&mut {{type}}> {
-        self.action.{{id}}_mut(id)
+    // the template includes each extension point with the whitespace before
+    // it removed, so each extension starts on a new line
+    for point in EXTENSION_POINTS {
+        let mut partial = String::new();
+        for (_, template) in extensions.iter().filter(|&(other, _)| other == point) {
+            partial.push('\n');
+            partial.push_str(template.trim_end_matches('\n'));
+        }
+        handlebars.register_template_string(point, partial).map_err(TemplateRenderError::from)?;
     }
-    {{/if}}
-{{/each}}
+
+    Ok(handlebars.template_render(template, data)?)
 }
 
-pub struct ActionEntityRef<'a> {
-    id: EntityId,
-    action: &'a EcsAction,
+fn generate_code(spec: &json::Object, extensions: &[(String, String)]) -> Result<String, GenerateError> {
+    render(TEMPLATE, &render_data(spec, ""), extensions)
 }
 
-impl<'a> ActionEntityRef<'a> {
-    fn new(id: EntityId, action: &'a EcsAction) -> Self {
-        ActionEntityRef {
-            id: id,
-            action: action,
-        }
+// The code of the root module followed by that of each module, named by
+// their paths relative to the output directory
+fn generate_module_code(spec: &json::Object, root: &str, extensions: &[(String, String)]) -> Result<Vec<(PathBuf, String)>, GenerateError> {
+    if !validate::is_module_name(root) {
+        return Err(GenerateError::ModuleName(root.to_string()));
     }
 
-    pub fn id(self) -> EntityId {
-        self.id
+    let mut data = render_data(spec, "pub(super) ");
+    {
+        let object = data.as_object_mut().expect("the model is an object");
+        object.insert("root".to_string(), Json::String(root.to_string()));
+        object.insert("modules".to_string(), Json::Array(MODULES.iter().map(|module| Json::String(module.to_string())).collect()));
     }
 
-{{#each component}}
-    pub fn contains_{{id}}(self) -> bool {
-        self.action.contains_{{id}}(self.id)
-    }
-    {{#if type}}
-        {{#if copy}}
-    pub fn {{id}}(self) -> Option<{{type}}> {
-        self.action.{{id}}(self.id)
-    }
-    pub fn {{id}}_ref(self) -> Option<&'a {{type}}> {
-        self.action.{{id}}_ref(self.id)
-    }
-        {{else}}
-    pub fn {{id}}(self) -> Option<&'a {{type}}> {
-        self.action.{{id}}(self.id)
+    let mut files = vec![(PathBuf::from(format!("{}.rs", root)), render(ROOT_TEMPLATE, &data, extensions)?)];
+    for (module, template) in module_templates() {
+        files.push((Path::new(root).join(format!("{}.rs", module)), render(&template, &data, extensions)?));
     }
-        {{/if}}
-    {{/if}}
-{{/each}}
+
+    Ok(files)
 }
 
-pub const NUM_ACTION_PROPERTIES: usize = {{num_action_properties}};
-const ACTION_PROPERTY_TYPE_SET_NUM_WORDS: usize = {{component_set_num_words}};
+fn read_file_to_string(path: &Path) -> Result<String, GenerateError> {
+    let mut string = String::new();
+    File::open(path)
+        .and_then(|mut file| file.read_to_string(&mut string))
+        .map_err(|error| GenerateError::Io { path: path.to_path_buf(), error })?;
 
-pub type ActionPropertyType = usize;
+    Ok(string)
+}
 
-pub mod action_property_type {
-    use std::usize;
+// Leaves the file alone if it already contains `string`, so its modification
+// time only changes with its contents and cargo doesn't rebuild code that
+// includes it. Otherwise the new contents are written beside it and renamed
+// into place, so the file is never left partially written.
+fn write_string_to_file(path: &Path, string: &str) -> Result<(), GenerateError> {
+    if let Ok(existing) = fs::read(path) {
+        if existing == string.as_bytes() {
+            return Ok(());
+        }
+    }
 
-{{#each action_property}}
-    pub const {{id_uppercase}}: usize = {{index}};
-{{/each}}
+    let temp_path = match path.file_name() {
+        Some(name) => path.with_file_name(format!(".{}.tmp", name.to_string_lossy())),
+        None => {
+            let error = io::Error::new(io::ErrorKind::InvalidInput, "not a file");
+            return Err(GenerateError::Io { path: path.to_path_buf(), error });
+        }
+    };
+
+    File::create(&temp_path)
+        .and_then(|mut file| file.write_all(string.as_bytes()))
+        .map_err(|error| GenerateError::Io { path: path.to_path_buf(), error })?;
+
+    fs::rename(&temp_path, path).map_err(|error| {
+        let _ = fs::remove_file(&temp_path);
+        GenerateError::Io { path: path.to_path_buf(), error }
+    })
 }
 
-pub struct ActionPropertyTypeSet {
-    bitfields: [usize; ACTION_PROPERTY_TYPE_SET_NUM_WORDS],
+// Tells cargo to rerun the build script when any of the files change
+fn rerun_if_changed<P: AsRef<Path>>(paths: &[P]) {
+    for path in paths {
+        println!("cargo:rerun-if-changed={}", path.as_ref().display());
+    }
 }
 
-pub struct ActionPropertyTypeSetIter {
-    bitfields: [usize; ACTION_PROPERTY_TYPE_SET_NUM_WORDS],
-    index: usize,
+/// Generates code from spec files, adding handlebars templates of its own to
+/// the generated code. Templates are rendered with the same data as the rest
+/// of the generated code: the spec, with its components and action properties
+/// as arrays in the order of their indices, each also having its `id`,
+/// `id_uppercase`, `index`, `set_index` and `set_bit`.
+///
+/// ```ignore
+/// let mut generator = genecs::Generator::new();
+/// generator.extend("ctx", "    pub fn num_positions(&self) -> usize { self.position.len() }");
+/// generator.generate("spec.toml", out_path);
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct Generator {
+    format: Option<SpecFormat>,
+    includes: Vec<PathBuf>,
+    lock: Option<PathBuf>,
+    extensions: Vec<(String, String)>,
 }
 
-impl ActionPropertyTypeSetIter {
-    fn new(bitfields: [usize; ACTION_PROPERTY_TYPE_SET_NUM_WORDS]) -> Self {
-        ActionPropertyTypeSetIter {
-            bitfields: bitfields,
-            index: 0,
+impl Generator {
+    pub fn new() -> Self {
+        Generator {
+            format: None,
+            includes: Vec::new(),
+            lock: None,
+            extensions: Vec::new(),
         }
     }
-}
 
-impl Iterator for ActionPropertyTypeSetIter {
-    type Item = ActionPropertyType;
-    fn next(&mut self) -> Option<Self::Item> {
-        while self.index < ACTION_PROPERTY_TYPE_SET_NUM_WORDS && self.bitfields[self.index] == 0 {
-            self.index += 1;
-        }
-        if self.index == ACTION_PROPERTY_TYPE_SET_NUM_WORDS {
-            return None;
+    /// Reads spec files as `format`. By default, the format of a spec file
+    /// is chosen by its extension, and specs with other extensions or given
+    /// as strings are read as toml.
+    pub fn format(&mut self, format: SpecFormat) -> &mut Self {
+        self.format = Some(format);
+        self
+    }
+
+    /// Merges the spec file at `path` into each spec, as though each spec
+    /// listed it first in `include`. This lets a crate defining common
+    /// components share them with the crates generating an ecs from their
+    /// own spec files. Like included files, it's read according to its
+    /// extension.
+    pub fn include<P: AsRef<Path>>(&mut self, path: P) -> &mut Self {
+        self.includes.push(path.as_ref().to_path_buf());
+        self
+    }
+
+    /// Checks the indices of components and action properties against the
+    /// lock file at `path`, failing instead of renumbering any recorded there.
+    /// Generating code records the current indices in the lock file, creating
+    /// it if needed, so it's best kept in version control beside the spec.
+    /// Entries removed from the spec move to its `removed` table, keeping
+    /// their indices from being reused. Removing an entry from the lock file
+    /// allows it to be renumbered, or its index to be reused. Build scripts
+    /// using `generate` or `generate_modules` rerun when it changes.
+    pub fn lock<P: AsRef<Path>>(&mut self, path: P) -> &mut Self {
+        self.lock = Some(path.as_ref().to_path_buf());
+        self
+    }
+
+    fn check_lock(&self, spec: &json::Object) -> Result<(), GenerateError> {
+        let path = match self.lock {
+            Some(ref path) => path,
+            None => return Ok(()),
+        };
+
+        if let Some(lock) = lock::read(path).map_err(|error| include::in_include(path, error))? {
+            let errors = lock::check(&schema::schema(spec), &lock);
+            if !errors.is_empty() {
+                return Err(GenerateError::Lock { path: path.clone(), errors });
+            }
         }
 
-        let trailing = self.bitfields[self.index].trailing_zeros() as usize;
-        self.bitfields[self.index] &= !(1 << trailing);
-        Some(self.index * WORD_BITS + trailing)
+        Ok(())
     }
-}
 
-impl ActionPropertyTypeSet {
-    pub fn new() -> Self {
-        ActionPropertyTypeSet {
-            bitfields: [0; COMPONENT_TYPE_SET_NUM_WORDS],
+    fn write_lock(&self, spec: &json::Object) -> Result<(), GenerateError> {
+        match self.lock {
+            Some(ref path) => {
+                let lock = lock::read(path).map_err(|error| include::in_include(path, error))?;
+                write_string_to_file(path, &lock::contents(&schema::schema(spec), lock.as_ref()))
+            }
+            None => Ok(()),
         }
     }
 
-    pub fn is_empty(&self) -> bool {
-        for b in &self.bitfields {
-            if *b != 0 {
-                return false;
-            }
+    fn includes(&self) -> Result<Specs, GenerateError> {
+        let mut specs = Specs::new();
+        for path in &self.includes {
+            let format = SpecFormat::from_path(path).unwrap_or(SpecFormat::Toml);
+            specs.read(path, format).map_err(|error| include::in_include(path, error))?;
         }
 
-        true
+        Ok(specs)
     }
 
-    pub fn clear(&mut self) {
-        for b in &mut self.bitfields {
-            *b = 0;
+    // Merges the spec files at `in_paths` and the files they include,
+    // returning the spec and every file read. Errors name the file they're
+    // in when there's more than one to choose from.
+    fn read_specs(&self, in_paths: &[&Path]) -> Result<(json::Object, Vec<PathBuf>), GenerateError> {
+        let mut specs = self.includes()?;
+        for path in in_paths {
+            let format = self.format.or_else(|| SpecFormat::from_path(path)).unwrap_or(SpecFormat::Toml);
+            specs.read(path, format).map_err(|error| {
+                if in_paths.len() > 1 { include::in_include(path, error) } else { error }
+            })?;
         }
-    }
 
-    pub fn iter(&self) -> ActionPropertyTypeSetIter {
-        ActionPropertyTypeSetIter::new(self.bitfields)
+        let (spec, files) = specs.finish()?;
+        self.check_lock(&spec)?;
+
+        Ok((spec, files))
     }
 
-{{#each action_property}}
-    pub fn contains_{{id}}(&self) -> bool {
-        self.bitfields[{{set_index}}] & (1 << {{set_bit}}) != 0
+    fn read_spec(&self, path: &Path) -> Result<json::Object, GenerateError> {
+        Ok(self.read_specs(&[path])?.0)
     }
 
-    pub fn insert_{{id}}(&mut self) {
-        self.bitfields[{{set_index}}] |= 1 << {{set_bit}};
+    fn parse_spec(&self, spec: &str) -> Result<json::Object, GenerateError> {
+        let mut specs = self.includes()?;
+        specs.add(spec.to_string(), self.format.unwrap_or(SpecFormat::Toml), None)?;
+
+        let (spec, _) = specs.finish()?;
+        self.check_lock(&spec)?;
+
+        Ok(spec)
     }
 
-    pub fn remove_{{id}}(&mut self) {
-        self.bitfields[{{set_index}}] &= !(1 << {{set_bit}});
+    fn write_code(&self, spec: &json::Object, out_path: &Path) -> Result<(), GenerateError> {
+        write_string_to_file(out_path, &generate_code(spec, &self.extensions)?)?;
+
+        self.write_lock(spec)
     }
-{{/each}}
-}
 
-pub struct EcsActionProperties {
-    property_types: ActionPropertyTypeSet,
-{{#each action_property}}
-    {{#if type}}
-    pub {{id}}: Option<{{type}}>,
-    {{else}}
-    pub {{id}}: bool,
-    {{/if}}
-{{/each}}
-}
+    fn write_modules(&self, spec: &json::Object, out_dir: &Path, root: &str) -> Result<(), GenerateError> {
+        let files = generate_module_code(spec, root, &self.extensions)?;
 
-impl EcsActionProperties {
-    pub fn new() -> Self {
-        EcsActionProperties {
-            property_types: ActionPropertyTypeSet::new(),
-{{#each action_property}}
-    {{#if type}}
-            {{id}}: None,
-    {{else}}
-            {{id}}: false,
-    {{/if}}
-{{/each}}
+        let module_dir = out_dir.join(root);
+        fs::create_dir_all(&module_dir).map_err(|error| GenerateError::Io { path: module_dir, error })?;
+
+        for (path, code) in files {
+            write_string_to_file(&out_dir.join(path), &code)?;
         }
+
+        self.write_lock(spec)
     }
 
-    pub fn clear(&mut self) {
-        for property_type in self.property_types.iter() {
-            match property_type {
-{{#each action_property}}
-                action_property_type::{{id_uppercase}} => {
-    {{#if type}}
-                    self.{{id}} = None;
-    {{else}}
-                    self.{{id}} = false;
-    {{/if}}
-                }
-{{/each}}
-                _ => panic!("Invalid action property type: {}", property_type),
+    // The files which should rerun the build script when they change: the
+    // spec files read, or those given when reading them failed, and the lock
+    // file. Cargo reruns failed build scripts anyway, but watching the given
+    // files keeps the build script watching them once it succeeds.
+    fn watched(&self, in_paths: &[&Path], read: Option<&[PathBuf]>) -> Vec<PathBuf> {
+        let mut files = match read {
+            Some(files) => files.to_vec(),
+            None => self.includes.iter().cloned().chain(in_paths.iter().map(|path| path.to_path_buf())).collect(),
+        };
+        files.extend(self.lock.iter().cloned());
+
+        files
+    }
+
+    // Tells cargo to rerun the build script when any of the files it depends
+    // on change, and panics if reading them or writing the code fails
+    fn build<F>(&self, in_paths: &[&Path], write: F) where F: FnOnce(&json::Object) -> Result<(), GenerateError> {
+        let result = self.read_specs(in_paths);
+        rerun_if_changed(&self.watched(in_paths, result.as_ref().ok().map(|(_, files)| &files[..])));
+
+        if let Err(error) = result.and_then(|(spec, _)| write(&spec)) {
+            match *in_paths {
+                [path] => panic!("{}: {}", path.display(), error),
+                _ => panic!("{}", error),
             }
         }
-        self.property_types.clear();
     }
 
-{{#each action_property}}
-    {{#if type}}
-    pub fn insert_{{id}}(&mut self, value: {{type}}) {
-        self.{{id}} = Some(value);
-        self.property_types.insert_{{id}}();
+    /// Adds a template at the extension point named `point`, after any
+    /// templates already added there. The extension points are:
+    ///
+    /// - `ctx`, `action`, `entity_ref`: the end of the `impl` blocks of
+    ///   `EcsCtx`, `EcsAction` and `EntityRef`, for adding methods
+    /// - `ctx_insert`, `action_insert`: the start of `EcsCtx::insert_{id}`
+    ///   and `EcsAction::insert_{id}`, rendered once for each component with
+    ///   the component as the context. `entity` is the entity being inserted
+    ///   into, and `value` the component's value if it has a type.
+    ///
+    /// Adding a template at any other point is an error when generating code.
+    pub fn extend(&mut self, point: &str, template: &str) -> &mut Self {
+        self.extensions.push((point.to_string(), template.to_string()));
+        self
     }
-        {{#if copy}}
-    pub fn {{id}}(&self) -> Option<{{type}}> {
-        self.{{id}}
+
+    /// Checks the spec file at `in_path`, and any files it includes, without
+    /// generating code from it.
+    pub fn check<P: AsRef<Path>>(&self, in_path: P) -> Result<(), GenerateError> {
+        self.read_spec(in_path.as_ref()).map(|_| ())
     }
-    pub fn {{id}}_ref(&self) -> Option<&{{type}}> {
-        self.{{id}}.as_ref()
+
+    /// Returns the code generated from the spec file at `in_path`.
+    pub fn code<P: AsRef<Path>>(&self, in_path: P) -> Result<String, GenerateError> {
+        generate_code(&self.read_spec(in_path.as_ref())?, &self.extensions)
     }
-        {{else}}
-    pub fn {{id}}(&self) -> Option<&{{type}}> {
-        self.{{id}}.as_ref()
+
+    /// Returns the code generated from the contents of a spec file.
+    pub fn code_from_spec(&self, spec: &str) -> Result<String, GenerateError> {
+        generate_code(&self.parse_spec(spec)?, &self.extensions)
     }
-        {{/if}}
-    pub fn contains_{{id}}(&self) -> bool {
-        self.{{id}}.is_some()
+
+    /// Returns the schema of the ecs described by the spec file at
+    /// `in_path`, for tools which generate code of their own alongside the
+    /// ecs.
+    pub fn schema<P: AsRef<Path>>(&self, in_path: P) -> Result<EcsSchema, GenerateError> {
+        Ok(schema::schema(&self.read_spec(in_path.as_ref())?))
     }
-    {{else}}
-    pub fn insert_{{id}}(&mut self) {
-        self.{{id}} = true;
-        self.property_types.insert_{{id}}();
+
+    /// Returns the schema of the ecs described by the contents of a spec
+    /// file.
+    pub fn schema_from_spec(&self, spec: &str) -> Result<EcsSchema, GenerateError> {
+        Ok(schema::schema(&self.parse_spec(spec)?))
     }
-    pub fn contains_{{id}}(&self) -> bool {
-        self.{{id}}
+
+    /// Writes the code generated from the spec file at `in_path` to
+    /// `out_path`, unless `out_path` already contains it.
+    pub fn try_generate<P: AsRef<Path>, Q: AsRef<Path>>(&self, in_path: P, out_path: Q) -> Result<(), GenerateError> {
+        self.write_code(&self.read_spec(in_path.as_ref())?, out_path.as_ref())
     }
-    {{/if}}
-    pub fn remove_{{id}}(&mut self) ->
-    {{#if type}}
-        Option<{{type}}>
-    {{else}}
-        bool
-    {{/if}}
-    {
-    {{#if type}}
-        mem::replace(&mut self.{{id}}, None)
-    {{else}}
-        mem::replace(&mut self.{{id}}, false)
-    {{/if}}
+
+    /// Generates the ecs described by the spec file at `in_path` as a module
+    /// named `root`, split into the submodules `types`, `storage`, `ctx`,
+    /// `action` and `entity_ref`. This writes `{root}.rs` to `out_dir`, and
+    /// the submodules to the `{root}` directory beside it. The root module
+    /// re-exports the commonly used types, such as `EcsCtx` and `EcsAction`.
+    pub fn try_generate_modules<P: AsRef<Path>, Q: AsRef<Path>>(&self, in_path: P, out_dir: Q, root: &str) -> Result<(), GenerateError> {
+        self.write_modules(&self.read_spec(in_path.as_ref())?, out_dir.as_ref(), root)
+    }
+
+    /// Like `try_generate`, but panics with a description of the error on
+    /// failure. Convenient for build scripts, where a panic aborts the build.
+    /// Also tells cargo to rerun the build script when the spec file, or any
+    /// file it includes, changes.
+    pub fn generate<P: AsRef<Path>, Q: AsRef<Path>>(&self, in_path: P, out_path: Q) {
+        self.build(&[in_path.as_ref()], |spec| self.write_code(spec, out_path.as_ref()));
+    }
+
+    /// Like `try_generate_modules`, but panics with a description of the
+    /// error on failure. Also tells cargo to rerun the build script when the
+    /// spec file, or any file it includes, changes.
+    pub fn generate_modules<P: AsRef<Path>, Q: AsRef<Path>>(&self, in_path: P, out_dir: Q, root: &str) {
+        self.build(&[in_path.as_ref()], |spec| self.write_modules(spec, out_dir.as_ref(), root));
     }
-{{/each}}
 }
 
-pub struct EntityRefIter<'a, I: Iterator<Item=EntityId>> {
-    ctx: &'a EcsCtx,
-    iter: I,
+/// Checks the spec file at `in_path` without generating code from it.
+pub fn check_spec<P: AsRef<Path>>(in_path: P) -> Result<(), GenerateError> {
+    Generator::new().check(in_path)
 }
 
-impl<'a, I: Iterator<Item=EntityId>> EntityRefIter<'a, I> {
-    fn new(ctx: &'a EcsCtx, iter: I) -> Self {
-        EntityRefIter {
-            ctx: ctx,
-            iter: iter,
-        }
-    }
+/// Returns the code generated from the spec file at `in_path`.
+pub fn generate_ecs_code<P: AsRef<Path>>(in_path: P) -> Result<String, GenerateError> {
+    Generator::new().code(in_path)
 }
 
-impl<'a, I: Iterator<Item=EntityId>> Iterator for EntityRefIter<'a, I> {
-    type Item = EntityRef<'a>;
-    fn next(&mut self) -> Option<Self::Item> {
-        self.iter.next().map(|id| self.ctx.entity(id))
-    }
+/// Returns the code generated from the contents of a toml spec file.
+pub fn generate_ecs_code_from_spec(spec: &str) -> Result<String, GenerateError> {
+    Generator::new().code_from_spec(spec)
 }
-"#;
 
-fn generate_code(mut toml: String) -> String {
-    // turn the toml string into json for compatibility with handlebars
-    let mut json = Toml::as_json(&mut toml).unwrap();
+/// Writes the code generated from the spec file at `in_path` to `out_path`,
+/// unless `out_path` already contains it.
+pub fn try_generate_ecs<P: AsRef<Path>, Q: AsRef<Path>>(in_path: P, out_path: Q) -> Result<(), GenerateError> {
+    Generator::new().try_generate(in_path, out_path)
+}
 
-    let mut component_clones = HashMap::new();
+/// Writes the code generated from the spec files at `in_paths` to
+/// `out_path`. The files are merged in order, as though the last included
+/// the others, so each component and action property may only be defined in
+/// one of them.
+pub fn try_generate_ecs_from_specs<P: AsRef<Path>, Q: AsRef<Path>>(in_paths: &[P], out_path: Q) -> Result<(), GenerateError> {
+    let generator = Generator::new();
+    let in_paths: Vec<&Path> = in_paths.iter().map(AsRef::as_ref).collect();
 
-    let num_components = json.search("component").unwrap().as_object().unwrap().len();
-    json.as_object_mut().unwrap().insert("num_components".to_string(), Json::U64(num_components as u64));
+    generator.write_code(&generator.read_specs(&in_paths)?.0, out_path.as_ref())
+}
 
-    let word_size = mem::size_of::<usize>();
-    json.as_object_mut().unwrap().insert("word_size".to_string(), Json::U64(word_size as u64));
+/// See `Generator::try_generate_modules`.
+pub fn try_generate_ecs_modules<P: AsRef<Path>, Q: AsRef<Path>>(in_path: P, out_dir: Q, root: &str) -> Result<(), GenerateError> {
+    Generator::new().try_generate_modules(in_path, out_dir, root)
+}
 
-    let word_bits = word_size * 8;
-    json.as_object_mut().unwrap().insert("word_bits".to_string(), Json::U64(word_bits as u64));
+/// Returns the schema of the ecs described by the spec file at `in_path`,
+/// for tools which generate code of their own alongside the ecs.
+pub fn parse_ecs_schema<P: AsRef<Path>>(in_path: P) -> Result<EcsSchema, GenerateError> {
+    Generator::new().schema(in_path)
+}
 
-    let component_set_num_words = (num_components - 1) / word_bits + 1;
-    json.as_object_mut().unwrap().insert("component_set_num_words".to_string(), Json::U64(component_set_num_words as u64));
+/// Returns the schema of the ecs described by the contents of a toml spec
+/// file.
+pub fn parse_ecs_schema_from_spec(spec: &str) -> Result<EcsSchema, GenerateError> {
+    Generator::new().schema_from_spec(spec)
+}
 
-    let mut index = 0;
-    for (id, component) in json.as_object_mut().unwrap().get_mut("component").unwrap().as_object_mut().unwrap().iter_mut() {
-        let component_obj = component.as_object_mut().unwrap();
-        component_obj.insert("index".to_string(), Json::U64(index as u64));
-        component_obj.insert("set_index".to_string(), Json::U64((index / word_bits) as u64));
-        component_obj.insert("set_bit".to_string(), Json::U64((index % word_bits) as u64));
-        component_obj.insert("id".to_string(), Json::String(id.to_string()));
-        component_obj.insert("id_uppercase".to_string(), Json::String(id.to_uppercase()));
+/// Lists the differences between two spec files, both of which must be
+/// valid.
+pub fn diff_specs<P: AsRef<Path>, Q: AsRef<Path>>(old_path: P, new_path: Q) -> Result<Vec<SpecChange>, GenerateError> {
+    let generator = Generator::new();
+    let old = generator.read_spec(old_path.as_ref())?;
+    let new = generator.read_spec(new_path.as_ref())?;
 
-        let maybe_container = component_obj.get("container").map(|container| {
-            container.clone()
-        });
+    Ok(diff::diff(&old, &new))
+}
 
-        if let Some(container) = maybe_container {
-            component_obj.insert(container.as_string().unwrap().to_string(), Json::Boolean(true));
-        }
+/// A JSON Schema describing the structure of spec files, for use by editors
+/// and other tools. Some rules, such as which ids are allowed, are only
+/// enforced when generating code.
+pub fn spec_schema() -> String {
+    validate::schema().pretty().to_string()
+}
 
-        component_clones.insert(id.to_string(), component_obj.clone());
+/// Like `try_generate_ecs`, but panics with a description of the error on
+/// failure. Convenient for build scripts, where a panic aborts the build.
+/// Also tells cargo to rerun the build script when the spec file changes.
+pub fn generate_ecs<P: AsRef<Path>, Q: AsRef<Path>>(in_path: P, out_path: Q) {
+    Generator::new().generate(in_path, out_path)
+}
 
-        index += 1;
-    }
+/// Like `try_generate_ecs_modules`, but panics with a description of the
+/// error on failure. Also tells cargo to rerun the build script when the spec
+/// file changes.
+pub fn generate_ecs_modules<P: AsRef<Path>, Q: AsRef<Path>>(in_path: P, out_dir: Q, root: &str) {
+    Generator::new().generate_modules(in_path, out_dir, root)
+}
 
-    let num_action_properties = if let Some(action_property) = json.search("action_property") {
-        action_property.as_object().unwrap().len()
-    } else {
-        0
-    };
+/// Like `try_generate_ecs_from_specs`, but panics with a description of the
+/// error on failure. Also tells cargo to rerun the build script when any of
+/// the spec files, or the files they include, change.
+pub fn generate_ecs_from_specs<P: AsRef<Path>, Q: AsRef<Path>>(in_paths: &[P], out_path: Q) {
+    let generator = Generator::new();
+    let in_paths: Vec<&Path> = in_paths.iter().map(AsRef::as_ref).collect();
 
-    json.as_object_mut().unwrap().insert("num_action_properties".to_string(), Json::U64(num_action_properties as u64));
-    index = 0;
+    generator.build(&in_paths, |spec| generator.write_code(spec, out_path.as_ref()))
+}
 
-    if let Some(mut action_property) = json.as_object_mut().unwrap().get_mut("action_property") {
-        for (id, action_property) in action_property.as_object_mut().unwrap().iter_mut() {
-            let action_property_obj = action_property.as_object_mut().unwrap();
-            action_property_obj.insert("index".to_string(), Json::U64(index as u64));
-            action_property_obj.insert("set_index".to_string(), Json::U64((index / word_bits) as u64));
-            action_property_obj.insert("set_bit".to_string(), Json::U64((index % word_bits) as u64));
-            action_property_obj.insert("id".to_string(), Json::String(id.to_string()));
-            action_property_obj.insert("id_uppercase".to_string(), Json::String(id.to_uppercase()));
+#[cfg(test)]
+mod tests {
+    use std::path::{Path, PathBuf};
 
-            index += 1;
-        }
-    }
+    use super::Generator;
 
-    let mut handlebars = Handlebars::new();
+    const DIR: &str = "tests/specs/include";
 
-    // prevent xml escaping
-    handlebars.register_escape_fn(|input| input.to_string());
-    handlebars.template_render(TEMPLATE, &json).unwrap()
-}
+    fn path(file: &str) -> PathBuf {
+        Path::new(DIR).join(file)
+    }
 
-fn read_file_to_string<P: AsRef<Path>>(path: P) -> String {
-    let mut file = File::open(path).unwrap();
-    let mut string = String::new();
-    file.read_to_string(&mut string).unwrap();
+    #[test]
+    fn watches_included_spec_files() {
+        let generator = Generator::new();
+        let spec = path("relative/ecs.toml");
+        let (_, files) = generator.read_specs(&[&spec]).unwrap();
 
-    string
-}
+        // each file once, as it was named by the file including it
+        assert_eq!(generator.watched(&[&spec], Some(&files)), vec![
+            path("relative/parts/../shared/actions.yaml"),
+            path("relative/parts/bodies.toml"),
+            spec.clone(),
+        ]);
+    }
 
-pub fn generate_ecs<P: AsRef<Path>, Q: AsRef<Path>>(in_path: P, out_path: Q) {
+    #[test]
+    fn watches_the_lock_file() {
+        let mut generator = Generator::new();
+        generator.lock(path("relative/ecs.lock"));
+        let spec = path("relative/ecs.toml");
+        let (_, files) = generator.read_specs(&[&spec]).unwrap();
 
-    let string = read_file_to_string(in_path);
+        let watched = generator.watched(&[&spec], Some(&files));
+        assert_eq!(watched.last(), Some(&path("relative/ecs.lock")));
+        assert_eq!(watched.len(), 4);
+    }
 
-    let output_string = generate_code(string);
+    #[test]
+    fn watches_the_given_files_when_reading_fails() {
+        let mut generator = Generator::new();
+        generator.include(path("relative/shared/actions.yaml")).lock(path("error/ecs.lock"));
+        let spec = path("error/ecs.toml");
+        assert!(generator.read_specs(&[&spec]).is_err());
 
-    let mut outfile = File::create(out_path).unwrap();
-    write!(outfile, "{}", output_string).unwrap();
+        assert_eq!(generator.watched(&[&spec], None), vec![
+            path("relative/shared/actions.yaml"),
+            spec.clone(),
+            path("error/ecs.lock"),
+        ]);
+    }
 }
@@ -0,0 +1,151 @@
+use std::collections::BTreeMap;
+use std::io;
+use std::path::Path;
+
+use rustc_serialize::json::{self, Json};
+
+use error::{GenerateError, SchemaError};
+use format::{self, SpecFormat};
+use schema::EcsSchema;
+use validate;
+
+const HEADER: &str = "# Automatically generated by genecs. Records the index of each component
+# and action property, so that generating code which renumbers them fails.
+# Entries removed from the spec move to [removed], so their indices aren't
+# reused. Remove an entry to let it be renumbered, or to free its index.
+";
+
+const SECTIONS: &[&str] = &["component", "action_property"];
+
+// The index of each component and action property, by section and id
+fn indices(schema: &EcsSchema) -> BTreeMap<(&'static str, &str), usize> {
+    let components = schema.components.iter().map(|component| (("component", component.id.as_str()), component.index));
+    let action_properties = schema.action_properties.iter()
+        .map(|action_property| (("action_property", action_property.id.as_str()), action_property.index));
+
+    components.chain(action_properties).collect()
+}
+
+// The indices in a table of the lock file, reporting any which aren't tables
+// of indices as `section`
+fn table<'a>(table: Option<&'a Json>, section: &str, errors: &mut Vec<SchemaError>) -> BTreeMap<&'a str, usize> {
+    let table = match table {
+        Some(Json::Object(table)) => table,
+        Some(_) => {
+            errors.push(SchemaError::new(section, None, None, "expected a table in the lock file"));
+            return BTreeMap::new();
+        }
+        None => return BTreeMap::new(),
+    };
+
+    table.iter().filter_map(|(id, index)| match validate::index(index) {
+        Some(index) => Some((id.as_str(), index)),
+        None => {
+            errors.push(SchemaError::new(section, Some(id), Some("index"), "expected an index in the lock file"));
+            None
+        }
+    }).collect()
+}
+
+// The indices recorded in a lock file for a section, including those of
+// removed entries, which are recorded under `removed`
+fn locked<'a>(lock: &'a json::Object, section: &str, errors: &mut Vec<SchemaError>) -> BTreeMap<&'a str, usize> {
+    let removed = match lock.get("removed") {
+        Some(Json::Object(removed)) => table(removed.get(section), &format!("removed.{}", section), errors),
+        _ => BTreeMap::new(),
+    };
+
+    let mut locked = table(lock.get(section), section, errors);
+    for (id, index) in removed {
+        locked.entry(id).or_insert(index);
+    }
+
+    locked
+}
+
+// The contents of the lock file at `path`, or None if there isn't one yet
+pub fn read(path: &Path) -> Result<Option<json::Object>, GenerateError> {
+    match ::read_file_to_string(path) {
+        Ok(source) => Ok(Some(format::parse(SpecFormat::Toml, source)?)),
+        Err(GenerateError::Io { ref error, .. }) if error.kind() == io::ErrorKind::NotFound => Ok(None),
+        Err(error) => Err(error),
+    }
+}
+
+// Compares the indices of a schema with those in a lock file. Components and
+// action properties must keep their index, which can't be reused by another
+// once they are removed, until they are removed from the lock file too.
+pub fn check(schema: &EcsSchema, lock: &json::Object) -> Vec<SchemaError> {
+    let current = indices(schema);
+    let mut errors = Vec::new();
+
+    match lock.get("removed") {
+        Some(Json::Object(_)) | None => (),
+        Some(_) => errors.push(SchemaError::new("removed", None, None, "expected a table in the lock file")),
+    }
+
+    for &section in SECTIONS {
+        let locked = locked(lock, section, &mut errors);
+
+        for (&id, &index) in &locked {
+            match current.get(&(section, id)) {
+                Some(&current_index) if current_index != index => {
+                    errors.push(SchemaError::new(section, Some(id), Some("index"), &format!(
+                        "renumbered from {} to {} (pin it with index = {}, or remove it from the lock file)",
+                        index, current_index, index)));
+                }
+                Some(_) => (),
+                None => {
+                    let reused = current.iter().find(|&(&(other_section, other), &other_index)| {
+                        other_section == section && other_index == index && !locked.contains_key(other)
+                    });
+                    if let Some((&(_, other), _)) = reused {
+                        errors.push(SchemaError::new(section, Some(other), Some("index"), &format!(
+                            "index {} belonged to the removed {} {} (remove it from the lock file to reuse its index)",
+                            index, section, id)));
+                    }
+                }
+            }
+        }
+    }
+
+    errors
+}
+
+// The lock file recording the indices of a schema, in the order of the
+// indices, followed by the indices of the entries in the previous lock file
+// which have been removed from the schema
+pub fn contents(schema: &EcsSchema, lock: Option<&json::Object>) -> String {
+    let current = indices(schema);
+    let mut contents = HEADER.to_string();
+
+    contents.push_str("\n[component]\n");
+    for component in &schema.components {
+        contents.push_str(&format!("{} = {}\n", component.id, component.index));
+    }
+
+    contents.push_str("\n[action_property]\n");
+    for action_property in &schema.action_properties {
+        contents.push_str(&format!("{} = {}\n", action_property.id, action_property.index));
+    }
+
+    for &section in SECTIONS {
+        let mut removed: Vec<(usize, &str)> = match lock {
+            Some(lock) => locked(lock, section, &mut Vec::new()).into_iter()
+                .filter(|&(id, _)| !current.contains_key(&(section, id)))
+                .map(|(id, index)| (index, id))
+                .collect(),
+            None => Vec::new(),
+        };
+        removed.sort();
+
+        if !removed.is_empty() {
+            contents.push_str(&format!("\n[removed.{}]\n", section));
+            for (index, id) in removed {
+                contents.push_str(&format!("{} = {}\n", id, index));
+            }
+        }
+    }
+
+    contents
+}
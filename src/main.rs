@@ -0,0 +1,224 @@
+extern crate genecs;
+
+use std::env;
+use std::io::{self, Write};
+use std::process;
+
+use genecs::{GenerateError, Generator, SpecFormat};
+
+const USAGE: &str = "Usage:
+    genecs generate SPEC [-o OUTPUT]    generate code, writing it to OUTPUT or stdout
+    genecs generate SPEC --modules DIR [--root NAME]
+                                        generate code split into modules, writing
+                                        DIR/NAME.rs and DIR/NAME/ (NAME defaults to ecs)
+    genecs check SPEC...                check that each spec generates code
+    genecs diff OLD NEW                 list the differences between two specs
+    genecs print-schema                 print a JSON Schema describing specs
+    genecs help                         print this message
+
+Specs are read as toml, json, yaml or ron according to their extension, or
+as toml if it's none of those. generate --format FORMAT reads SPEC as FORMAT.
+A spec can merge other specs into itself with include = [\"core.toml\", ...],
+where paths are relative to the including spec. generate --lock FILE fails
+rather than renumber the components and action properties recorded in FILE,
+and records them there when writing OUTPUT or DIR.
+
+Exits with status 1 if a spec is invalid, and 2 if the arguments are.";
+
+enum Command {
+    Generate {
+        spec: String,
+        format: Option<SpecFormat>,
+        lock: Option<String>,
+        output: Option<String>,
+    },
+    GenerateModules {
+        spec: String,
+        format: Option<SpecFormat>,
+        lock: Option<String>,
+        dir: String,
+        root: String,
+    },
+    Check(Vec<String>),
+    Diff {
+        old: String,
+        new: String,
+    },
+    PrintSchema,
+    Help,
+}
+
+fn parse_args(args: &[String]) -> Result<Command, String> {
+    let (command, rest) = match args.split_first() {
+        Some((command, rest)) => (command.as_str(), rest),
+        None => return Err("missing command".to_string()),
+    };
+
+    match command {
+        "generate" => {
+            let mut spec = None;
+            let mut output = None;
+            let mut dir = None;
+            let mut root = None;
+            let mut format = None;
+            let mut lock = None;
+            let mut rest = rest.iter();
+            while let Some(arg) = rest.next() {
+                let option = match arg.as_str() {
+                    "-o" | "--output" => &mut output,
+                    "--modules" => &mut dir,
+                    "--root" => &mut root,
+                    "--format" => &mut format,
+                    "--lock" => &mut lock,
+                    _ if spec.is_none() => {
+                        spec = Some(arg.clone());
+                        continue;
+                    }
+                    _ => return Err(format!("unexpected argument: {}", arg)),
+                };
+                match rest.next() {
+                    Some(value) => *option = Some(value.clone()),
+                    None => return Err(format!("{} requires a value", arg)),
+                }
+            }
+            let spec = match spec {
+                Some(spec) => spec,
+                None => return Err("generate requires a spec".to_string()),
+            };
+            let format = match format {
+                Some(name) => match SpecFormat::from_name(&name) {
+                    Some(format) => Some(format),
+                    None => return Err(format!("unknown format: {} (expected toml, json, yaml or ron)", name)),
+                },
+                None => None,
+            };
+            match (output, dir, root) {
+                (Some(_), Some(_), _) => Err("-o and --modules can't be used together".to_string()),
+                (_, None, Some(_)) => Err("--root requires --modules".to_string()),
+                (output, None, None) => Ok(Command::Generate { spec, format, lock, output }),
+                (None, Some(dir), root) => {
+                    let root = root.unwrap_or_else(|| "ecs".to_string());
+                    Ok(Command::GenerateModules { spec, format, lock, dir, root })
+                }
+            }
+        }
+        "check" => {
+            if rest.is_empty() {
+                Err("check requires at least one spec".to_string())
+            } else {
+                Ok(Command::Check(rest.to_vec()))
+            }
+        }
+        "diff" => match rest {
+            [old, new] => Ok(Command::Diff { old: old.clone(), new: new.clone() }),
+            _ => Err("diff requires two specs".to_string()),
+        },
+        "print-schema" if rest.is_empty() => Ok(Command::PrintSchema),
+        "help" | "-h" | "--help" => Ok(Command::Help),
+        _ => Err(format!("unknown command: {}", args.join(" "))),
+    }
+}
+
+fn report(spec: &str, error: &GenerateError) {
+    match *error {
+        // io errors already name the file
+        GenerateError::Io { .. } => eprintln!("{}", error),
+        _ => eprintln!("{}: {}", spec, error),
+    }
+}
+
+fn generator(format: Option<SpecFormat>, lock: Option<String>) -> Generator {
+    let mut generator = Generator::new();
+    if let Some(format) = format {
+        generator.format(format);
+    }
+    if let Some(lock) = lock {
+        generator.lock(lock);
+    }
+
+    generator
+}
+
+fn run(command: Command) -> i32 {
+    match command {
+        Command::Generate { spec, format, lock, output } => {
+            let generator = generator(format, lock);
+            let result = match output {
+                Some(output) => generator.try_generate(&spec, output),
+                None => generator.code(&spec).and_then(|code| {
+                    io::stdout().write_all(code.as_bytes())
+                        .map_err(|error| GenerateError::Io { path: "<stdout>".into(), error })
+                }),
+            };
+            match result {
+                Ok(()) => 0,
+                Err(error) => {
+                    report(&spec, &error);
+                    1
+                }
+            }
+        }
+        Command::GenerateModules { spec, format, lock, dir, root } => {
+            match generator(format, lock).try_generate_modules(&spec, dir, &root) {
+                Ok(()) => 0,
+                Err(error) => {
+                    report(&spec, &error);
+                    1
+                }
+            }
+        }
+        Command::Check(specs) => {
+            let mut status = 0;
+            for spec in specs {
+                if let Err(error) = genecs::check_spec(&spec) {
+                    report(&spec, &error);
+                    status = 1;
+                }
+            }
+            status
+        }
+        Command::Diff { old, new } => {
+            // check each spec separately, so errors name the spec they're in
+            for spec in &[&old, &new] {
+                if let Err(error) = genecs::check_spec(spec) {
+                    report(spec, &error);
+                    return 1;
+                }
+            }
+            match genecs::diff_specs(&old, &new) {
+                Ok(changes) => {
+                    for change in changes {
+                        println!("{}", change);
+                    }
+                    0
+                }
+                Err(error) => {
+                    eprintln!("{}", error);
+                    1
+                }
+            }
+        }
+        Command::PrintSchema => {
+            println!("{}", genecs::spec_schema());
+            0
+        }
+        Command::Help => {
+            println!("{}", USAGE);
+            0
+        }
+    }
+}
+
+fn main() {
+    let args: Vec<String> = env::args().skip(1).collect();
+
+    let command = match parse_args(&args) {
+        Ok(command) => command,
+        Err(message) => {
+            eprintln!("genecs: {}\n\n{}", message, USAGE);
+            process::exit(2);
+        }
+    };
+
+    process::exit(run(command));
+}
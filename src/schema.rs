@@ -0,0 +1,276 @@
+use std::cmp;
+use std::collections::BTreeMap;
+use std::mem;
+
+use rustc_serialize::json::{self, Json};
+
+use validate;
+
+/// The ecs described by a spec file, with everything the generated code
+/// derives from it. Components and action properties are in the order of
+/// their indices, which follow the alphabetical order of their ids apart from
+/// those pinned with an `index` key. Pinned indices can leave gaps, so an
+/// entry's index isn't necessarily its position.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EcsSchema {
+    pub components: Vec<ComponentSchema>,
+    pub action_properties: Vec<ActionPropertySchema>,
+    pub imports: Vec<String>,
+    pub dynamic: bool,
+    /// The size in bytes of the words making up sets of component and action
+    /// property types
+    pub word_size: usize,
+    pub word_bits: usize,
+    /// The number of words in a set of component or action property types
+    pub component_set_num_words: usize,
+}
+
+/// A component. Components without a type are flags, which entities either
+/// have or don't. `set_index` and `set_bit` locate the component's bit in a
+/// set of component types.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ComponentSchema {
+    pub id: String,
+    pub index: usize,
+    pub set_index: usize,
+    pub set_bit: usize,
+    pub ty: Option<String>,
+    pub container: Option<Container>,
+    pub copy: bool,
+    pub storage: Storage,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ActionPropertySchema {
+    pub id: String,
+    pub index: usize,
+    pub set_index: usize,
+    pub set_bit: usize,
+    pub ty: Option<String>,
+    pub copy: bool,
+}
+
+/// The type wrapping each value of a component, allowing it to be mutated
+/// through a shared reference to the ecs
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Container {
+    RefCell,
+    UnsafeCell,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Storage {
+    BTree,
+    Hash,
+    DenseVec,
+    SparseSet,
+}
+
+impl EcsSchema {
+    /// One more than the highest component index, which is the number of
+    /// components unless pinned indices leave gaps
+    pub fn num_components(&self) -> usize {
+        self.components.last().map_or(0, |component| component.index + 1)
+    }
+
+    /// One more than the highest action property index
+    pub fn num_action_properties(&self) -> usize {
+        self.action_properties.last().map_or(0, |action_property| action_property.index + 1)
+    }
+}
+
+impl Container {
+    /// The name of the container type, as it appears in spec files
+    pub fn name(self) -> &'static str {
+        match self {
+            Container::RefCell => "RefCell",
+            Container::UnsafeCell => "UnsafeCell",
+        }
+    }
+}
+
+impl Storage {
+    /// The name of the storage, as it appears in spec files
+    pub fn name(self) -> &'static str {
+        match self {
+            Storage::BTree => "btree",
+            Storage::Hash => "hash",
+            Storage::DenseVec => "dense_vec",
+            Storage::SparseSet => "sparse_set",
+        }
+    }
+}
+
+impl ComponentSchema {
+    // the generated type storing the component
+    fn storage_type(&self) -> &'static str {
+        match (self.ty.is_some(), self.storage) {
+            (true, Storage::BTree) => "EntityMap",
+            (true, Storage::Hash) => "EntityHashMap",
+            (true, Storage::DenseVec) => "EntityDenseMap",
+            (true, Storage::SparseSet) => "EntitySparseMap",
+            (false, Storage::BTree) => "EntitySet",
+            (false, Storage::Hash) => "EntityHashSet",
+            (false, Storage::DenseVec) => "EntityDenseSet",
+            (false, Storage::SparseSet) => "EntitySparseSet",
+        }
+    }
+}
+
+// The entries of a section in the order of their indices, with their
+// indices. Entries with an `index` take that index, and the rest fill the
+// lowest remaining indices in alphabetical order, as json objects are ordered
+// by key. Pinned indices beyond the number of entries leave gaps.
+fn entries<'a>(root: &'a json::Object, section: &str) -> Vec<(usize, &'a String, &'a json::Object)> {
+    let entries = match root.get(section).and_then(Json::as_object) {
+        Some(entries) => entries,
+        None => return Vec::new(),
+    };
+
+    let mut indexed = BTreeMap::new();
+    let mut unpinned = Vec::new();
+    for (id, entry) in entries {
+        if let Some(entry) = entry.as_object() {
+            match entry.get("index").and_then(validate::index) {
+                Some(index) => {
+                    indexed.insert(index, (id, entry));
+                }
+                None => unpinned.push((id, entry)),
+            }
+        }
+    }
+
+    let mut index = 0;
+    for (id, entry) in unpinned {
+        while indexed.contains_key(&index) {
+            index += 1;
+        }
+        indexed.insert(index, (id, entry));
+    }
+
+    indexed.into_iter().map(|(index, (id, entry))| (index, id, entry)).collect()
+}
+
+fn string(entry: &json::Object, key: &str) -> Option<String> {
+    entry.get(key).and_then(Json::as_string).map(str::to_string)
+}
+
+fn boolean(entry: &json::Object, key: &str) -> bool {
+    entry.get(key).and_then(Json::as_boolean).unwrap_or(false)
+}
+
+// The schema of a spec which has been validated
+pub fn schema(root: &json::Object) -> EcsSchema {
+    let word_size = mem::size_of::<usize>();
+    let word_bits = word_size * 8;
+
+    let components: Vec<ComponentSchema> = entries(root, "component").into_iter().map(|(index, id, entry)| {
+        ComponentSchema {
+            id: id.clone(),
+            index,
+            set_index: index / word_bits,
+            set_bit: index % word_bits,
+            ty: string(entry, "type"),
+            container: entry.get("container").and_then(Json::as_string).map(|container| match container {
+                "UnsafeCell" => Container::UnsafeCell,
+                _ => Container::RefCell,
+            }),
+            copy: boolean(entry, "copy"),
+            storage: match entry.get("storage").and_then(Json::as_string) {
+                Some("hash") => Storage::Hash,
+                Some("dense_vec") => Storage::DenseVec,
+                Some("sparse_set") => Storage::SparseSet,
+                _ => Storage::BTree,
+            },
+        }
+    }).collect();
+
+    let action_properties = entries(root, "action_property").into_iter().map(|(index, id, entry)| {
+        ActionPropertySchema {
+            id: id.clone(),
+            index,
+            set_index: index / word_bits,
+            set_bit: index % word_bits,
+            ty: string(entry, "type"),
+            copy: boolean(entry, "copy"),
+        }
+    }).collect();
+
+    let imports = match root.get("imports").and_then(Json::as_array) {
+        Some(imports) => imports.iter().filter_map(Json::as_string).map(str::to_string).collect(),
+        None => Vec::new(),
+    };
+
+    let mut schema = EcsSchema {
+        components,
+        action_properties,
+        imports,
+        dynamic: boolean(root, "dynamic"),
+        word_size,
+        word_bits,
+        component_set_num_words: 0,
+    };
+    let num_types = cmp::max(schema.num_components(), schema.num_action_properties());
+    schema.component_set_num_words = (num_types - 1) / word_bits + 1;
+
+    schema
+}
+
+fn object(fields: Vec<(&str, Json)>) -> Json {
+    Json::Object(fields.into_iter().map(|(key, value)| (key.to_string(), value)).collect())
+}
+
+fn entry_fields(id: &str, index: usize, set_index: usize, set_bit: usize, ty: &Option<String>, copy: bool) -> Vec<(&'static str, Json)> {
+    let mut fields = vec![
+        ("id", Json::String(id.to_string())),
+        ("id_uppercase", Json::String(id.to_uppercase())),
+        ("index", Json::U64(index as u64)),
+        ("set_index", Json::U64(set_index as u64)),
+        ("set_bit", Json::U64(set_bit as u64)),
+        ("copy", Json::Boolean(copy)),
+    ];
+    if let Some(ref ty) = *ty {
+        fields.push(("type", Json::String(ty.clone())));
+    }
+
+    fields
+}
+
+// The data the templates are rendered with. Components and action properties
+// are arrays in the order of their indices. Flags are set for a component's
+// container and storage, so templates can check for them with #if.
+pub fn model(schema: &EcsSchema) -> json::Object {
+    let components = schema.components.iter().map(|component| {
+        let mut fields = entry_fields(&component.id, component.index, component.set_index, component.set_bit,
+                                      &component.ty, component.copy);
+        if let Some(container) = component.container {
+            fields.push(("container", Json::String(container.name().to_string())));
+            fields.push((container.name(), Json::Boolean(true)));
+        }
+        fields.push(("storage", Json::String(component.storage.name().to_string())));
+        fields.push((component.storage.name(), Json::Boolean(true)));
+        fields.push(("storage_type", Json::String(component.storage_type().to_string())));
+
+        object(fields)
+    }).collect();
+
+    let action_properties = schema.action_properties.iter().map(|action_property| {
+        let fields = entry_fields(&action_property.id, action_property.index, action_property.set_index,
+                                  action_property.set_bit, &action_property.ty, action_property.copy);
+
+        object(fields)
+    }).collect();
+
+    let mut model = json::Object::new();
+    model.insert("component".to_string(), Json::Array(components));
+    model.insert("action_property".to_string(), Json::Array(action_properties));
+    model.insert("imports".to_string(), Json::Array(schema.imports.iter().cloned().map(Json::String).collect()));
+    model.insert("dynamic".to_string(), Json::Boolean(schema.dynamic));
+    model.insert("num_components".to_string(), Json::U64(schema.num_components() as u64));
+    model.insert("num_action_properties".to_string(), Json::U64(schema.num_action_properties() as u64));
+    model.insert("word_size".to_string(), Json::U64(schema.word_size as u64));
+    model.insert("word_bits".to_string(), Json::U64(schema.word_bits as u64));
+    model.insert("component_set_num_words".to_string(), Json::U64(schema.component_set_num_words as u64));
+
+    model
+}
@@ -0,0 +1,664 @@
+use std::collections::BTreeMap;
+use std::convert::TryFrom;
+
+use rustc_serialize::json::{self, Json};
+
+use error::SchemaError;
+
+const CONTAINERS: &[&str] = &["RefCell", "UnsafeCell"];
+const STORAGES: &[&str] = &["btree", "hash", "dense_vec", "sparse_set"];
+
+const SECTIONS: &[&str] = &["component", "action_property", "imports", "dynamic", "include"];
+const COMPONENT_KEYS: &[&str] = &["type", "container", "copy", "storage", "index"];
+const ACTION_PROPERTY_KEYS: &[&str] = &["type", "copy", "index"];
+
+const KEYWORDS: &[&str] = &[
+    "abstract", "as", "async", "await", "become", "box", "break", "const", "continue", "crate",
+    "do", "dyn", "else", "enum", "extern", "false", "final", "fn", "for", "if", "impl", "in",
+    "let", "loop", "macro", "match", "mod", "move", "mut", "override", "priv", "pub", "ref",
+    "return", "self", "static", "struct", "super", "trait", "true", "try", "type", "typeof",
+    "unsafe", "unsized", "use", "virtual", "where", "while", "yield",
+];
+
+// Names that the template defines independently of the spec. Any name
+// generated from a component or action property id must avoid these. The
+// tests below check this against the rendered template.
+const BUILTIN_NAMES: &[&str] = &[
+    "ComponentTypeSet::new", "ComponentTypeSet::is_empty", "ComponentTypeSet::clear", "ComponentTypeSet::iter",
+    "EcsCtx::new", "EcsCtx::remove_component", "EcsCtx::remove_components", "EcsCtx::remove_entity",
+    "EcsCtx::entity", "EcsCtx::entity_mut", "EcsCtx::post_action_entity", "EcsCtx::commit",
+    "EcsCtx::commit_into", "EcsCtx::commit_with_inverse", "EcsCtx::entity_iter", "EcsCtx::clear",
+    "EcsCtx::spawn", "EcsCtx::despawn", "EcsCtx::is_alive", "EcsCtx::allocate_reserved", "EcsCtx::query",
+    "EcsCtx::replay", "EcsCtx::validate", "EcsCtx::try_commit", "EcsCtx::contains_component", "EcsCtx::component_ids",
+    "EcsCtx::change_tick", "EcsCtx::forget_changes",
+    "EcsCtx.tracker", "EcsCtx.allocator", "EcsCtx.tick", "EcsCtx.changes", "EcsCtx.observers",
+    "EntityRef::new", "EntityRef::id", "EntityRef::is_alive", "EntityRef::is_empty",
+    "EntityRefMut::new", "EntityRefMut::id", "EntityRefMut::is_alive", "EntityRefMut::is_empty",
+    "EntityRefMut::destroy",
+    "EcsAction::new", "EcsAction::clear", "EcsAction::remove_entity", "EcsAction::remove_entity_by_id",
+    "EcsAction::remove_component", "EcsAction::entity", "EcsAction::entity_mut",
+    "EcsAction::reserve_entity", "EcsAction::is_reserved", "EcsAction::merge", "EcsAction::merge_conflicts",
+    "EcsAction::ordered", "EcsAction::is_ordered", "EcsAction::set_ordered",
+    "EcsAction.changed_components", "EcsAction.properties", "EcsAction.num_reserved_entities",
+    "EcsAction.ordered",
+    "PostActionEntityRef::new", "PostActionEntityRef::id", "PostActionEntityRef::to_entity_ref",
+    "PostActionEntityRef::is_alive",
+    "ActionEntityRefMut::new", "ActionEntityRefMut::id",
+    "ActionEntityRef::new", "ActionEntityRef::id",
+    "ActionPropertyTypeSet::new", "ActionPropertyTypeSet::is_empty", "ActionPropertyTypeSet::clear",
+    "ActionPropertyTypeSet::iter",
+    "EcsActionProperties::new", "EcsActionProperties::clear",
+    "EcsActionProperties.property_types",
+    "Query::new",
+    "component_type::INVALID_COMPONENT", "component_type::name", "component_type::from_name",
+    "component_type::is_flag", "component_type::type_name",
+];
+
+// Names the template defines only when the corresponding option is set.
+const DYNAMIC_NAMES: &[&str] = &[
+    "EcsCtx::get_dynamic", "EcsCtx::insert_dynamic", "EcsCtx::remove_dynamic",
+    "EcsAction::insert_dynamic", "EcsAction::remove_dynamic",
+];
+
+// Local variables which shadow the destructured component maps in the
+// conversions between EcsCtx and SerializableEcsCtx, for components whose
+// values are moved between maps one at a time.
+const CONTAINER_LOCALS: &[&str] = &["map", "keys", "key"];
+
+struct Entry<'a> {
+    section: &'static str,
+    id: &'a str,
+    typed: bool,
+    copy: bool,
+    container: Option<&'a str>,
+    storage: &'a str,
+    index: Option<usize>,
+}
+
+impl<'a> Entry<'a> {
+    fn new(section: &'static str, id: &'a str, fields: &'a json::Object) -> Self {
+        Entry {
+            section,
+            id,
+            typed: fields.contains_key("type"),
+            copy: fields.get("copy").and_then(Json::as_boolean).unwrap_or(false),
+            container: fields.get("container").and_then(Json::as_string),
+            storage: fields.get("storage").and_then(Json::as_string).unwrap_or("btree"),
+            index: fields.get("index").and_then(index),
+        }
+    }
+
+    fn component_names(&self) -> Vec<String> {
+        let id = self.id;
+        let mut names = vec![
+            format!("ComponentTypeSet::contains_{}", id),
+            format!("ComponentTypeSet::insert_{}", id),
+            format!("ComponentTypeSet::remove_{}", id),
+            format!("component_type::{}", id.to_uppercase()),
+            format!("EcsCtx.{}", id),
+            format!("EcsCtx::insert_{}", id),
+            format!("EcsCtx::contains_{}", id),
+            format!("EcsCtx::remove_{}", id),
+            format!("EcsCtx::remove_{}_into", id),
+            format!("EcsCtx::move_{}", id),
+            format!("EcsCtx::swap_{}", id),
+            format!("EcsCtx::{}_id_iter", id),
+            format!("EcsCtx::commit_{}", id),
+            format!("EcsCtx::on_insert_{}", id),
+            format!("EcsCtx::on_remove_{}", id),
+            format!("EcsCtx::{}_changed_since", id),
+            format!("EcsCtx::{}_added_since", id),
+            format!("EcsCtx::{}_removed_since", id),
+            format!("EntityRef::contains_{}", id),
+            format!("EntityRefMut::contains_{}", id),
+            format!("EntityRefMut::remove_{}", id),
+            format!("EcsAction.{}", id),
+            format!("EcsAction::insert_{}", id),
+            format!("EcsAction::contains_{}", id),
+            format!("EcsAction::remove_{}", id),
+            format!("EcsAction::move_{}", id),
+            format!("EcsAction::swap_{}", id),
+            format!("EcsAction::{}_profile", id),
+            format!("EcsAction::{}_positive_iter", id),
+            format!("EcsAction::{}_negative_iter", id),
+            format!("PostActionEntityRef::change_{}", id),
+            format!("ActionEntityRefMut::remove_{}", id),
+            format!("ActionEntityRef::contains_{}", id),
+            format!("Query::with_{}", id),
+            format!("Query::without_{}", id),
+        ];
+
+        if self.typed {
+            for scope in &["EcsCtx", "EntityRef", "EntityRefMut", "EcsAction", "PostActionEntityRef", "ActionEntityRef"] {
+                names.push(format!("{}::{}", scope, id));
+            }
+            for scope in &["EcsCtx", "EntityRefMut", "EcsAction", "ActionEntityRefMut"] {
+                names.push(format!("{}::{}_mut", scope, id));
+            }
+            names.push(format!("EcsCtx::{}_iter", id));
+            names.push(format!("EcsCtx::on_replace_{}", id));
+            names.push(format!("EcsAction::{}_profile_mut", id));
+            names.push(format!("PostActionEntityRef::current_{}", id));
+        } else {
+            names.push(format!("PostActionEntityRef::contains_{}", id));
+            names.push(format!("PostActionEntityRef::current_contains_{}", id));
+        }
+
+        if self.copy {
+            for scope in &["EcsCtx", "EntityRef", "EntityRefMut", "EcsAction", "ActionEntityRef"] {
+                names.push(format!("{}::{}_ref", scope, id));
+            }
+            names.push(format!("PostActionEntityRef::{}_ref", id));
+            names.push(format!("PostActionEntityRef::current_{}_ref", id));
+            names.push(format!("PostActionEntityRef::change_{}_ref", id));
+        }
+
+        if let Some(container) = self.container {
+            names.push(format!("EcsCtx::bare_insert_{}", id));
+            names.push(format!("EcsCtx::bare_remove_{}", id));
+            names.push(format!("EntityRefMut::bare_remove_{}", id));
+            let suffixes: &[&str] = if container == "RefCell" {
+                &["borrow", "borrow_mut"]
+            } else {
+                &["unsafe_get_mut", "unsafe_get"]
+            };
+            for scope in &["EcsCtx", "EntityRef", "EntityRefMut"] {
+                for suffix in suffixes {
+                    names.push(format!("{}::{}_{}", scope, id, suffix));
+                }
+            }
+        }
+
+        names
+    }
+
+    fn action_property_names(&self) -> Vec<String> {
+        let id = self.id;
+        let mut names = vec![
+            format!("ActionPropertyTypeSet::contains_{}", id),
+            format!("ActionPropertyTypeSet::insert_{}", id),
+            format!("ActionPropertyTypeSet::remove_{}", id),
+            format!("action_property_type::{}", id.to_uppercase()),
+            format!("EcsActionProperties.{}", id),
+            format!("EcsActionProperties::insert_{}", id),
+            format!("EcsActionProperties::contains_{}", id),
+            format!("EcsActionProperties::remove_{}", id),
+            format!("EcsAction::set_{}", id),
+            format!("EcsAction::clear_{}", id),
+        ];
+
+        if self.typed {
+            names.push(format!("EcsActionProperties::{}", id));
+            names.push(format!("EcsAction::{}", id));
+        } else {
+            names.push(format!("EcsAction::contains_{}", id));
+        }
+
+        if self.copy {
+            names.push(format!("EcsActionProperties::{}_ref", id));
+            names.push(format!("EcsAction::{}_ref", id));
+        }
+
+        names
+    }
+
+    fn names(&self) -> Vec<String> {
+        if self.section == "component" {
+            self.component_names()
+        } else {
+            self.action_property_names()
+        }
+    }
+}
+
+struct Validator {
+    errors: Vec<SchemaError>,
+}
+
+impl Validator {
+    fn error(&mut self, section: &str, id: Option<&str>, field: Option<&str>, description: &str) {
+        self.errors.push(SchemaError::new(section, id, field, description));
+    }
+
+    fn section<'a>(&mut self, root: &'a json::Object, section: &str) -> Option<&'a json::Object> {
+        match root.get(section) {
+            Some(Json::Object(entries)) => Some(entries),
+            Some(_) => {
+                self.error(section, None, None, "expected a table");
+                None
+            }
+            None => None,
+        }
+    }
+
+    fn id(&mut self, section: &str, id: &str) {
+        if !is_snake_case(id) {
+            self.error(section, Some(id), None,
+                       "ids must be snake_case identifiers starting with a lowercase letter");
+        } else if KEYWORDS.contains(&id) {
+            self.error(section, Some(id), None, "ids must not be rust keywords");
+        }
+    }
+
+    fn fields(&mut self, section: &str, id: &str, fields: &json::Object, allowed: &[&str]) {
+        for (key, value) in fields {
+            if !allowed.contains(&key.as_str()) {
+                self.error(section, Some(id), Some(key),
+                           &format!("unknown key (expected one of: {})", allowed.join(", ")));
+                continue;
+            }
+
+            match key.as_str() {
+                "type" => match value.as_string() {
+                    Some(ty) if !ty.trim().is_empty() => (),
+                    _ => self.error(section, Some(id), Some(key), "expected a non-empty string"),
+                },
+                "copy" if !value.is_boolean() => {
+                    self.error(section, Some(id), Some(key), "expected a boolean");
+                }
+                "container" => match value.as_string() {
+                    Some(container) if CONTAINERS.contains(&container) => (),
+                    _ => self.error(section, Some(id), Some(key),
+                                    &format!("expected one of: {}", CONTAINERS.join(", "))),
+                },
+                "storage" => match value.as_string() {
+                    Some(storage) if STORAGES.contains(&storage) => (),
+                    _ => self.error(section, Some(id), Some(key),
+                                    &format!("expected one of: {}", STORAGES.join(", "))),
+                },
+                "index" if index(value).is_none() => {
+                    self.error(section, Some(id), Some(key), "expected a non-negative integer");
+                }
+                _ => (),
+            }
+        }
+
+        let typed = fields.contains_key("type");
+        if !typed && fields.contains_key("copy") {
+            self.error(section, Some(id), Some("copy"), "copy requires a type");
+        }
+        if !typed && fields.contains_key("container") {
+            self.error(section, Some(id), Some("container"), "container requires a type");
+        }
+        if fields.get("copy").and_then(Json::as_boolean) == Some(true) && fields.contains_key("container") {
+            self.error(section, Some(id), Some("copy"), "copy cannot be combined with a container");
+        }
+    }
+
+    fn entries<'a>(&mut self, root: &'a json::Object, section: &'static str, allowed: &[&str]) -> Vec<Entry<'a>> {
+        let mut entries = Vec::new();
+        if let Some(table) = self.section(root, section) {
+            for (id, value) in table {
+                self.id(section, id);
+                match *value {
+                    Json::Object(ref fields) => {
+                        self.fields(section, id, fields, allowed);
+                        entries.push(Entry::new(section, id, fields));
+                    }
+                    _ => self.error(section, Some(id), None, "expected a table"),
+                }
+            }
+        }
+
+        entries
+    }
+
+    // Pinned indices must be distinct. They may be beyond the number of
+    // entries, leaving gaps such as the indices of removed entries.
+    fn indices(&mut self, entries: &[Entry], section: &str) {
+        let mut pinned: BTreeMap<usize, &str> = BTreeMap::new();
+        for entry in entries.iter().filter(|entry| entry.section == section) {
+            let index = match entry.index {
+                Some(index) => index,
+                None => continue,
+            };
+            if let Some(other) = pinned.get(&index) {
+                self.error(section, Some(entry.id), Some("index"), &format!("index {} is also used by {}", index, other));
+            } else {
+                pinned.insert(index, entry.id);
+            }
+        }
+    }
+
+    fn imports(&mut self, root: &json::Object) {
+        match root.get("imports") {
+            Some(Json::Array(imports)) if imports.iter().all(Json::is_string) => (),
+            Some(_) => self.error("imports", None, None, "expected an array of strings"),
+            None => (),
+        }
+    }
+
+    fn options(&mut self, root: &json::Object) {
+        if let Some(value) = root.get("dynamic") {
+            if !value.is_boolean() {
+                self.error("dynamic", None, None, "expected a boolean");
+            }
+        }
+    }
+
+    fn clashes(&mut self, entries: &[Entry], builtins: &[&str]) {
+        let mut owners: BTreeMap<String, &Entry> = BTreeMap::new();
+        for entry in entries {
+            let mut builtin = Vec::new();
+            let mut generated: BTreeMap<(&str, &str), Vec<String>> = BTreeMap::new();
+
+            for name in entry.names() {
+                if builtins.contains(&name.as_str()) {
+                    builtin.push(name);
+                } else if let Some(owner) = owners.get(&name) {
+                    generated.entry((owner.section, owner.id)).or_default().push(name);
+                } else {
+                    owners.insert(name, entry);
+                }
+            }
+
+            if !builtin.is_empty() {
+                self.error(entry.section, Some(entry.id), None,
+                           &format!("generated names clash with built-in names: {}", builtin.join(", ")));
+            }
+            for ((section, id), names) in generated {
+                self.error(entry.section, Some(entry.id), None,
+                           &format!("generated names clash with those of {}.{}: {}", section, id, names.join(", ")));
+            }
+
+            let converted = entry.container.is_some() || (entry.typed && entry.storage != "btree");
+            if converted && CONTAINER_LOCALS.contains(&entry.id) {
+                self.error(entry.section, Some(entry.id), None,
+                           "id shadows a local variable used for components with a container or storage");
+            }
+        }
+    }
+}
+
+fn is_snake_case(name: &str) -> bool {
+    let mut chars = name.chars();
+    chars.next().is_some_and(|c| c.is_ascii_lowercase()) &&
+        chars.all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '_')
+}
+
+// names of generated modules follow the same rules as ids
+pub fn is_module_name(name: &str) -> bool {
+    is_snake_case(name) && !KEYWORDS.contains(&name)
+}
+
+// The value of an `index` key, if it's a valid index
+pub fn index(value: &Json) -> Option<usize> {
+    match *value {
+        Json::U64(index) => usize::try_from(index).ok(),
+        Json::I64(index) => usize::try_from(index).ok(),
+        _ => None,
+    }
+}
+
+pub fn validate(root: &json::Object) -> Result<(), Vec<SchemaError>> {
+    let mut validator = Validator {
+        errors: Vec::new(),
+    };
+
+    for key in root.keys() {
+        if !SECTIONS.contains(&key.as_str()) {
+            validator.error(key, None, None,
+                            &format!("unknown section (expected one of: {})", SECTIONS.join(", ")));
+        }
+    }
+
+    validator.imports(root);
+    validator.options(root);
+
+    let num_components = root.get("component").and_then(Json::as_object).map_or(0, |components| components.len());
+    if num_components == 0 && root.get("component").is_none_or(Json::is_object) {
+        validator.error("component", None, None, "at least one component must be defined");
+    }
+
+    let mut entries = validator.entries(root, "component", COMPONENT_KEYS);
+
+    entries.extend(validator.entries(root, "action_property", ACTION_PROPERTY_KEYS));
+    validator.indices(&entries, "component");
+    validator.indices(&entries, "action_property");
+    let mut builtins = BUILTIN_NAMES.to_vec();
+    if root.get("dynamic").and_then(Json::as_boolean) == Some(true) {
+        builtins.extend_from_slice(DYNAMIC_NAMES);
+    }
+    validator.clashes(&entries, &builtins);
+
+    if validator.errors.is_empty() {
+        Ok(())
+    } else {
+        Err(validator.errors)
+    }
+}
+
+fn object(fields: Vec<(&str, Json)>) -> Json {
+    Json::Object(fields.into_iter().map(|(key, value)| (key.to_string(), value)).collect())
+}
+
+fn strings(values: &[&str]) -> Json {
+    Json::Array(values.iter().map(|value| Json::String(value.to_string())).collect())
+}
+
+fn entry_schema(keys: &[&str], description: &str) -> Json {
+    let mut properties = Vec::new();
+    for &key in keys {
+        let property = match key {
+            "type" => object(vec![
+                ("type", Json::String("string".to_string())),
+                ("minLength", Json::U64(1)),
+                ("description", Json::String("the rust type of the value".to_string())),
+            ]),
+            "copy" => object(vec![
+                ("type", Json::String("boolean".to_string())),
+                ("description", Json::String("whether the type implements Copy".to_string())),
+            ]),
+            "container" => object(vec![("enum", strings(CONTAINERS))]),
+            "storage" => object(vec![("enum", strings(STORAGES))]),
+            "index" => object(vec![
+                ("type", Json::String("integer".to_string())),
+                ("minimum", Json::U64(0)),
+                ("description", Json::String("pins the index, which otherwise follows the alphabetical order of ids".to_string())),
+            ]),
+            _ => unreachable!(),
+        };
+        properties.push((key, property));
+    }
+
+    let mut dependencies = Vec::new();
+    for &key in keys {
+        if key == "copy" || key == "container" {
+            dependencies.push((key, strings(&["type"])));
+        }
+    }
+
+    let mut fields = vec![
+        ("type", Json::String("object".to_string())),
+        ("description", Json::String(description.to_string())),
+        ("properties", object(properties)),
+        ("dependencies", object(dependencies)),
+        ("additionalProperties", Json::Boolean(false)),
+    ];
+    if keys.contains(&"container") {
+        // copy cannot be combined with a container
+        fields.push(("not", object(vec![
+            ("required", strings(&["copy", "container"])),
+            ("properties", object(vec![("copy", object(vec![("const", Json::Boolean(true))]))])),
+        ])));
+    }
+
+    object(fields)
+}
+
+fn section_schema(keys: &[&str], description: &str, min_entries: u64) -> Json {
+    object(vec![
+        ("type", Json::String("object".to_string())),
+        ("minProperties", Json::U64(min_entries)),
+        ("propertyNames", object(vec![("pattern", Json::String("^[a-z][a-z0-9_]*$".to_string()))])),
+        ("additionalProperties", entry_schema(keys, description)),
+    ])
+}
+
+pub fn schema() -> Json {
+    object(vec![
+        ("$schema", Json::String("http://json-schema.org/draft-07/schema#".to_string())),
+        ("title", Json::String("genecs spec".to_string())),
+        ("type", Json::String("object".to_string())),
+        ("properties", object(vec![
+            ("component", section_schema(COMPONENT_KEYS, "a component, which is a flag if it has no type", 1)),
+            ("action_property", section_schema(ACTION_PROPERTY_KEYS, "a property of actions, which is a flag if it has no type", 0)),
+            ("imports", object(vec![
+                ("type", Json::String("array".to_string())),
+                ("items", object(vec![("type", Json::String("string".to_string()))])),
+                ("description", Json::String("paths the generated code imports with use declarations".to_string())),
+            ])),
+            ("dynamic", object(vec![
+                ("type", Json::String("boolean".to_string())),
+                ("description", Json::String("whether to generate access to components by name".to_string())),
+            ])),
+            ("include", object(vec![
+                ("type", Json::String("array".to_string())),
+                ("items", object(vec![("type", Json::String("string".to_string()))])),
+                ("description", Json::String("spec files to merge into this one, relative to its directory".to_string())),
+            ])),
+        ])),
+        ("additionalProperties", Json::Boolean(false)),
+    ])
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeSet;
+
+    use format::{self, SpecFormat};
+    use rustc_serialize::json::Json;
+
+    use super::{index, Entry, BUILTIN_NAMES, DYNAMIC_NAMES};
+
+    // Covers every kind of component and action property, so every name the
+    // template can generate from an id appears in the rendered code
+    const SPEC: &str = r#"
+dynamic = true
+
+[component.typed]
+type = "u32"
+
+[component.copied]
+type = "u32"
+copy = true
+
+[component.ref_cell]
+type = "Vec<u32>"
+container = "RefCell"
+storage = "hash"
+
+[component.unsafe_cell]
+type = "Vec<u32>"
+container = "UnsafeCell"
+storage = "dense_vec"
+
+[component.flag]
+storage = "sparse_set"
+
+[action_property.typed_property]
+type = "u32"
+
+[action_property.copied_property]
+type = "u32"
+copy = true
+
+[action_property.flag_property]
+"#;
+
+    // The names defined directly in `impl` blocks, struct definitions and
+    // modules of the generated code, written as they are in BUILTIN_NAMES
+    fn generated_names(code: &str) -> BTreeSet<String> {
+        let mut names = BTreeSet::new();
+        let mut scope: Option<(String, &str)> = None;
+        for line in code.lines() {
+            if line == "}" {
+                scope = None;
+                continue;
+            }
+
+            if let Some((ref name, separator)) = scope {
+                let item = match line.strip_prefix("    ") {
+                    Some(item) if !item.starts_with(' ') => item,
+                    _ => continue,
+                };
+                let item = item.trim_start_matches("pub ").trim_start_matches("unsafe ");
+                let ident = if separator == "." {
+                    item.split(':').next().filter(|field| !field.contains(' '))
+                } else if let Some(item) = item.strip_prefix("fn ").or_else(|| item.strip_prefix("const ")) {
+                    item.split(|c: char| !c.is_alphanumeric() && c != '_').next()
+                } else {
+                    None
+                };
+                if let Some(ident) = ident.filter(|ident| !ident.is_empty()) {
+                    names.insert(format!("{}{}{}", name, separator, ident));
+                }
+                continue;
+            }
+
+            let header = line.trim_end_matches(" {");
+            let type_name = |declaration: &str| {
+                declaration.split(|c: char| !c.is_alphanumeric() && c != '_').next().unwrap_or("").to_string()
+            };
+            if let Some(declaration) = header.strip_prefix("pub struct ") {
+                scope = Some((type_name(declaration), "."));
+            } else if let Some(declaration) = header.strip_prefix("pub mod ") {
+                scope = Some((type_name(declaration), "::"));
+            } else if header.starts_with("impl") && !header.contains(" for ") && line.ends_with(" {") {
+                // skip the generic parameters of the impl itself
+                let declaration = match header.find("> ") {
+                    Some(end) if header.starts_with("impl<") => &header[end + 2..],
+                    _ => header.trim_start_matches("impl "),
+                };
+                scope = Some((type_name(declaration), "::"));
+            }
+        }
+
+        names
+    }
+
+    #[test]
+    fn builtin_names_match_template() {
+        let code = ::generate_ecs_code_from_spec(SPEC).unwrap();
+        let spec = format::parse(SpecFormat::Toml, SPEC.to_string()).unwrap();
+
+        let mut entry_names = BTreeSet::new();
+        for &section in &["component", "action_property"] {
+            for (id, fields) in spec[section].as_object().unwrap() {
+                entry_names.extend(Entry::new(section, id, fields.as_object().unwrap()).names());
+            }
+        }
+
+        // names are only checked in the scopes ids generate names in, and
+        // fields can't clash with methods
+        let scope = |name: &str| name[..name.find([':', '.']).map_or(0, |end| end + 1)].to_string();
+        let scopes: BTreeSet<String> = entry_names.iter().map(|name| scope(name)).collect();
+
+        let missing: Vec<String> = generated_names(&code).into_iter()
+            .filter(|name| scopes.contains(&scope(name)))
+            .filter(|name| !entry_names.contains(name))
+            .filter(|name| !BUILTIN_NAMES.contains(&name.as_str()) && !DYNAMIC_NAMES.contains(&name.as_str()))
+            .collect();
+        assert!(missing.is_empty(), "names missing from BUILTIN_NAMES: {:?}", missing);
+    }
+
+    #[test]
+    fn builtin_names_are_generated() {
+        let code = ::generate_ecs_code_from_spec(SPEC).unwrap();
+        let names = generated_names(&code);
+
+        let stale: Vec<&str> = BUILTIN_NAMES.iter().chain(DYNAMIC_NAMES).cloned()
+            .filter(|name| !names.contains(*name))
+            .collect();
+        assert!(stale.is_empty(), "names in BUILTIN_NAMES which aren't generated: {:?}", stale);
+    }
+
+    #[test]
+    fn indices_are_non_negative_integers() {
+        assert_eq!(index(&Json::U64(3)), Some(3));
+        assert_eq!(index(&Json::I64(3)), Some(3));
+        assert_eq!(index(&Json::I64(-1)), None);
+        assert_eq!(index(&Json::F64(1.0)), None);
+        assert_eq!(index(&Json::String("1".to_string())), None);
+    }
+}
@@ -0,0 +1,30 @@
+extern crate genecs;
+
+use genecs::{GenerateError, Generator};
+
+#[test]
+fn checks_valid_specs() {
+    assert!(genecs::check_spec("tests/specs/simple.toml").is_ok());
+}
+
+#[test]
+fn reports_invalid_specs() {
+    match genecs::check_spec("tests/specs/invalid.toml") {
+        Err(GenerateError::Schema(ref errors)) if !errors.is_empty() => (),
+        other => panic!("expected schema errors, found {:?}", other),
+    }
+}
+
+#[test]
+fn checks_without_rendering() {
+    // templates are only looked at when rendering, so an extension at an
+    // unknown point is only an error when generating code
+    let mut generator = Generator::new();
+    generator.extend("nowhere", "");
+
+    assert!(generator.check("tests/specs/simple.toml").is_ok());
+    match generator.code("tests/specs/simple.toml") {
+        Err(GenerateError::ExtensionPoint(ref point)) if point == "nowhere" => (),
+        other => panic!("expected an extension point error, found {:?}", other),
+    }
+}
@@ -0,0 +1,54 @@
+extern crate genecs;
+
+use genecs::SpecChange;
+
+fn renumbered(section: &str, id: &str, old: usize, new: usize) -> SpecChange {
+    SpecChange::Renumbered { section: section.to_string(), id: id.to_string(), old, new }
+}
+
+#[test]
+fn reports_renumbered_entries() {
+    let changes = genecs::diff_specs("tests/specs/diff/old.toml", "tests/specs/diff/new.toml").unwrap();
+    assert_eq!(changes, vec![
+        SpecChange::Changed {
+            section: "component".to_string(),
+            id: Some("bravo".to_string()),
+            field: Some("type".to_string()),
+            old: Some("u32".to_string()),
+            new: Some("u64".to_string()),
+        },
+        SpecChange::Added { section: "component".to_string(), id: "alpha".to_string() },
+        SpecChange::Added { section: "action_property".to_string(), id: "undoable".to_string() },
+        SpecChange::Changed {
+            section: "dynamic".to_string(),
+            id: None,
+            field: None,
+            old: None,
+            new: Some("true".to_string()),
+        },
+        // delta keeps its pinned index
+        renumbered("component", "bravo", 1, 2),
+        renumbered("component", "charlie", 2, 3),
+        renumbered("action_property", "turn", 0, 1),
+    ]);
+
+    assert_eq!(changes[4].to_string(), "renumbered component.bravo: 1 -> 2");
+}
+
+#[test]
+fn removals_renumber_the_entries_after_them() {
+    let changes = genecs::diff_specs("tests/specs/diff/new.toml", "tests/specs/diff/old.toml").unwrap();
+    assert!(changes.contains(&SpecChange::Removed { section: "component".to_string(), id: "alpha".to_string() }));
+    assert!(changes.contains(&renumbered("component", "bravo", 2, 1)));
+    assert!(changes.contains(&renumbered("component", "charlie", 3, 2)));
+    assert!(changes.contains(&renumbered("action_property", "turn", 1, 0)));
+    assert!(!changes.iter().any(|change| match *change {
+        SpecChange::Renumbered { ref id, .. } => id == "delta",
+        _ => false,
+    }));
+}
+
+#[test]
+fn identical_specs_have_no_changes() {
+    assert_eq!(genecs::diff_specs("tests/specs/diff/old.toml", "tests/specs/diff/old.toml").unwrap(), vec![]);
+}
@@ -0,0 +1,115 @@
+extern crate genecs;
+extern crate tempfile;
+
+use std::fs;
+
+use genecs::Generator;
+
+const SPEC: &str = r#"
+[component.position]
+type = "(i32, i32)"
+copy = true
+
+[component.name]
+type = "String"
+storage = "hash"
+
+[component.solid]
+"#;
+
+const COMPONENTS: &[(&str, usize, &str)] = &[
+    ("name", 0, "entity: EntityId, value: String"),
+    ("position", 1, "entity: EntityId, value: (i32, i32)"),
+    ("solid", 2, "entity: EntityId"),
+];
+
+fn generator() -> Generator {
+    let mut generator = Generator::new();
+    generator
+        .extend("ctx", "    // ext ctx{{#each component}} {{id}}={{index}}{{/each}}")
+        .extend("ctx_insert", "        // ext ctx_insert {{id}} {{index}}")
+        .extend("action", "    // ext action{{#each component}} {{id}}={{index}}{{/each}}")
+        .extend("action_insert", "        // ext action_insert {{id}} {{index}}")
+        .extend("entity_ref", "    // ext entity_ref{{#each component}} {{id}}={{index}}{{/each}}")
+        // later templates at the same point follow earlier ones
+        .extend("ctx", "    // ext ctx again");
+    generator
+}
+
+// The line after the first containing `anchor`
+fn line_after<'a>(code: &'a str, anchor: &str) -> &'a str {
+    let mut lines = code.lines().skip_while(|line| !line.contains(anchor));
+    assert!(lines.next().is_some(), "{} isn't generated", anchor);
+    lines.next().unwrap_or("")
+}
+
+// The impl block containing the first line containing `needle`
+fn enclosing_impl<'a>(code: &'a str, needle: &str) -> &'a str {
+    let end = code.find(needle).unwrap_or_else(|| panic!("{} isn't generated", needle));
+    code[..end].lines().rev().find(|line| line.starts_with("impl")).unwrap()
+}
+
+fn check_ctx(code: &str) {
+    assert_eq!(enclosing_impl(code, "// ext ctx name"), "impl EcsCtx {");
+    assert_eq!(line_after(code, "// ext ctx name"), "    // ext ctx again");
+    assert_eq!(line_after(code, "// ext ctx again"), "}");
+    assert!(code.contains("    // ext ctx name=0 position=1 solid=2\n"));
+
+    for &(id, index, args) in COMPONENTS {
+        let insert = format!("pub fn insert_{}(&mut self, {}) {{", id, args);
+        assert_eq!(enclosing_impl(code, &insert), "impl EcsCtx {");
+        assert_eq!(line_after(code, &insert), format!("        // ext ctx_insert {} {}", id, index));
+    }
+}
+
+fn check_action(code: &str) {
+    // the single file output has the ctx's insert methods first
+    let code = &code[code.find("impl EcsAction {").unwrap()..];
+    assert_eq!(enclosing_impl(code, "// ext action name"), "impl EcsAction {");
+    assert_eq!(line_after(code, "// ext action name"), "}");
+    assert!(code.contains("    // ext action name=0 position=1 solid=2\n"));
+
+    for &(id, index, args) in COMPONENTS {
+        let insert = format!("pub fn insert_{}(&mut self, {}) {{", id, args);
+        assert_eq!(enclosing_impl(code, &insert), "impl EcsAction {");
+        assert_eq!(line_after(code, &insert), format!("        // ext action_insert {} {}", id, index));
+    }
+}
+
+fn check_entity_ref(code: &str) {
+    assert_eq!(enclosing_impl(code, "// ext entity_ref"), "impl<'a> EntityRef<'a> {");
+    assert_eq!(line_after(code, "// ext entity_ref name"), "}");
+    assert!(code.contains("    // ext entity_ref name=0 position=1 solid=2\n"));
+}
+
+#[test]
+fn extensions_render_at_their_points() {
+    let code = generator().code_from_spec(SPEC).unwrap();
+    check_ctx(&code);
+    check_action(&code);
+    check_entity_ref(&code);
+}
+
+#[test]
+fn extensions_render_in_their_modules() {
+    let dir = tempfile::tempdir().unwrap();
+    let spec = dir.path().join("ecs.toml");
+    fs::write(&spec, SPEC).unwrap();
+    generator().try_generate_modules(&spec, dir.path(), "ecs").unwrap();
+
+    let module = |name: &str| fs::read_to_string(dir.path().join("ecs").join(format!("{}.rs", name))).unwrap();
+    let (ctx, action, entity_ref) = (module("ctx"), module("action"), module("entity_ref"));
+    check_ctx(&ctx);
+    check_action(&action);
+    check_entity_ref(&entity_ref);
+
+    // each extension is only in the module of its point
+    for &(name, ref code) in &[("types", module("types")), ("storage", module("storage")),
+                               ("ctx", ctx.clone()), ("action", action.clone()), ("entity_ref", entity_ref.clone())] {
+        let points: Vec<&str> = ["ctx", "action", "entity_ref"].iter().cloned()
+            .filter(|point| code.contains(&format!("// ext {} ", point)))
+            .collect();
+        let expected: Vec<&str> = ["ctx", "action", "entity_ref"].iter().cloned().filter(|&point| point == name).collect();
+        assert_eq!(points, expected, "{}", name);
+    }
+}
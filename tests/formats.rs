@@ -0,0 +1,63 @@
+extern crate genecs;
+
+use genecs::{GenerateError, Generator, SpecFormat};
+
+const FORMATS: &[(SpecFormat, &str)] = &[
+    (SpecFormat::Toml, "tests/specs/formats/ecs.toml"),
+    (SpecFormat::Json, "tests/specs/formats/ecs.json"),
+    (SpecFormat::Yaml, "tests/specs/formats/ecs.yaml"),
+    (SpecFormat::Ron, "tests/specs/formats/ecs.ron"),
+];
+
+#[test]
+fn formats_generate_the_same_code() {
+    let toml = genecs::generate_ecs_code(FORMATS[0].1).unwrap();
+    for &(format, path) in &FORMATS[1..] {
+        let code = genecs::generate_ecs_code(path).unwrap_or_else(|error| panic!("{}: {}", path, error));
+        assert!(code == toml, "the code generated from {} differs from that of toml", format);
+    }
+}
+
+#[test]
+fn formats_have_the_same_schema() {
+    let toml = genecs::parse_ecs_schema(FORMATS[0].1).unwrap();
+    assert_eq!(toml.components.iter().map(|component| component.id.as_str()).collect::<Vec<_>>(),
+               vec!["inventory", "position", "solid"]);
+    for &(_, path) in &FORMATS[1..] {
+        assert_eq!(genecs::parse_ecs_schema(path).unwrap(), toml);
+    }
+}
+
+// The line and column of the only parse error in `spec`
+fn parse_error(format: SpecFormat, spec: &str) -> (usize, usize) {
+    match Generator::new().format(format).code_from_spec(spec) {
+        Err(GenerateError::Parse(error_format, ref errors)) if error_format == format && errors.len() == 1 => {
+            (errors[0].line, errors[0].column)
+        }
+        other => panic!("expected a {} parse error, found {:?}", format, other.map(|_| ())),
+    }
+}
+
+#[test]
+fn toml_parse_error_position() {
+    let spec = "[component.position]\ntype = \"u32\"\ncopy = tru\n";
+    assert_eq!(parse_error(SpecFormat::Toml, spec), (3, 8));
+}
+
+#[test]
+fn json_parse_error_position() {
+    let spec = "{\n    \"component\": {\n        \"position\": { \"type\" \"u32\" }\n    }\n}\n";
+    assert_eq!(parse_error(SpecFormat::Json, spec), (3, 30));
+}
+
+#[test]
+fn yaml_parse_error_position() {
+    let spec = "component:\n  position:\n    type: u32: u64\n";
+    assert_eq!(parse_error(SpecFormat::Yaml, spec), (3, 14));
+}
+
+#[test]
+fn ron_parse_error_position() {
+    let spec = "{\n    \"component\": {\n        \"position\": { \"type\": \"u32\" \"copy\": true },\n    },\n}\n";
+    assert_eq!(parse_error(SpecFormat::Ron, spec), (3, 37));
+}
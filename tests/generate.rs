@@ -0,0 +1,102 @@
+extern crate genecs;
+extern crate tempfile;
+
+use std::fs::{self, File};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use genecs::Generator;
+
+const SPEC: &str = "[component.position]\ntype = \"(i32, i32)\"\n";
+
+// A time long before the test runs, so a rewritten file is easy to tell apart
+fn long_ago() -> SystemTime {
+    UNIX_EPOCH + Duration::from_secs(1_000_000)
+}
+
+fn age(path: &Path) {
+    File::options().write(true).open(path).unwrap().set_modified(long_ago()).unwrap();
+}
+
+fn modified(path: &Path) -> SystemTime {
+    fs::metadata(path).unwrap().modified().unwrap()
+}
+
+// Every file under `dir`, relative to it
+fn files(dir: &Path) -> Vec<PathBuf> {
+    let mut files = Vec::new();
+    for entry in fs::read_dir(dir).unwrap() {
+        let path = entry.unwrap().path();
+        if path.is_dir() {
+            files.extend(self::files(&path).into_iter().map(|file| Path::new(path.file_name().unwrap()).join(file)));
+        } else {
+            files.push(PathBuf::from(path.file_name().unwrap()));
+        }
+    }
+    files.sort();
+
+    files
+}
+
+#[test]
+fn unchanged_code_isnt_rewritten() {
+    let dir = tempfile::tempdir().unwrap();
+    let (spec, out, lock) = (dir.path().join("ecs.toml"), dir.path().join("ecs.rs"), dir.path().join("ecs.lock"));
+    fs::write(&spec, SPEC).unwrap();
+
+    let mut generator = Generator::new();
+    generator.lock(&lock);
+    generator.try_generate(&spec, &out).unwrap();
+    age(&out);
+    age(&lock);
+
+    generator.try_generate(&spec, &out).unwrap();
+    assert_eq!(modified(&out), long_ago());
+    assert_eq!(modified(&lock), long_ago());
+
+    // changing the spec rewrites both
+    fs::write(&spec, format!("{}[component.solid]\n", SPEC)).unwrap();
+    generator.try_generate(&spec, &out).unwrap();
+    assert!(modified(&out) > long_ago());
+    assert!(modified(&lock) > long_ago());
+}
+
+#[test]
+fn unchanged_modules_arent_rewritten() {
+    let dir = tempfile::tempdir().unwrap();
+    let spec = dir.path().join("ecs.toml");
+    fs::write(&spec, SPEC).unwrap();
+    let out = dir.path().join("out");
+
+    genecs::try_generate_ecs_modules(&spec, &out, "ecs").unwrap();
+    for file in files(&out) {
+        age(&out.join(file));
+    }
+
+    genecs::try_generate_ecs_modules(&spec, &out, "ecs").unwrap();
+    for file in files(&out) {
+        assert_eq!(modified(&out.join(&file)), long_ago(), "{}", file.display());
+    }
+}
+
+#[test]
+fn no_temporary_files_are_left() {
+    let dir = tempfile::tempdir().unwrap();
+    let spec = dir.path().join("ecs.toml");
+    fs::write(&spec, SPEC).unwrap();
+
+    let mut generator = Generator::new();
+    generator.lock(dir.path().join("ecs.lock"));
+    for spec_contents in &[SPEC.to_string(), SPEC.to_string(), format!("{}[component.solid]\n", SPEC)] {
+        fs::write(&spec, spec_contents).unwrap();
+        generator.try_generate(&spec, dir.path().join("ecs.rs")).unwrap();
+        generator.try_generate_modules(&spec, dir.path().join("out"), "ecs").unwrap();
+    }
+
+    let mut expected: Vec<PathBuf> = vec![
+        "ecs.lock", "ecs.rs", "ecs.toml",
+        "out/ecs.rs", "out/ecs/action.rs", "out/ecs/ctx.rs", "out/ecs/entity_ref.rs", "out/ecs/storage.rs", "out/ecs/types.rs",
+    ].into_iter().map(PathBuf::from).collect();
+    expected.sort();
+    assert_eq!(files(dir.path()), expected);
+}
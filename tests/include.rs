@@ -0,0 +1,98 @@
+extern crate genecs;
+
+use std::path::{Path, PathBuf};
+
+use genecs::{GenerateError, SchemaError, SpecFormat};
+
+const DIR: &str = "tests/specs/include";
+
+fn path(file: &str) -> PathBuf {
+    Path::new(DIR).join(file)
+}
+
+#[test]
+fn includes_relative_to_the_including_file() {
+    let schema = genecs::parse_ecs_schema(path("relative/ecs.toml")).unwrap();
+
+    let components: Vec<&str> = schema.components.iter().map(|component| component.id.as_str()).collect();
+    assert_eq!(components, vec!["name", "position", "solid"]);
+    let action_properties: Vec<&str> = schema.action_properties.iter().map(|property| property.id.as_str()).collect();
+    assert_eq!(action_properties, vec!["turn"]);
+
+    // imports from both files are merged without repeats, and the shared file
+    // is only merged once although two files include it
+    assert_eq!(schema.imports, vec!["std::collections::BTreeMap".to_string(), "std::collections::BTreeSet".to_string()]);
+    assert!(schema.dynamic);
+
+    assert!(genecs::generate_ecs_code(path("relative/ecs.toml")).is_ok());
+}
+
+#[test]
+fn reports_include_cycles() {
+    match genecs::check_spec(path("cycle/a.toml")) {
+        Err(GenerateError::IncludeCycle(ref paths)) => {
+            assert_eq!(*paths, vec![path("cycle/a.toml"), path("cycle/b.toml"), path("cycle/a.toml")]);
+        }
+        other => panic!("expected an include cycle, found {:?}", other),
+    }
+
+    // starting from the other file names the cycle from there
+    match genecs::check_spec(path("cycle/b.toml")) {
+        Err(GenerateError::IncludeCycle(ref paths)) => {
+            assert_eq!(*paths, vec![path("cycle/b.toml"), path("cycle/a.toml"), path("cycle/b.toml")]);
+        }
+        other => panic!("expected an include cycle, found {:?}", other),
+    }
+}
+
+#[test]
+fn reports_entries_defined_in_several_files() {
+    let included = path("duplicate/other.json").display().to_string();
+    let including = path("duplicate/ecs.toml").display().to_string();
+    let description = format!("defined in both {} and {}", included, including);
+
+    // every duplicate is reported, naming the file it was first defined in,
+    // which is the included file as includes are merged first
+    match genecs::check_spec(path("duplicate/ecs.toml")) {
+        Err(GenerateError::Schema(errors)) => assert_eq!(errors, vec![
+            SchemaError::new("action_property", Some("turn"), None, &description),
+            SchemaError::new("component", Some("position"), None, &description),
+        ]),
+        other => panic!("expected duplicate entries, found {:?}", other),
+    }
+}
+
+#[test]
+fn names_the_included_file_an_error_is_in() {
+    let error = genecs::check_spec(path("error/ecs.toml")).unwrap_err();
+    match error {
+        GenerateError::Include { path: ref middle, error: ref inner } => {
+            assert_eq!(*middle, path("error/middle.toml"));
+            match **inner {
+                GenerateError::Include { path: ref broken, error: ref inner } => {
+                    assert_eq!(*broken, path("error/broken.json"));
+                    match **inner {
+                        GenerateError::Parse(SpecFormat::Json, ref errors) => assert_eq!(errors[0].line, 5),
+                        ref other => panic!("expected a json parse error, found {:?}", other),
+                    }
+                }
+                ref other => panic!("expected an error in broken.json, found {:?}", other),
+            }
+        }
+        ref other => panic!("expected an error in middle.toml, found {:?}", other),
+    }
+
+    let message = error.to_string();
+    let expected = format!("in {}: in {}: invalid json", path("error/middle.toml").display(), path("error/broken.json").display());
+    assert!(message.starts_with(&expected), "{}", message);
+}
+
+#[test]
+fn missing_includes_are_named_once() {
+    // io errors already name the file, so aren't wrapped in another error
+    // naming it
+    match genecs::check_spec(path("error/missing.toml")) {
+        Err(GenerateError::Io { path: ref missing, .. }) => assert_eq!(*missing, path("error/absent.toml")),
+        other => panic!("expected an io error, found {:?}", other),
+    }
+}
@@ -0,0 +1,174 @@
+extern crate genecs;
+extern crate tempfile;
+
+use std::fs;
+use std::path::Path;
+
+use genecs::{GenerateError, Generator, SchemaError};
+
+const SPEC: &str = r#"
+[component.alpha]
+[component.charlie]
+
+[action_property.turn]
+type = "u64"
+"#;
+
+fn generate(lock: &Path, spec: &str) -> Result<(), GenerateError> {
+    let dir = lock.parent().unwrap();
+    fs::write(dir.join("ecs.toml"), spec).unwrap();
+    Generator::new().lock(lock).try_generate(dir.join("ecs.toml"), dir.join("ecs.rs"))
+}
+
+fn lock_errors(lock: &Path, spec: &str) -> Vec<SchemaError> {
+    match generate(lock, spec) {
+        Err(GenerateError::Lock { ref path, ref errors }) if path == lock => errors.clone(),
+        other => panic!("expected lock errors, found {:?}", other),
+    }
+}
+
+#[test]
+fn writes_the_lock_file() {
+    let dir = tempfile::tempdir().unwrap();
+    let lock = dir.path().join("ecs.lock");
+    generate(&lock, SPEC).unwrap();
+
+    let contents = fs::read_to_string(&lock).unwrap();
+    let entries: Vec<&str> = contents.lines().filter(|line| !line.starts_with('#') && !line.is_empty()).collect();
+    assert_eq!(entries, vec!["[component]", "alpha = 0", "charlie = 1", "[action_property]", "turn = 0"]);
+
+    // generating again from the same spec is fine
+    generate(&lock, SPEC).unwrap();
+    assert_eq!(fs::read_to_string(&lock).unwrap(), contents);
+}
+
+#[test]
+fn renumbered_entries() {
+    let dir = tempfile::tempdir().unwrap();
+    let lock = dir.path().join("ecs.lock");
+    generate(&lock, SPEC).unwrap();
+    let contents = fs::read_to_string(&lock).unwrap();
+
+    let added = format!("{}\n[component.bravo]\n", SPEC);
+    assert_eq!(lock_errors(&lock, &added), vec![
+        SchemaError::new("component", Some("charlie"), Some("index"),
+                         "renumbered from 1 to 2 (pin it with index = 1, or remove it from the lock file)"),
+    ]);
+    // nothing is written when the indices don't match
+    assert_eq!(fs::read_to_string(&lock).unwrap(), contents);
+
+    // pinning the index as suggested fixes it, and the new entry is locked
+    let pinned = added.replace("[component.charlie]\n", "[component.charlie]\nindex = 1\n");
+    generate(&lock, &pinned).unwrap();
+    assert!(fs::read_to_string(&lock).unwrap().contains("charlie = 1\nbravo = 2\n"));
+}
+
+#[test]
+fn indices_of_removed_entries_arent_reused() {
+    let dir = tempfile::tempdir().unwrap();
+    let lock = dir.path().join("ecs.lock");
+    generate(&lock, SPEC).unwrap();
+
+    let spec = "[component.charlie]\nindex = 1\n[component.zulu]\n[action_property.turn]\ntype = \"u64\"\n";
+    assert_eq!(lock_errors(&lock, spec), vec![
+        SchemaError::new("component", Some("zulu"), Some("index"),
+                         "index 0 belonged to the removed component alpha (remove it from the lock file to reuse its index)"),
+    ]);
+
+    // removing the entry from the lock file frees its index
+    let contents = fs::read_to_string(&lock).unwrap().replace("alpha = 0\n", "");
+    fs::write(&lock, contents).unwrap();
+    generate(&lock, spec).unwrap();
+}
+
+#[test]
+fn removed_entries_are_kept_until_removed_from_the_lock_file() {
+    let dir = tempfile::tempdir().unwrap();
+    let lock = dir.path().join("ecs.lock");
+    generate(&lock, SPEC).unwrap();
+
+    let removed = "[component.charlie]\nindex = 1\n";
+    generate(&lock, removed).unwrap();
+    let contents = fs::read_to_string(&lock).unwrap();
+    assert!(contents.contains("\n[removed.component]\nalpha = 0\n\n[removed.action_property]\nturn = 0\n"));
+
+    // generating again keeps them
+    generate(&lock, removed).unwrap();
+    assert_eq!(fs::read_to_string(&lock).unwrap(), contents);
+
+    let added = format!("{}[component.zulu]\n[action_property.undoable]\n", removed);
+    assert_eq!(lock_errors(&lock, &added), vec![
+        SchemaError::new("component", Some("zulu"), Some("index"),
+                         "index 0 belonged to the removed component alpha (remove it from the lock file to reuse its index)"),
+        SchemaError::new("action_property", Some("undoable"), Some("index"),
+                         "index 0 belonged to the removed action_property turn (remove it from the lock file to reuse its index)"),
+    ]);
+
+    // removing them from the lock file frees their indices
+    fs::write(&lock, contents.replace("alpha = 0\n", "").replace("turn = 0\n", "")).unwrap();
+    generate(&lock, &added).unwrap();
+    let contents = fs::read_to_string(&lock).unwrap();
+    assert!(contents.contains("zulu = 0\n"));
+    assert!(!contents.contains("alpha"));
+}
+
+#[test]
+fn removing_a_middle_entry() {
+    let dir = tempfile::tempdir().unwrap();
+    let lock = dir.path().join("ecs.lock");
+    let spec = "[component.alpha]\n[component.bravo]\n[component.charlie]\n";
+    generate(&lock, spec).unwrap();
+
+    let removed = spec.replace("[component.bravo]\n", "");
+    assert_eq!(lock_errors(&lock, &removed), vec![
+        SchemaError::new("component", Some("charlie"), Some("index"),
+                         "renumbered from 2 to 1 (pin it with index = 2, or remove it from the lock file)"),
+    ]);
+
+    // pinning the index as suggested leaves a gap where the removed entry was
+    let pinned = removed.replace("[component.charlie]\n", "[component.charlie]\nindex = 2\n");
+    generate(&lock, &pinned).unwrap();
+    let code = fs::read_to_string(dir.path().join("ecs.rs")).unwrap();
+    assert!(code.contains("pub const NUM_COMPONENTS: usize = 3;"));
+    assert!(code.contains("pub const CHARLIE: usize = 2;"));
+    let contents = fs::read_to_string(&lock).unwrap();
+    assert!(contents.contains("[component]\nalpha = 0\ncharlie = 2\n"));
+    assert!(contents.contains("[removed.component]\nbravo = 1\n"));
+
+    // the removed entry can return to its index
+    generate(&lock, &format!("{}[component.bravo]\n", pinned)).unwrap();
+    let contents = fs::read_to_string(&lock).unwrap();
+    assert!(contents.contains("[component]\nalpha = 0\nbravo = 1\ncharlie = 2\n"));
+    assert!(!contents.contains("[removed."));
+}
+
+#[test]
+fn invalid_lock_files() {
+    let dir = tempfile::tempdir().unwrap();
+    let lock = dir.path().join("ecs.lock");
+
+    fs::write(&lock, "component = 1\n\n[action_property]\nturn = \"first\"\n").unwrap();
+    assert_eq!(lock_errors(&lock, SPEC), vec![
+        SchemaError::new("component", None, None, "expected a table in the lock file"),
+        SchemaError::new("action_property", Some("turn"), Some("index"), "expected an index in the lock file"),
+    ]);
+
+    fs::write(&lock, "[removed]\ncomponent = 1\n").unwrap();
+    assert_eq!(lock_errors(&lock, SPEC), vec![
+        SchemaError::new("removed.component", None, None, "expected a table in the lock file"),
+    ]);
+    fs::write(&lock, "removed = 1\n").unwrap();
+    assert_eq!(lock_errors(&lock, SPEC), vec![
+        SchemaError::new("removed", None, None, "expected a table in the lock file"),
+    ]);
+
+    // errors parsing the lock file name it
+    fs::write(&lock, "[component\n").unwrap();
+    match generate(&lock, SPEC) {
+        Err(GenerateError::Include { ref path, ref error }) if *path == lock => match **error {
+            GenerateError::Parse(..) => (),
+            ref other => panic!("expected a parse error, found {:?}", other),
+        },
+        other => panic!("expected an error in the lock file, found {:?}", other),
+    }
+}
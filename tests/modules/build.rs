@@ -0,0 +1,27 @@
+extern crate genecs;
+
+use std::env;
+
+use genecs::Generator;
+
+// Generates the ecs split into modules, so building this crate checks that
+// the modules, and the extensions added to them, fit together
+fn main() {
+    Generator::new()
+        .extend("ctx", r#"
+    pub fn num_components(&self, entity: EntityId) -> usize {
+        0{{#each component}} + self.contains_{{id}}(entity) as usize{{/each}}
+    }"#)
+        .extend("ctx_insert", "        ::inserts::record(\"ctx\", {{index}});")
+        .extend("action", r#"
+    pub fn with_turn(mut self, turn: u64) -> Self {
+        self.set_turn(turn);
+        self
+    }"#)
+        .extend("action_insert", "        ::inserts::record(\"action\", {{index}});")
+        .extend("entity_ref", r#"
+    pub fn num_components(self) -> usize {
+        self.ctx.num_components(self.id)
+    }"#)
+        .generate_modules("spec.toml", env::var("OUT_DIR").unwrap(), "ecs");
+}
@@ -0,0 +1,35 @@
+#[macro_use]
+extern crate serde_derive;
+extern crate serde;
+extern crate serde_json;
+
+// imported by the spec into each of the ecs's modules
+pub mod geometry {
+    #[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+    pub struct Vec2 {
+        pub x: i32,
+        pub y: i32,
+    }
+}
+
+// records the inserts seen by the ecs's extensions
+pub mod inserts {
+    use std::cell::RefCell;
+
+    thread_local!(static INSERTS: RefCell<Vec<(&'static str, usize)>> = const { RefCell::new(Vec::new()) });
+
+    pub fn record(point: &'static str, index: usize) {
+        INSERTS.with(|inserts| inserts.borrow_mut().push((point, index)));
+    }
+
+    // the points and component indices recorded on this thread since last taken
+    pub fn take() -> Vec<(&'static str, usize)> {
+        INSERTS.with(|inserts| inserts.borrow_mut().split_off(0))
+    }
+}
+
+// the generated code is checked by building it, not by linting it
+#[allow(warnings)]
+pub mod ecs {
+    include!(concat!(env!("OUT_DIR"), "/ecs.rs"));
+}
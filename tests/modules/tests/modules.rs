@@ -0,0 +1,162 @@
+#[macro_use]
+extern crate serde_json;
+extern crate genecs_modules;
+
+use genecs_modules::ecs::*;
+use genecs_modules::ecs::storage::EntityMap;
+use genecs_modules::geometry::Vec2;
+use genecs_modules::inserts;
+
+fn v(x: i32, y: i32) -> Vec2 {
+    Vec2 { x, y }
+}
+
+// Inserts every component into `entity`, with values based on `n`
+fn populate(action: &mut EcsAction, entity: EntityId, n: u32) {
+    let i = n as i32;
+    action.insert_position(entity, v(i, i));
+    action.insert_velocity(entity, (i, -i));
+    action.insert_health(entity, n);
+    action.insert_speed(entity, n * 2);
+    action.insert_name(entity, format!("name {}", n));
+    action.insert_tags(entity, vec![format!("tag {}", n)]);
+    action.insert_path(entity, vec![v(i, 0), v(0, i)]);
+    action.insert_label(entity, format!("label {}", n));
+    action.insert_inventory(entity, vec![n]);
+    action.insert_cache(entity, n + 1);
+    action.insert_memory(entity, vec![n, n]);
+    action.insert_scratch(entity, vec![n, n, n]);
+    action.insert_solid(entity);
+    action.insert_hidden(entity);
+    action.insert_active(entity);
+    action.insert_flying(entity);
+}
+
+fn check(ctx: &EcsCtx, entity: EntityId, n: u32) {
+    let i = n as i32;
+    assert_eq!(ctx.position(entity), Some(v(i, i)));
+    assert_eq!(ctx.velocity(entity), Some((i, -i)));
+    assert_eq!(ctx.health(entity), Some(n));
+    assert_eq!(ctx.speed(entity), Some(n * 2));
+    assert_eq!(ctx.name(entity), Some(&format!("name {}", n)));
+    assert_eq!(ctx.tags(entity), Some(&vec![format!("tag {}", n)]));
+    assert_eq!(ctx.path(entity), Some(&vec![v(i, 0), v(0, i)]));
+    assert_eq!(ctx.label(entity), Some(&format!("label {}", n)));
+    assert_eq!(*ctx.inventory_borrow(entity).unwrap(), vec![n]);
+    assert_eq!(unsafe { *ctx.cache_unsafe_get(entity).unwrap() }, n + 1);
+    assert_eq!(*ctx.memory_borrow(entity).unwrap(), vec![n, n]);
+    assert_eq!(unsafe { (*ctx.scratch_unsafe_get(entity).unwrap()).clone() }, vec![n, n, n]);
+    for &component_type in &[component_type::SOLID, component_type::HIDDEN, component_type::ACTIVE, component_type::FLYING] {
+        assert!(ctx.contains_component(entity, component_type));
+    }
+}
+
+#[test]
+fn commits_every_component() {
+    let mut ctx = EcsCtx::new();
+    let a = ctx.spawn();
+
+    let mut action = EcsAction::new();
+    let b = action.reserve_entity();
+    populate(&mut action, a, 1);
+    populate(&mut action, b, 2);
+    action.set_turn(1);
+    action.set_message("populate".to_string());
+    action.set_undoable();
+    assert_eq!(action.turn(), Some(1));
+
+    // the ctx's view of the action before committing it
+    assert_eq!(ctx.post_action_entity(a, &action).position(), Some(v(1, 1)));
+    let b = ctx.commit(&mut action)[0];
+    check(&ctx, a, 1);
+    check(&ctx, b, 2);
+
+    let entity = ctx.entity(b);
+    assert_eq!(entity.health(), Some(2));
+    assert!(entity.contains_flying());
+
+    // moves and swaps of each storage
+    let mut action = EcsAction::ordered();
+    action.swap_position(a, b);
+    action.move_velocity(a, b);
+    action.swap_memory(a, b);
+    action.move_flying(b, a);
+    action.remove_entity_by_id(a, &ctx);
+    assert_eq!(ctx.validate(&action), Ok(()));
+    ctx.commit(&mut action);
+    assert_eq!(ctx.position(b), Some(v(1, 1)));
+    assert_eq!(ctx.velocity(b), Some((1, -1)));
+    assert_eq!(*ctx.memory_borrow(b).unwrap(), vec![1, 1]);
+    assert!(!ctx.contains_flying(b));
+    assert_eq!(ctx.position(a), None);
+    assert!(!ctx.contains_solid(a));
+}
+
+#[test]
+fn queries_span_storages() {
+    let mut ctx = EcsCtx::new();
+    let mut action = EcsAction::new();
+    let entities: Vec<EntityId> = (0..6).map(|_| ctx.spawn()).collect();
+    for (n, &entity) in entities.iter().enumerate() {
+        populate(&mut action, entity, n as u32);
+    }
+    ctx.commit(&mut action);
+    ctx.remove_health(entities[1]);
+    ctx.remove_flying(entities[2]);
+
+    let mut found: Vec<EntityId> = ctx.query().with_health().with_flying().with_tags().into_iter()
+        .map(|((id, _), _)| id)
+        .collect();
+    found.sort();
+    assert_eq!(found, vec![entities[0], entities[3], entities[4], entities[5]]);
+}
+
+#[test]
+fn serializes_dynamic_and_observed() {
+    let mut ctx = EcsCtx::new();
+    let entity = ctx.spawn();
+    let inserted = ::std::sync::Arc::new(::std::sync::Mutex::new(Vec::new()));
+    {
+        let inserted = inserted.clone();
+        ctx.on_insert_memory(move |id, value| inserted.lock().unwrap().push((id, value.borrow().clone())));
+    }
+    let tick = ctx.change_tick();
+
+    let mut action = EcsAction::new();
+    populate(&mut action, entity, 3);
+    ctx.commit(&mut action);
+    assert_eq!(*inserted.lock().unwrap(), vec![(entity, vec![3, 3])]);
+    assert_eq!(ctx.memory_changed_since(tick).collect::<Vec<_>>().len(), 1);
+
+    assert_eq!(ctx.get_dynamic(entity, "path").unwrap(), Some(json!([{"x": 3, "y": 0}, {"x": 0, "y": 3}])));
+    ctx.insert_dynamic(entity, "cache", json!(9)).unwrap();
+    assert!(ctx.get_dynamic(entity, "missing").is_err());
+
+    let json = serde_json::to_string(&SerializableEcsCtx::from(ctx)).unwrap();
+    let ctx = EcsCtx::from(serde_json::from_str::<SerializableEcsCtx>(&json).unwrap());
+    assert_eq!(unsafe { *ctx.cache_unsafe_get(entity).unwrap() }, 9);
+    assert_eq!(*ctx.memory_borrow(entity).unwrap(), vec![3, 3]);
+
+    // storage types are reachable through their module
+    let mut map = EntityMap::new();
+    map.insert(entity, 1);
+    assert_eq!(map.get(entity), Some(&1));
+}
+
+#[test]
+fn extensions_are_generated_into_their_modules() {
+    let mut ctx = EcsCtx::new();
+    let entity = ctx.spawn();
+    ctx.insert_health(entity, 1);
+    ctx.insert_solid(entity);
+    assert_eq!(inserts::take(), vec![("ctx", component_type::HEALTH), ("ctx", component_type::SOLID)]);
+
+    let mut action = EcsAction::new().with_turn(4);
+    action.insert_position(entity, v(0, 0));
+    assert_eq!(inserts::take(), vec![("action", component_type::POSITION)]);
+    assert_eq!(action.turn(), Some(4));
+
+    ctx.commit(&mut action);
+    assert_eq!(ctx.num_components(entity), 3);
+    assert_eq!(ctx.entity(entity).num_components(), 3);
+}
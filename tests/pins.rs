@@ -0,0 +1,117 @@
+extern crate genecs;
+
+use std::mem;
+
+use genecs::{GenerateError, SchemaError};
+
+fn ids(spec: &str) -> (Vec<String>, Vec<String>) {
+    let schema = genecs::parse_ecs_schema_from_spec(spec).unwrap();
+    for (index, component) in schema.components.iter().enumerate() {
+        assert_eq!(component.index, index);
+    }
+
+    (schema.components.into_iter().map(|component| component.id).collect(),
+     schema.action_properties.into_iter().map(|action_property| action_property.id).collect())
+}
+
+fn schema_errors(spec: &str) -> Vec<SchemaError> {
+    match genecs::parse_ecs_schema_from_spec(spec) {
+        Err(GenerateError::Schema(errors)) => errors,
+        other => panic!("expected schema errors, found {:?}", other),
+    }
+}
+
+#[test]
+fn unpinned_entries_fill_the_remaining_indices_alphabetically() {
+    let spec = r#"
+[component.alpha]
+[component.bravo]
+index = 3
+[component.charlie]
+[component.delta]
+[component.echo]
+index = 0
+
+[action_property.first]
+[action_property.last]
+index = 0
+"#;
+    let (components, action_properties) = ids(spec);
+    assert_eq!(components, vec!["echo", "alpha", "charlie", "bravo", "delta"]);
+    assert_eq!(action_properties, vec!["last", "first"]);
+}
+
+#[test]
+fn every_entry_can_be_pinned() {
+    let spec = r#"
+[component.alpha]
+index = 2
+[component.bravo]
+index = 0
+[component.charlie]
+index = 1
+"#;
+    assert_eq!(ids(spec).0, vec!["bravo", "charlie", "alpha"]);
+}
+
+#[test]
+fn pins_beyond_the_entries_leave_gaps() {
+    let spec = r#"
+[component.alpha]
+index = 3
+[component.bravo]
+[component.charlie]
+
+[action_property.turn]
+index = 1
+"#;
+    let schema = genecs::parse_ecs_schema_from_spec(spec).unwrap();
+    let indices: Vec<(&str, usize)> = schema.components.iter()
+        .map(|component| (component.id.as_str(), component.index))
+        .collect();
+    assert_eq!(indices, vec![("bravo", 0), ("charlie", 1), ("alpha", 3)]);
+    assert_eq!(schema.num_components(), 4);
+    assert_eq!(schema.action_properties[0].index, 1);
+    assert_eq!(schema.num_action_properties(), 2);
+}
+
+#[test]
+fn sets_are_sized_by_the_highest_index() {
+    let word_bits = mem::size_of::<usize>() * 8;
+    let spec = format!("[component.alpha]\nindex = {}\n[component.bravo]\n", word_bits);
+    let schema = genecs::parse_ecs_schema_from_spec(&spec).unwrap();
+    assert_eq!((schema.components[1].set_index, schema.components[1].set_bit), (1, 0));
+    assert_eq!(schema.component_set_num_words, 2);
+
+    // action property types share the size of sets
+    let spec = format!("[component.alpha]\n[action_property.turn]\nindex = {}\n", word_bits * 2);
+    let schema = genecs::parse_ecs_schema_from_spec(&spec).unwrap();
+    assert_eq!(schema.action_properties[0].set_index, 2);
+    assert_eq!(schema.component_set_num_words, 3);
+}
+
+#[test]
+fn duplicate_pins() {
+    let spec = r#"
+[component.alpha]
+index = 1
+[component.bravo]
+index = 1
+[component.charlie]
+index = 1
+"#;
+    assert_eq!(schema_errors(spec), vec![
+        SchemaError::new("component", Some("bravo"), Some("index"), "index 1 is also used by alpha"),
+        SchemaError::new("component", Some("charlie"), Some("index"), "index 1 is also used by alpha"),
+    ]);
+}
+
+#[test]
+fn pins_which_arent_indices() {
+    for index in &["-1", "1.5", "\"1\"", "true"] {
+        let spec = format!("[component.alpha]\nindex = {}\n[component.bravo]\n", index);
+        assert_eq!(schema_errors(&spec), vec![
+            SchemaError::new("component", Some("alpha"), Some("index"), "expected a non-negative integer"),
+        ], "index = {}", index);
+    }
+}
@@ -0,0 +1,126 @@
+extern crate genecs;
+
+use std::mem;
+
+use genecs::{EcsSchema, ComponentSchema, ActionPropertySchema, Container, Storage};
+
+const SPEC: &str = r#"
+dynamic = true
+imports = ["std::collections::BTreeSet", "geometry::Vec2"]
+
+[component.position]
+type = "Vec2"
+copy = true
+storage = "dense_vec"
+
+[component.name]
+type = "String"
+storage = "hash"
+
+[component.tags]
+type = "BTreeSet<String>"
+container = "RefCell"
+
+[component.cache]
+type = "Vec<u32>"
+container = "UnsafeCell"
+storage = "sparse_set"
+index = 0
+
+[component.solid]
+
+[component.hidden]
+storage = "sparse_set"
+
+[action_property.turn]
+type = "u64"
+copy = true
+
+[action_property.message]
+type = "String"
+
+[action_property.undoable]
+"#;
+
+fn component(id: &str, index: usize, ty: Option<&str>, container: Option<Container>, copy: bool, storage: Storage)
+             -> ComponentSchema {
+    ComponentSchema {
+        id: id.to_string(),
+        index,
+        set_index: 0,
+        set_bit: index,
+        ty: ty.map(str::to_string),
+        container,
+        copy,
+        storage,
+    }
+}
+
+fn action_property(id: &str, index: usize, ty: Option<&str>, copy: bool) -> ActionPropertySchema {
+    ActionPropertySchema {
+        id: id.to_string(),
+        index,
+        set_index: 0,
+        set_bit: index,
+        ty: ty.map(str::to_string),
+        copy,
+    }
+}
+
+#[test]
+fn representative_spec() {
+    let word_size = mem::size_of::<usize>();
+    let expected = EcsSchema {
+        components: vec![
+            component("cache", 0, Some("Vec<u32>"), Some(Container::UnsafeCell), false, Storage::SparseSet),
+            component("hidden", 1, None, None, false, Storage::SparseSet),
+            component("name", 2, Some("String"), None, false, Storage::Hash),
+            component("position", 3, Some("Vec2"), None, true, Storage::DenseVec),
+            component("solid", 4, None, None, false, Storage::BTree),
+            component("tags", 5, Some("BTreeSet<String>"), Some(Container::RefCell), false, Storage::BTree),
+        ],
+        action_properties: vec![
+            action_property("message", 0, Some("String"), false),
+            action_property("turn", 1, Some("u64"), true),
+            action_property("undoable", 2, None, false),
+        ],
+        imports: vec!["std::collections::BTreeSet".to_string(), "geometry::Vec2".to_string()],
+        dynamic: true,
+        word_size,
+        word_bits: word_size * 8,
+        component_set_num_words: 1,
+    };
+
+    assert_eq!(genecs::parse_ecs_schema_from_spec(SPEC).unwrap(), expected);
+}
+
+#[test]
+fn defaults() {
+    let schema = genecs::parse_ecs_schema_from_spec("[component.solid]\n").unwrap();
+    assert_eq!(schema.components, vec![component("solid", 0, None, None, false, Storage::BTree)]);
+    assert!(schema.action_properties.is_empty());
+    assert!(schema.imports.is_empty());
+    assert!(!schema.dynamic);
+}
+
+#[test]
+fn sets_span_words() {
+    let word_bits = mem::size_of::<usize>() * 8;
+    // zero padded so the alphabetical order is the numerical order
+    let spec: String = (0..word_bits * 2 + 1).map(|i| format!("[component.c{:03}]\n", i)).collect();
+    let schema = genecs::parse_ecs_schema_from_spec(&spec).unwrap();
+
+    assert_eq!(schema.component_set_num_words, 3);
+    for (index, component) in schema.components.iter().enumerate() {
+        assert_eq!(component.id, format!("c{:03}", index));
+        assert_eq!(component.index, index);
+        assert_eq!((component.set_index, component.set_bit), (index / word_bits, index % word_bits));
+    }
+
+    let last = &schema.components[word_bits * 2];
+    assert_eq!((last.set_index, last.set_bit), (2, 0));
+
+    // a full word doesn't need another
+    let spec: String = (0..word_bits).map(|i| format!("[component.c{:03}]\n", i)).collect();
+    assert_eq!(genecs::parse_ecs_schema_from_spec(&spec).unwrap().component_set_num_words, 1);
+}